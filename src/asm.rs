@@ -0,0 +1,944 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt, fs, io,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::cpu::{MEMORY_SIZE, PROGRAM_START};
+
+use self::{
+    expr::Expr,
+    lexer::{Token, TokenKind},
+};
+
+mod expr;
+mod lexer;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_SYMBOL_DEPTH: usize = 64;
+
+/// Reads a source file.
+type Loader<'a> = Box<dyn FnMut(&Path) -> io::Result<String> + 'a>;
+
+/// Operand keywords, which can't be used as symbol names.
+const KEYWORDS: [&str; 9] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+
+const MNEMONICS: [&str; 33] = [
+    "CLS", "RET", "SYS", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH", "DW",
+];
+
+/// Instruction set the assembler accepts. Each platform includes the
+/// instructions of the ones before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    #[default]
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 3] = [Platform::Chip8, Platform::SuperChip, Platform::XoChip];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => {
+                let names: Vec<_> = Platform::ALL.iter().map(|p| p.name()).collect();
+                Err(format!(
+                    "Unknown platform '{}' (expected one of: {})",
+                    s,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+/// An assembly error, located by file, line and column (both 1-based). Errors
+/// that aren't tied to a line, like a missing source file, have line 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// An assembled program image, loaded at `origin`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    pub origin: usize,
+    pub bytes: Vec<u8>,
+    pub labels: BTreeMap<String, usize>,
+}
+
+impl Program {
+    /// Renders the labels as a symbol file, one `ADDR name` pair per line in
+    /// address order.
+    pub fn symbol_file(&self) -> String {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_by_key(|(name, addr)| (**addr, *name));
+        labels
+            .into_iter()
+            .map(|(name, addr)| format!("{:04X} {}\n", addr, name))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pos {
+    file: usize,
+    line: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    Delay,
+    Sound,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(Expr),
+    Value(Expr),
+    Str(String),
+}
+
+#[derive(Clone, Debug)]
+struct Arg {
+    operand: Operand,
+    column: usize,
+}
+
+#[derive(Clone, Debug)]
+enum StmtKind {
+    Instruction { mnemonic: String, args: Vec<Arg> },
+    Bytes(Vec<Arg>),
+}
+
+/// A statement that emits bytes, placed at its final address.
+#[derive(Clone, Debug)]
+struct Stmt {
+    pos: Pos,
+    column: usize,
+    addr: usize,
+    size: usize,
+    kind: StmtKind,
+}
+
+#[derive(Clone, Debug)]
+enum Symbol {
+    Label(usize),
+    Const(Expr),
+}
+
+/// Two-pass assembler for the classic Cowgod mnemonics, e.g. `LD V0, #12`.
+///
+/// The first pass tokenizes every line, follows includes, assigns addresses
+/// and records labels, constants and register aliases. The second pass
+/// evaluates operands, now that every symbol is known, and encodes the
+/// instructions.
+pub struct Assembler<'a> {
+    platform: Platform,
+    loader: Loader<'a>,
+    files: Vec<String>,
+    symbols: HashMap<String, Symbol>,
+    aliases: HashMap<String, u8>,
+    stmts: Vec<Stmt>,
+    addr: usize,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(platform: Platform) -> Self {
+        Self {
+            platform,
+            loader: Box::new(|path| fs::read_to_string(path)),
+            files: Vec::new(),
+            symbols: HashMap::new(),
+            aliases: HashMap::new(),
+            stmts: Vec::new(),
+            addr: PROGRAM_START,
+        }
+    }
+
+    /// Replaces how source files are read, e.g. to serve includes from memory.
+    pub fn with_loader(mut self, loader: impl FnMut(&Path) -> io::Result<String> + 'a) -> Self {
+        self.loader = Box::new(loader);
+        self
+    }
+
+    /// Assembles `source`, naming it `name` in errors. Includes are resolved
+    /// relative to `name`.
+    pub fn assemble(mut self, name: &str, source: &str) -> Result<Program, AsmError> {
+        self.read_source(name.to_string(), source, 0)?;
+        self.emit()
+    }
+
+    pub fn assemble_file(mut self, path: &Path) -> Result<Program, AsmError> {
+        let name = path.display().to_string();
+        let source = (self.loader)(path).map_err(|err| AsmError {
+            file: name.clone(),
+            line: 0,
+            column: 0,
+            message: err.to_string(),
+        })?;
+        self.assemble(&name, &source)
+    }
+
+    fn error(&self, pos: Pos, column: usize, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.files[pos.file].clone(),
+            line: pos.line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    fn read_source(&mut self, name: String, source: &str, depth: usize) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(name);
+        for (idx, line) in source.lines().enumerate() {
+            let pos = Pos {
+                file,
+                line: idx + 1,
+            };
+            let end = line.chars().count() + 1;
+            let tokens =
+                lexer::tokenize(line).map_err(|(column, msg)| self.error(pos, column, msg))?;
+            self.read_line(pos, &tokens, end, depth)?;
+        }
+        Ok(())
+    }
+
+    fn read_line(
+        &mut self,
+        pos: Pos,
+        tokens: &[Token],
+        end: usize,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let mut tokens = tokens;
+        if let [Token {
+            kind: TokenKind::Ident(name),
+            column,
+        }, Token {
+            kind: TokenKind::Punct(':'),
+            ..
+        }, rest @ ..] = tokens
+        {
+            self.define(pos, *column, name, Symbol::Label(self.addr))?;
+            tokens = rest;
+        }
+
+        match tokens {
+            [] => Ok(()),
+            [Token {
+                kind: TokenKind::Punct(':'),
+                ..
+            }, Token {
+                kind: TokenKind::Ident(directive),
+                column,
+            }, rest @ ..] => self.directive(pos, directive, *column, rest, end, depth),
+            [Token {
+                kind: TokenKind::Ident(mnemonic),
+                column,
+            }, rest @ ..] => {
+                let args = self.parse_args(pos, rest, end)?;
+                self.statement(pos, *column, &mnemonic.to_ascii_uppercase(), args)
+            }
+            [token, ..] => Err(self.error(
+                pos,
+                token.column,
+                "Expected a label, directive or instruction",
+            )),
+        }
+    }
+
+    fn define(
+        &mut self,
+        pos: Pos,
+        column: usize,
+        name: &str,
+        symbol: Symbol,
+    ) -> Result<(), AsmError> {
+        if is_reserved(name) {
+            return Err(self.error(pos, column, format!("'{}' is a reserved name", name)));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(self.error(pos, column, format!("'{}' is already defined", name)));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        pos: Pos,
+        directive: &str,
+        column: usize,
+        args: &[Token],
+        end: usize,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        match (directive.to_ascii_lowercase().as_str(), args) {
+            (
+                "alias",
+                [Token {
+                    kind: TokenKind::Ident(name),
+                    column,
+                }, Token {
+                    kind: TokenKind::Ident(reg),
+                    column: reg_column,
+                }],
+            ) => {
+                let reg = self.register(reg).ok_or_else(|| {
+                    self.error(pos, *reg_column, format!("'{}' is not a register", reg))
+                })?;
+                if is_reserved(name) {
+                    return Err(self.error(pos, *column, format!("'{}' is a reserved name", name)));
+                }
+                self.aliases.insert(name.clone(), reg);
+                Ok(())
+            }
+            (
+                "const",
+                [Token {
+                    kind: TokenKind::Ident(name),
+                    column,
+                }, rest @ ..],
+            ) => {
+                let expr = Expr::parse(rest, end).map_err(|(c, msg)| self.error(pos, c, msg))?;
+                self.define(pos, *column, name, Symbol::Const(expr))
+            }
+            (
+                "include",
+                [Token {
+                    kind: TokenKind::Str(path),
+                    column,
+                }],
+            ) => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(self.error(pos, *column, "Includes are nested too deeply"));
+                }
+                let parent = Path::new(&self.files[pos.file])
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""));
+                let path = parent.join(path);
+                let source = (self.loader)(&path).map_err(|err| {
+                    self.error(
+                        pos,
+                        *column,
+                        format!("Failed to include '{}': {}", path.display(), err),
+                    )
+                })?;
+                self.read_source(path.display().to_string(), &source, depth + 1)
+            }
+            ("org", rest) => {
+                let expr = Expr::parse(rest, end).map_err(|(c, msg)| self.error(pos, c, msg))?;
+                let addr = self.eval(pos, &expr)?;
+                if addr < self.addr as i64 || addr >= MEMORY_SIZE as i64 {
+                    return Err(self.error(
+                        pos,
+                        expr.column,
+                        format!(
+                            "Origin must be between 0x{:04X} and 0x{:04X}",
+                            self.addr,
+                            MEMORY_SIZE - 1
+                        ),
+                    ));
+                }
+                self.addr = addr as usize;
+                Ok(())
+            }
+            ("alias" | "const" | "include", _) => {
+                Err(self.error(pos, column, format!("Invalid arguments for :{}", directive)))
+            }
+            _ => Err(self.error(pos, column, format!("Unknown directive ':{}'", directive))),
+        }
+    }
+
+    fn statement(
+        &mut self,
+        pos: Pos,
+        column: usize,
+        mnemonic: &str,
+        args: Vec<Arg>,
+    ) -> Result<(), AsmError> {
+        let (size, kind) = match mnemonic {
+            "DB" => {
+                let size = args
+                    .iter()
+                    .map(|arg| match &arg.operand {
+                        Operand::Str(s) => s.len(),
+                        _ => 1,
+                    })
+                    .sum();
+                (size, StmtKind::Bytes(args))
+            }
+            "DS" => {
+                let count = match args.as_slice() {
+                    [Arg {
+                        operand: Operand::Value(expr),
+                        ..
+                    }] => self.eval(pos, expr)?,
+                    _ => return Err(self.error(pos, column, "DS takes a single count")),
+                };
+                if count < 0 {
+                    return Err(self.error(pos, column, "DS count can't be negative"));
+                }
+                self.advance(pos, column, count as usize)?;
+                return Ok(());
+            }
+            _ => {
+                let long = args
+                    .iter()
+                    .any(|arg| matches!(arg.operand, Operand::Long(_)));
+                let size = if long { 4 } else { 2 };
+                let words = if mnemonic == "DW" {
+                    2 * args.len()
+                } else {
+                    size
+                };
+                let kind = StmtKind::Instruction {
+                    mnemonic: mnemonic.to_string(),
+                    args,
+                };
+                (words, kind)
+            }
+        };
+        let addr = self.addr;
+        self.advance(pos, column, size)?;
+        self.stmts.push(Stmt {
+            pos,
+            column,
+            addr,
+            size,
+            kind,
+        });
+        Ok(())
+    }
+
+    fn advance(&mut self, pos: Pos, column: usize, size: usize) -> Result<(), AsmError> {
+        if self.addr + size > MEMORY_SIZE {
+            return Err(self.error(pos, column, "Program doesn't fit in memory"));
+        }
+        self.addr += size;
+        Ok(())
+    }
+
+    fn parse_args(&self, pos: Pos, tokens: &[Token], end: usize) -> Result<Vec<Arg>, AsmError> {
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut groups = Vec::new();
+        let mut depth = 0;
+        let mut start = 0;
+        for (idx, token) in tokens.iter().enumerate() {
+            match token.kind {
+                TokenKind::Punct('(' | '[') => depth += 1,
+                TokenKind::Punct(')' | ']') => depth -= 1,
+                TokenKind::Punct(',') if depth == 0 => {
+                    groups.push((&tokens[start..idx], token.column));
+                    start = idx + 1;
+                }
+                _ => {}
+            }
+        }
+        groups.push((&tokens[start..], end));
+
+        groups
+            .into_iter()
+            .map(|(group, group_end)| {
+                let column = group.first().map_or(group_end, |t| t.column);
+                let operand = self
+                    .parse_operand(group, group_end)
+                    .map_err(|(c, msg)| self.error(pos, c, msg))?;
+                Ok(Arg { operand, column })
+            })
+            .collect()
+    }
+
+    fn parse_operand(&self, tokens: &[Token], end: usize) -> Result<Operand, (usize, String)> {
+        let operand = match tokens {
+            [Token {
+                kind: TokenKind::Ident(name),
+                ..
+            }] => match self.register(name) {
+                Some(reg) => Operand::Register(reg),
+                None => match name.to_ascii_uppercase().as_str() {
+                    "I" => Operand::I,
+                    "DT" => Operand::Delay,
+                    "ST" => Operand::Sound,
+                    "K" => Operand::Key,
+                    "F" => Operand::Font,
+                    "HF" => Operand::BigFont,
+                    "B" => Operand::Bcd,
+                    "R" => Operand::Flags,
+                    _ => Operand::Value(Expr::parse(tokens, end)?),
+                },
+            },
+            [Token {
+                kind: TokenKind::Punct('['),
+                ..
+            }, Token {
+                kind: TokenKind::Ident(name),
+                ..
+            }, Token {
+                kind: TokenKind::Punct(']'),
+                ..
+            }] if name.eq_ignore_ascii_case("I") => Operand::IndirectI,
+            [Token {
+                kind: TokenKind::Str(string),
+                ..
+            }] => Operand::Str(string.clone()),
+            [Token {
+                kind: TokenKind::Ident(name),
+                ..
+            }, rest @ ..]
+                if name.eq_ignore_ascii_case("LONG") =>
+            {
+                Operand::Long(Expr::parse(rest, end)?)
+            }
+            _ => Operand::Value(Expr::parse(tokens, end)?),
+        };
+        Ok(operand)
+    }
+
+    /// Resolves a register name or alias.
+    fn register(&self, name: &str) -> Option<u8> {
+        register(name).or_else(|| self.aliases.get(name).copied())
+    }
+
+    fn eval(&self, pos: Pos, expr: &Expr) -> Result<i64, AsmError> {
+        expr.eval(&mut |name, column| self.resolve(name, column, 0))
+            .map_err(|(column, msg)| self.error(pos, column, msg))
+    }
+
+    fn resolve(&self, name: &str, column: usize, depth: usize) -> Result<i64, (usize, String)> {
+        match self.symbols.get(name) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Const(expr)) => {
+                if depth >= MAX_SYMBOL_DEPTH {
+                    return Err((column, format!("'{}' is defined in terms of itself", name)));
+                }
+                expr.eval(&mut |name, _| self.resolve(name, column, depth + 1))
+                    .map_err(|(_, msg)| (column, msg))
+            }
+            None => Err((column, format!("Undefined symbol '{}'", name))),
+        }
+    }
+
+    fn emit(&self) -> Result<Program, AsmError> {
+        let end = self
+            .stmts
+            .iter()
+            .map(|stmt| stmt.addr + stmt.size)
+            .max()
+            .unwrap_or(PROGRAM_START);
+        let mut bytes = vec![0; end - PROGRAM_START];
+        for stmt in &self.stmts {
+            let encoded = self.encode(stmt)?;
+            let start = stmt.addr - PROGRAM_START;
+            bytes[start..(start + encoded.len())].copy_from_slice(&encoded);
+        }
+
+        let labels = self
+            .symbols
+            .iter()
+            .filter_map(|(name, symbol)| match symbol {
+                Symbol::Label(addr) => Some((name.clone(), *addr)),
+                Symbol::Const(_) => None,
+            })
+            .collect();
+        Ok(Program {
+            origin: PROGRAM_START,
+            bytes,
+            labels,
+        })
+    }
+
+    fn encode(&self, stmt: &Stmt) -> Result<Vec<u8>, AsmError> {
+        let encoder = Encoder { asm: self, stmt };
+        match &stmt.kind {
+            StmtKind::Bytes(args) => {
+                let mut bytes = Vec::new();
+                for arg in args {
+                    match &arg.operand {
+                        Operand::Str(string) => bytes.extend(string.bytes()),
+                        Operand::Value(expr) => bytes.push(encoder.byte(expr)? as u8),
+                        _ => return Err(self.error(stmt.pos, arg.column, "Expected a value")),
+                    }
+                }
+                Ok(bytes)
+            }
+            StmtKind::Instruction { mnemonic, args } => {
+                let words = encoder.instruction(mnemonic, args)?;
+                Ok(words.iter().flat_map(|word| word.to_be_bytes()).collect())
+            }
+        }
+    }
+}
+
+/// Encodes a single statement during the second pass.
+struct Encoder<'s, 'a> {
+    asm: &'s Assembler<'a>,
+    stmt: &'s Stmt,
+}
+
+impl<'s, 'a> Encoder<'s, 'a> {
+    fn error(&self, column: usize, message: impl Into<String>) -> AsmError {
+        self.asm.error(self.stmt.pos, column, message)
+    }
+
+    /// Evaluates `expr` and checks that it fits in `0..=max`.
+    fn value(&self, expr: &Expr, max: u16) -> Result<u16, AsmError> {
+        let value = self.asm.eval(self.stmt.pos, expr)?;
+        if !(0..=max as i64).contains(&value) {
+            return Err(self.error(
+                expr.column,
+                format!("Value {} is out of range (0 to {})", value, max),
+            ));
+        }
+        Ok(value as u16)
+    }
+
+    /// Evaluates a byte operand, which may also be written as a negative number.
+    fn byte(&self, expr: &Expr) -> Result<u16, AsmError> {
+        let value = self.asm.eval(self.stmt.pos, expr)?;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(
+                expr.column,
+                format!("Value {} doesn't fit in a byte", value),
+            ));
+        }
+        Ok(value as u16 & 0xFF)
+    }
+
+    fn require(&self, platform: Platform, mnemonic: &str) -> Result<(), AsmError> {
+        if self.asm.platform < platform {
+            return Err(self.error(
+                self.stmt.column,
+                format!("{} requires {}", mnemonic, platform.title()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn instruction(&self, mnemonic: &str, args: &[Arg]) -> Result<Vec<u16>, AsmError> {
+        use Operand::*;
+
+        let operands: Vec<&Operand> = args.iter().map(|arg| &arg.operand).collect();
+        let xy = |x: &u8, y: &u8| ((*x as u16) << 8) | ((*y as u16) << 4);
+        let x = |x: &u8| (*x as u16) << 8;
+
+        let word = match (mnemonic, operands.as_slice()) {
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SYS", [Value(addr)]) => self.value(addr, 0xFFF)?,
+            ("SCD", [Value(n)]) => {
+                self.require(Platform::SuperChip, mnemonic)?;
+                0x00C0 | self.value(n, 0xF)?
+            }
+            ("SCU", [Value(n)]) => {
+                self.require(Platform::XoChip, mnemonic)?;
+                0x00D0 | self.value(n, 0xF)?
+            }
+            ("SCR" | "SCL" | "EXIT" | "LOW" | "HIGH", []) => {
+                self.require(Platform::SuperChip, mnemonic)?;
+                match mnemonic {
+                    "SCR" => 0x00FB,
+                    "SCL" => 0x00FC,
+                    "EXIT" => 0x00FD,
+                    "LOW" => 0x00FE,
+                    _ => 0x00FF,
+                }
+            }
+            ("JP", [Value(addr)]) => 0x1000 | self.value(addr, 0xFFF)?,
+            ("JP", [Register(0), Value(addr)]) => 0xB000 | self.value(addr, 0xFFF)?,
+            ("JP", [Register(reg), Value(addr)]) => {
+                // With the jump quirk BXNN jumps to XNN + VX, so the register
+                // has to match the high nibble of the address
+                let addr_value = self.value(addr, 0xFFF)?;
+                if addr_value >> 8 != *reg as u16 {
+                    return Err(self.error(
+                        addr.column,
+                        format!("Address must be in 0x{:X}00..0x{:X}FF", reg, reg),
+                    ));
+                }
+                0xB000 | addr_value
+            }
+            ("CALL", [Value(addr)]) => 0x2000 | self.value(addr, 0xFFF)?,
+            ("SE", [Register(r), Value(nn)]) => 0x3000 | x(r) | self.byte(nn)?,
+            ("SNE", [Register(r), Value(nn)]) => 0x4000 | x(r) | self.byte(nn)?,
+            ("SE", [Register(r), Register(s)]) => 0x5000 | xy(r, s),
+            ("SNE", [Register(r), Register(s)]) => 0x9000 | xy(r, s),
+            ("SAVE", [Register(r), Register(s)]) => {
+                self.require(Platform::XoChip, mnemonic)?;
+                0x5002 | xy(r, s)
+            }
+            ("LOAD", [Register(r), Register(s)]) => {
+                self.require(Platform::XoChip, mnemonic)?;
+                0x5003 | xy(r, s)
+            }
+            ("LD", [Register(r), Value(nn)]) => 0x6000 | x(r) | self.byte(nn)?,
+            ("ADD", [Register(r), Value(nn)]) => 0x7000 | x(r) | self.byte(nn)?,
+            ("LD", [Register(r), Register(s)]) => 0x8000 | xy(r, s),
+            ("OR", [Register(r), Register(s)]) => 0x8001 | xy(r, s),
+            ("AND", [Register(r), Register(s)]) => 0x8002 | xy(r, s),
+            ("XOR", [Register(r), Register(s)]) => 0x8003 | xy(r, s),
+            ("ADD", [Register(r), Register(s)]) => 0x8004 | xy(r, s),
+            ("SUB", [Register(r), Register(s)]) => 0x8005 | xy(r, s),
+            ("SHR", [Register(r)]) => 0x8006 | xy(r, r),
+            ("SHR", [Register(r), Register(s)]) => 0x8006 | xy(r, s),
+            ("SUBN", [Register(r), Register(s)]) => 0x8007 | xy(r, s),
+            ("SHL", [Register(r)]) => 0x800E | xy(r, r),
+            ("SHL", [Register(r), Register(s)]) => 0x800E | xy(r, s),
+            ("LD", [I, Value(addr)]) => 0xA000 | self.value(addr, 0xFFF)?,
+            ("LD", [I, Long(addr)]) => {
+                self.require(Platform::XoChip, "LD I, LONG")?;
+                return Ok(vec![0xF000, self.value(addr, 0xFFFF)?]);
+            }
+            ("RND", [Register(r), Value(nn)]) => 0xC000 | x(r) | self.byte(nn)?,
+            ("DRW", [Register(r), Register(s), Value(n)]) => {
+                let n = self.value(n, 0xF)?;
+                if n == 0 {
+                    self.require(Platform::SuperChip, "DRW with height 0")?;
+                }
+                0xD000 | xy(r, s) | n
+            }
+            ("SKP", [Register(r)]) => 0xE09E | x(r),
+            ("SKNP", [Register(r)]) => 0xE0A1 | x(r),
+            ("PLANE", [Value(n)]) => {
+                self.require(Platform::XoChip, mnemonic)?;
+                0xF001 | (self.value(n, 0xF)? << 8)
+            }
+            ("AUDIO", []) => {
+                self.require(Platform::XoChip, mnemonic)?;
+                0xF002
+            }
+            ("LD", [Register(r), Delay]) => 0xF007 | x(r),
+            ("LD", [Register(r), Key]) => 0xF00A | x(r),
+            ("LD", [Delay, Register(r)]) => 0xF015 | x(r),
+            ("LD", [Sound, Register(r)]) => 0xF018 | x(r),
+            ("ADD", [I, Register(r)]) => 0xF01E | x(r),
+            ("LD", [Font, Register(r)]) => 0xF029 | x(r),
+            ("LD", [BigFont, Register(r)]) => {
+                self.require(Platform::SuperChip, "LD HF")?;
+                0xF030 | x(r)
+            }
+            ("LD", [Bcd, Register(r)]) => 0xF033 | x(r),
+            ("PITCH", [Register(r)]) => {
+                self.require(Platform::XoChip, mnemonic)?;
+                0xF03A | x(r)
+            }
+            ("LD", [IndirectI, Register(r)]) => 0xF055 | x(r),
+            ("LD", [Register(r), IndirectI]) => 0xF065 | x(r),
+            ("LD", [Flags, Register(r)]) => {
+                self.require(Platform::SuperChip, "LD R")?;
+                0xF075 | x(r)
+            }
+            ("LD", [Register(r), Flags]) => {
+                self.require(Platform::SuperChip, "LD R")?;
+                0xF085 | x(r)
+            }
+            ("DW", _) => {
+                return args
+                    .iter()
+                    .map(|arg| match &arg.operand {
+                        Value(expr) => self.value(expr, 0xFFFF),
+                        _ => Err(self.error(arg.column, "Expected a value")),
+                    })
+                    .collect();
+            }
+            _ if MNEMONICS.contains(&mnemonic) => {
+                return Err(self.error(
+                    self.stmt.column,
+                    format!("Invalid operands for {}", mnemonic),
+                ));
+            }
+            _ => {
+                return Err(self.error(
+                    self.stmt.column,
+                    format!("Unknown instruction '{}'", mnemonic),
+                ));
+            }
+        };
+        Ok(vec![word])
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    register(name).is_some() || KEYWORDS.contains(&name.to_ascii_uppercase().as_str())
+}
+
+/// Parses a register name like `V3` or `vf`.
+fn register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V' | 'v'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu::Op,
+        disasm::{mnemonic, Syntax},
+    };
+
+    fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+        Assembler::new(Platform::XoChip)
+            .assemble("test.asm", source)
+            .map(|program| program.bytes)
+    }
+
+    #[test]
+    fn assembles_instructions() {
+        let bytes = assemble(
+            "
+            start:  CLS
+                    LD V0, #12      ; comment
+                    LD I, sprite
+                    DRW V0, V1, 5
+                    JP start
+            sprite: DB 0b1, 2
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            bytes,
+            [0x00, 0xE0, 0x60, 0x12, 0xA2, 0x0A, 0xD0, 0x15, 0x12, 0x00, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn supports_aliases_constants_and_expressions() {
+        let bytes = assemble(
+            "
+            :alias counter V3
+            :const STEP 2 * (1 + 1)
+            :const LIMIT STEP << 2
+                ADD counter, STEP
+                SE counter, LIMIT - 1
+                DW end - 2, -1 & #FFFF
+            end:
+            ",
+        )
+        .unwrap();
+        assert_eq!(bytes, [0x73, 0x04, 0x33, 0x0F, 0x02, 0x06, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn supports_data_directives() {
+        let bytes = assemble("DB \"AB\", -1\nDS 2\n:org #208\nDW #1234").unwrap();
+        assert_eq!(bytes, [0x41, 0x42, 0xFF, 0, 0, 0, 0, 0, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn resolves_includes_relative_to_the_source() {
+        let program = Assembler::new(Platform::XoChip)
+            .with_loader(|path| match path.to_str() {
+                Some("lib/sprites.asm") => Ok("sprite: DB #FF".to_string()),
+                _ => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+            })
+            .assemble("lib/main.asm", "LD I, sprite\n:include \"sprites.asm\"")
+            .unwrap();
+        assert_eq!(program.bytes, [0xA2, 0x02, 0xFF]);
+        assert_eq!(program.labels["sprite"], 0x202);
+        assert_eq!(program.symbol_file(), "0202 sprite\n");
+    }
+
+    #[test]
+    fn reports_positions() {
+        let err = assemble("CLS\n  LD V0, nope").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 10));
+        assert_eq!(err.to_string(), "test.asm:2:10: Undefined symbol 'nope'");
+
+        let err = assemble("  ADD V0, 300").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 11));
+
+        let err = assemble("  LD V0").unwrap_err();
+        assert_eq!(err.message, "Invalid operands for LD");
+        assert_eq!(err.column, 3);
+
+        let err = assemble("a:\na:").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+    }
+
+    #[test]
+    fn gates_extensions_by_platform() {
+        let chip8 = Assembler::new(Platform::Chip8).assemble("test.asm", "HIGH");
+        assert_eq!(chip8.unwrap_err().message, "HIGH requires SUPER-CHIP");
+        let schip = Assembler::new(Platform::SuperChip).assemble("test.asm", "HIGH\nAUDIO");
+        assert_eq!(schip.unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn assembles_every_disassembled_instruction() {
+        for opcode in 0..=0xFFFF_u16 {
+            let op = Op::decode(opcode, 0x1234);
+            let source = mnemonic(&op, Syntax::Cowgod);
+            let bytes = assemble(&source).unwrap_or_else(|err| panic!("{}: {}", source, err));
+            assert_eq!(bytes[..2], opcode.to_be_bytes(), "{}", source);
+            if let Op::LongI { .. } = op {
+                assert_eq!(bytes[2..], [0x12, 0x34]);
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use super::lexer::{Token, TokenKind};
+
+/// Resolves a symbol name, found at the given column, to its value.
+pub(crate) type Lookup<'a> = dyn FnMut(&str, usize) -> Result<i64, (usize, String)> + 'a;
+
+/// An arithmetic expression over numbers and symbols.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Expr {
+    pub node: Node,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Node {
+    Number(i64),
+    Symbol(String),
+    Unary(char, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BinOp {
+    Or,
+    Xor,
+    And,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinOp {
+    fn from_token(kind: &TokenKind) -> Option<Self> {
+        let op = match kind {
+            TokenKind::Punct('|') => BinOp::Or,
+            TokenKind::Punct('^') => BinOp::Xor,
+            TokenKind::Punct('&') => BinOp::And,
+            TokenKind::ShiftLeft => BinOp::ShiftLeft,
+            TokenKind::ShiftRight => BinOp::ShiftRight,
+            TokenKind::Punct('+') => BinOp::Add,
+            TokenKind::Punct('-') => BinOp::Sub,
+            TokenKind::Punct('*') => BinOp::Mul,
+            TokenKind::Punct('/') => BinOp::Div,
+            TokenKind::Punct('%') => BinOp::Rem,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::Xor => 2,
+            BinOp::And => 3,
+            BinOp::ShiftLeft | BinOp::ShiftRight => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+        }
+    }
+}
+
+impl Expr {
+    /// Parses a complete expression from `tokens`. `end_column` is used for
+    /// errors about missing input.
+    pub fn parse(tokens: &[Token], end_column: usize) -> Result<Self, (usize, String)> {
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end_column,
+        };
+        let expr = parser.binary(1)?;
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err((token.column, "Unexpected token in expression".to_string())),
+            None => Ok(expr),
+        }
+    }
+
+    /// Evaluates the expression, resolving symbols through `lookup`.
+    pub fn eval(&self, lookup: &mut Lookup) -> Result<i64, (usize, String)> {
+        match &self.node {
+            Node::Number(value) => Ok(*value),
+            Node::Symbol(name) => lookup(name, self.column),
+            Node::Unary(op, operand) => {
+                let value = operand.eval(lookup)?;
+                Ok(match op {
+                    '-' => value.wrapping_neg(),
+                    '~' => !value,
+                    _ => value,
+                })
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(lookup)?;
+                let rhs_column = rhs.column;
+                let rhs = rhs.eval(lookup)?;
+                let value = match op {
+                    BinOp::Or => lhs | rhs,
+                    BinOp::Xor => lhs ^ rhs,
+                    BinOp::And => lhs & rhs,
+                    BinOp::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+                    BinOp::ShiftRight => lhs.wrapping_shr(rhs as u32),
+                    BinOp::Add => lhs.wrapping_add(rhs),
+                    BinOp::Sub => lhs.wrapping_sub(rhs),
+                    BinOp::Mul => lhs.wrapping_mul(rhs),
+                    BinOp::Div | BinOp::Rem if rhs == 0 => {
+                        return Err((rhs_column, "Division by zero".to_string()));
+                    }
+                    BinOp::Div => lhs.wrapping_div(rhs),
+                    BinOp::Rem => lhs.wrapping_rem(rhs),
+                };
+                Ok(value)
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    end_column: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Precedence climbing over the binary operators.
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, (usize, String)> {
+        let mut lhs = self.unary()?;
+        while let Some(token) = self.tokens.get(self.pos) {
+            let op = match BinOp::from_token(&token.kind) {
+                Some(op) if op.precedence() >= min_precedence => op,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.binary(op.precedence() + 1)?;
+            let column = lhs.column;
+            lhs = Expr {
+                node: Node::Binary(op, Box::new(lhs), Box::new(rhs)),
+                column,
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, (usize, String)> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or((self.end_column, "Expected an expression".to_string()))?;
+        self.pos += 1;
+        let column = token.column;
+        let node = match &token.kind {
+            TokenKind::Number(value) => Node::Number(*value),
+            TokenKind::Ident(name) => Node::Symbol(name.clone()),
+            TokenKind::Punct(op @ ('-' | '~' | '+')) => Node::Unary(*op, Box::new(self.unary()?)),
+            TokenKind::Punct('(') => {
+                let inner = self.binary(1)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token {
+                        kind: TokenKind::Punct(')'),
+                        ..
+                    }) => self.pos += 1,
+                    Some(token) => return Err((token.column, "Expected ')'".to_string())),
+                    None => return Err((self.end_column, "Expected ')'".to_string())),
+                }
+                inner.node
+            }
+            _ => return Err((column, "Expected an expression".to_string())),
+        };
+        Ok(Expr { node, column })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::lexer::tokenize;
+
+    fn eval(source: &str) -> Result<i64, (usize, String)> {
+        let tokens = tokenize(source).unwrap();
+        let expr = Expr::parse(&tokens, source.len() + 1)?;
+        expr.eval(&mut |name, column| match name {
+            "base" => Ok(0x300),
+            _ => Err((column, format!("Undefined symbol '{}'", name))),
+        })
+    }
+
+    #[test]
+    fn respects_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("-base + ~0"), Ok(-0x301));
+    }
+
+    #[test]
+    fn resolves_symbols() {
+        assert_eq!(eval("base + 2"), Ok(0x302));
+        assert_eq!(eval("1 + nope").unwrap_err().0, 5);
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(eval("4 / (2 - 2)").unwrap_err().0, 5);
+        assert_eq!(eval("(1 + 2").unwrap_err().0, 7);
+        assert_eq!(eval("1 2").unwrap_err().0, 3);
+    }
+}
@@ -0,0 +1,135 @@
+/// A lexical token together with the column it starts at (1-based).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Punct(char),
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// Splits one line of source into tokens, stopping at a `;` comment. Errors
+/// carry the column they were found at.
+pub(crate) fn tokenize(line: &str) -> Result<Vec<Token>, (usize, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        let column = idx + 1;
+        if c == ';' {
+            break;
+        }
+        if c.is_whitespace() {
+            idx += 1;
+            continue;
+        }
+
+        let kind = if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let len = run_len(&chars[idx..], |c| {
+                c.is_ascii_alphanumeric() || c == '_' || c == '.'
+            });
+            let ident: String = chars[idx..(idx + len)].iter().collect();
+            idx += len;
+            TokenKind::Ident(ident)
+        } else if c.is_ascii_digit() || c == '#' || c == '$' {
+            let (radix, prefix) = match (c, chars.get(idx + 1)) {
+                ('#', _) | ('$', _) => (16, 1),
+                ('0', Some('x' | 'X')) => (16, 2),
+                ('0', Some('b' | 'B')) => (2, 2),
+                _ => (10, 0),
+            };
+            let start = idx + prefix;
+            let len = run_len(&chars[start..], |c| c.is_ascii_alphanumeric() || c == '_');
+            let digits: String = chars[start..(start + len)]
+                .iter()
+                .filter(|c| **c != '_')
+                .collect();
+            idx = start + len;
+            let value = i64::from_str_radix(&digits, radix)
+                .map_err(|_| (column, format!("Invalid number '{}'", digits)))?;
+            TokenKind::Number(value)
+        } else if c == '"' {
+            let len = run_len(&chars[(idx + 1)..], |c| c != '"');
+            if idx + 1 + len >= chars.len() {
+                return Err((column, "Unterminated string".to_string()));
+            }
+            let string: String = chars[(idx + 1)..(idx + 1 + len)].iter().collect();
+            idx += len + 2;
+            TokenKind::Str(string)
+        } else if (c == '<' || c == '>') && chars.get(idx + 1) == Some(&c) {
+            idx += 2;
+            if c == '<' {
+                TokenKind::ShiftLeft
+            } else {
+                TokenKind::ShiftRight
+            }
+        } else if ",:()[]+-*/%&|^~=".contains(c) {
+            idx += 1;
+            TokenKind::Punct(c)
+        } else {
+            return Err((column, format!("Unexpected character '{}'", c)));
+        };
+        tokens.push(Token { kind, column });
+    }
+    Ok(tokens)
+}
+
+fn run_len(chars: &[char], pred: impl Fn(char) -> bool) -> usize {
+    chars.iter().take_while(|c| pred(**c)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(line: &str) -> Vec<TokenKind> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    #[test]
+    fn tokenizes_numbers() {
+        assert_eq!(
+            kinds("10 #1F $20 0x30 0b101"),
+            [10, 0x1F, 0x20, 0x30, 0b101].map(TokenKind::Number)
+        );
+    }
+
+    #[test]
+    fn tokenizes_instruction() {
+        assert_eq!(
+            kinds("loop: LD V0, [I] ; comment"),
+            vec![
+                TokenKind::Ident("loop".to_string()),
+                TokenKind::Punct(':'),
+                TokenKind::Ident("LD".to_string()),
+                TokenKind::Ident("V0".to_string()),
+                TokenKind::Punct(','),
+                TokenKind::Punct('['),
+                TokenKind::Ident("I".to_string()),
+                TokenKind::Punct(']'),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_columns() {
+        let tokens = tokenize("  DB 1 << 2").unwrap();
+        assert_eq!(tokens[0].column, 3);
+        assert_eq!(tokens[2].kind, TokenKind::ShiftLeft);
+        assert_eq!(tokens[2].column, 8);
+        assert_eq!(tokenize("DB \"abc").unwrap_err().0, 4);
+        assert_eq!(tokenize("DB 1 @").unwrap_err().0, 6);
+    }
+}
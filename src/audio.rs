@@ -0,0 +1,137 @@
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    AudioSubsystem,
+};
+
+const SAMPLE_RATE: i32 = 44_100;
+const PATTERN_BITS: usize = 128;
+
+/// Square wave as a 1-bit pattern: the first half of the pattern high, the
+/// second half low.
+const SQUARE_PATTERN: [u8; PATTERN_BITS / 8] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Audio callback that loops a 128-bit pattern, which is how XO-CHIP
+/// describes sound. The plain CHIP-8 beep is just a square wave pattern.
+struct Voice {
+    pattern: [u8; PATTERN_BITS / 8],
+    /// Pattern bits played per second.
+    rate: f32,
+    position: f32,
+    volume: f32,
+    playing: bool,
+    muted: bool,
+}
+
+impl Voice {
+    fn bit(&self, index: usize) -> bool {
+        let byte = self.pattern[index / 8];
+        byte & (0x80 >> (index % 8)) != 0
+    }
+}
+
+impl AudioCallback for Voice {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        if !self.playing || self.muted {
+            out.fill(0.0);
+            return;
+        }
+
+        let step = self.rate / SAMPLE_RATE as f32;
+        for sample in out.iter_mut() {
+            let bit = self.bit(self.position as usize % PATTERN_BITS);
+            *sample = if bit { self.volume } else { -self.volume };
+            self.position = (self.position + step) % PATTERN_BITS as f32;
+        }
+    }
+}
+
+/// Plays sound while the CPU's sound timer is running.
+pub struct Beeper {
+    device: AudioDevice<Voice>,
+}
+
+impl Beeper {
+    pub fn new(audio: &AudioSubsystem, frequency: f32, volume: f32) -> Result<Self, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let device = audio.open_playback(None, &spec, |_| Voice {
+            pattern: SQUARE_PATTERN,
+            rate: frequency * PATTERN_BITS as f32,
+            position: 0.0,
+            volume: volume.clamp(0.0, 1.0),
+            playing: false,
+            muted: false,
+        })?;
+        device.resume();
+        Ok(Self { device })
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        let mut voice = self.device.lock();
+        if voice.playing != active {
+            voice.playing = active;
+            voice.position = 0.0;
+        }
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.device.lock().muted = muted;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.device.lock().volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Plays a plain square wave at `frequency` Hz.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.set_pattern(SQUARE_PATTERN, frequency * PATTERN_BITS as f32);
+    }
+
+    /// Plays an arbitrary 128-bit pattern at `rate` bits per second.
+    pub fn set_pattern(&mut self, pattern: [u8; PATTERN_BITS / 8], rate: f32) {
+        let mut voice = self.device.lock();
+        voice.pattern = pattern;
+        voice.rate = rate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(pattern: [u8; PATTERN_BITS / 8], rate: f32) -> Voice {
+        Voice {
+            pattern,
+            rate,
+            position: 0.0,
+            volume: 0.5,
+            playing: true,
+            muted: false,
+        }
+    }
+
+    #[test]
+    fn plays_square_wave() {
+        let mut voice = voice(SQUARE_PATTERN, SAMPLE_RATE as f32);
+        let mut out = [0.0; PATTERN_BITS];
+        voice.callback(&mut out);
+        assert!(out[..64].iter().all(|s| *s == 0.5));
+        assert!(out[64..].iter().all(|s| *s == -0.5));
+    }
+
+    #[test]
+    fn silent_when_muted() {
+        let mut voice = voice(SQUARE_PATTERN, SAMPLE_RATE as f32);
+        voice.muted = true;
+        let mut out = [1.0; 16];
+        voice.callback(&mut out);
+        assert!(out.iter().all(|s| *s == 0.0));
+    }
+}
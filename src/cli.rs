@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use reimu::{
+    asm::Platform, cpu::QuirkProfile, disasm::Syntax, headless::KeyScript,
+    machine::DEFAULT_INSTRUCTIONS_PER_FRAME, trace::TraceFormat,
+};
+use tracing::Level;
+
+use crate::palette::Palette;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Disassemble a ROM
+    Disasm(DisasmArgs),
+    /// Assemble a source file into a ROM
+    Asm(AsmArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct DisasmArgs {
+    /// Path to the ROM to disassemble
+    pub rom: PathBuf,
+
+    /// Mnemonics to use (octo, cowgod)
+    #[arg(short, long, default_value_t = Syntax::default())]
+    pub syntax: Syntax,
+
+    /// Write the listing to a file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct AsmArgs {
+    /// Path to the source file
+    pub source: PathBuf,
+
+    /// Path of the ROM to write [default: the source path with a .ch8 extension]
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Instruction set to accept (chip8, schip, xochip)
+    #[arg(short, long, default_value_t = Platform::default())]
+    pub platform: Platform,
+
+    /// Also write a symbol file with the address of every label
+    #[arg(long)]
+    pub symbols: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Path to the ROM to run, or an Octo source (.8o) to compile first
+    #[arg(required = true)]
+    pub rom: Option<PathBuf>,
+
+    /// Quirk profile to run the ROM with (vip, chip48, schip, xochip)
+    #[arg(short, long, default_value_t = QuirkProfile::default())]
+    pub profile: QuirkProfile,
+
+    /// Number of instructions to execute per 60 Hz frame
+    #[arg(short, long, default_value_t = DEFAULT_INSTRUCTIONS_PER_FRAME)]
+    pub ipf: usize,
+
+    /// Size of each CHIP-8 pixel on screen
+    #[arg(short, long, default_value_t = 10)]
+    pub scale: u32,
+
+    /// Comma-separated RRGGBB colours for background, plane 1, plane 2 and overlap
+    #[arg(long)]
+    pub palette: Option<Palette>,
+
+    /// TOML file mapping keyboard keys to CHIP-8 keys [default:
+    /// reimu/keymap.toml in the user's configuration directory]
+    #[arg(short, long)]
+    pub keymap: Option<PathBuf>,
+
+    /// Frequency of the beep in Hz
+    #[arg(long, default_value_t = 440.0)]
+    pub frequency: f32,
+
+    /// Volume of the beep, between 0 and 1
+    #[arg(long, default_value_t = 0.25)]
+    pub volume: f32,
+
+    /// Start with sound muted
+    #[arg(long)]
+    pub mute: bool,
+
+    /// Memory budget for the rewind history, in MiB
+    #[arg(long, default_value_t = 16)]
+    pub rewind_budget: usize,
+
+    /// Symbol file with labels to show in the disassembly
+    #[arg(long)]
+    pub symbols: Option<PathBuf>,
+
+    /// Start with execution paused
+    #[arg(long)]
+    pub paused: bool,
+
+    /// Run without a window, then print the screen, registers and why the
+    /// program stopped, exiting with its exit code
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Stop a headless run after this many frames
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "headless",
+        conflicts_with = "cycles"
+    )]
+    pub frames: Option<u64>,
+
+    /// Stop a headless run after this many instructions
+    #[arg(long, value_name = "N", requires = "headless")]
+    pub cycles: Option<u64>,
+
+    /// Keys to press during a headless run, e.g. "10:5 20-30:A" presses 5 on
+    /// frame 10 and holds A from frame 20 until frame 30
+    #[arg(long, value_name = "SCRIPT", requires = "headless")]
+    pub keys: Option<KeyScript>,
+
+    /// Write the final screen of a headless run to a file instead of stdout
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    pub screen: Option<PathBuf>,
+
+    /// Seed for the random number generator, for reproducible runs
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Show the on-screen keypad on startup
+    #[arg(long)]
+    pub keypad: bool,
+
+    /// Show the debug windows on startup
+    #[arg(short, long)]
+    pub debug: bool,
+
+    /// Write an execution trace of every instruction to a file
+    #[arg(long)]
+    pub trace: Option<PathBuf>,
+
+    /// Format of the execution trace (text, binary)
+    #[arg(long, default_value_t = TraceFormat::default())]
+    pub trace_format: TraceFormat,
+
+    /// Only keep the last N traced instructions, written out when execution
+    /// stops on a fault, breakpoint or exit
+    #[arg(long, value_name = "N", requires = "trace")]
+    pub trace_last: Option<usize>,
+
+    /// Log events at this level and above to stderr (debug for frames, trace
+    /// for every instruction)
+    #[arg(long, value_name = "LEVEL")]
+    pub log: Option<Level>,
+}
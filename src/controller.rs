@@ -0,0 +1,320 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use sdl2::{
+    controller::{Axis, Button, GameController},
+    event::Event,
+    GameControllerSubsystem,
+};
+
+use crate::keymap::ControllerLayer;
+
+/// How far a stick or trigger has to move to press its key, as a fraction of
+/// its range.
+pub const DEFAULT_DEADZONE: f32 = 0.3;
+
+/// Buttons by their names in SDL controller mappings.
+const BUTTONS: [(&str, Button); 15] = [
+    ("a", Button::A),
+    ("b", Button::B),
+    ("x", Button::X),
+    ("y", Button::Y),
+    ("back", Button::Back),
+    ("guide", Button::Guide),
+    ("start", Button::Start),
+    ("leftstick", Button::LeftStick),
+    ("rightstick", Button::RightStick),
+    ("leftshoulder", Button::LeftShoulder),
+    ("rightshoulder", Button::RightShoulder),
+    ("dpup", Button::DPadUp),
+    ("dpdown", Button::DPadDown),
+    ("dpleft", Button::DPadLeft),
+    ("dpright", Button::DPadRight),
+];
+
+/// Stick axes, which take a `-` or `+` suffix for the direction.
+const STICKS: [(&str, Axis); 4] = [
+    ("leftx", Axis::LeftX),
+    ("lefty", Axis::LeftY),
+    ("rightx", Axis::RightX),
+    ("righty", Axis::RightY),
+];
+
+const TRIGGERS: [(&str, Axis); 2] = [
+    ("lefttrigger", Axis::TriggerLeft),
+    ("righttrigger", Axis::TriggerRight),
+];
+
+/// A controller input that can be bound to a CHIP-8 key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Button(Button),
+    /// An axis moved past the deadzone, towards its negative end when the
+    /// flag is set.
+    Axis(Axis, bool),
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        let find = |table: &[(&str, Axis)], name: &str| {
+            table
+                .iter()
+                .find(|(axis, _)| *axis == name)
+                .map(|(_, axis)| *axis)
+        };
+        if let Some((_, button)) = BUTTONS.iter().find(|(button, _)| *button == name) {
+            return Ok(Input::Button(*button));
+        }
+        if let Some(axis) = find(&TRIGGERS, &name) {
+            return Ok(Input::Axis(axis, false));
+        }
+        let stick = name
+            .strip_suffix('-')
+            .map(|stick| (stick, true))
+            .or_else(|| name.strip_suffix('+').map(|stick| (stick, false)));
+        if let Some((stick, negative)) = stick {
+            if let Some(axis) = find(&STICKS, stick) {
+                return Ok(Input::Axis(axis, negative));
+            }
+        }
+        let buttons: Vec<_> = BUTTONS.iter().map(|(name, _)| *name).collect();
+        Err(format!(
+            "Unknown controller input '{}' (expected a stick direction such as leftx-, a trigger or one of: {})",
+            s,
+            buttons.join(", ")
+        ))
+    }
+}
+
+/// The buttons and axes of a controller, as reported by its events.
+#[derive(Debug, Default)]
+struct PadState {
+    buttons: HashSet<Button>,
+    axes: HashMap<Axis, i16>,
+}
+
+impl PadState {
+    fn active(&self, input: Input, deadzone: i16) -> bool {
+        match input {
+            Input::Button(button) => self.buttons.contains(&button),
+            Input::Axis(axis, negative) => {
+                let value = self.axes.get(&axis).copied().unwrap_or(0);
+                if negative {
+                    value < -deadzone
+                } else {
+                    value > deadzone
+                }
+            }
+        }
+    }
+}
+
+/// Mapping from the inputs of one controller to the 16 CHIP-8 keys.
+#[derive(Clone, Debug)]
+pub struct ControllerMap {
+    inputs: Vec<(Input, u8)>,
+    deadzone: i16,
+}
+
+impl ControllerMap {
+    pub fn new(layer: &ControllerLayer) -> Result<Self> {
+        let mut inputs = Vec::new();
+        for (name, key) in layer.bindings() {
+            inputs.push((name.parse().map_err(|err: String| anyhow!(err))?, key));
+        }
+        let deadzone = (layer.deadzone() * i16::MAX as f32) as i16;
+        Ok(Self { inputs, deadzone })
+    }
+
+    /// The keys held on a controller in the given state.
+    fn held(&self, state: &PadState) -> u16 {
+        self.inputs
+            .iter()
+            .filter(|(input, _)| state.active(*input, self.deadzone))
+            .fold(0, |keys, (_, key)| keys | 1 << key)
+    }
+}
+
+struct Pad {
+    controller: GameController,
+    state: PadState,
+}
+
+/// The connected game controllers, one slot per player. A controller takes
+/// the first free slot when it is connected, so the other players keep
+/// their slots and mappings when one is unplugged.
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<Option<Pad>>,
+    /// The mapping of each player; players past the end use the default.
+    maps: Vec<ControllerMap>,
+    default_map: ControllerMap,
+}
+
+impl Controllers {
+    pub fn new(subsystem: GameControllerSubsystem, maps: Vec<ControllerMap>) -> Self {
+        let default_map =
+            ControllerMap::new(&ControllerLayer::default()).expect("Preset inputs are valid");
+        Self {
+            subsystem,
+            pads: Vec::new(),
+            maps,
+            default_map,
+        }
+    }
+
+    /// Handles controller events, opening controllers as they are plugged
+    /// in. Returns a message to show when a controller comes or goes.
+    pub fn handle_event(&mut self, event: &Event) -> Option<String> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let controller = match self.subsystem.open(which) {
+                    Ok(controller) => controller,
+                    Err(err) => return Some(format!("Failed to open controller: {}", err)),
+                };
+                // SDL also reports controllers that were connected at startup
+                if self.pad_index(controller.instance_id()).is_some() {
+                    return None;
+                }
+                let slot = free_slot(&self.pads);
+                let message = format!("{} connected as player {}", controller.name(), slot + 1);
+                let pad = Some(Pad {
+                    controller,
+                    state: PadState::default(),
+                });
+                if slot < self.pads.len() {
+                    self.pads[slot] = pad;
+                } else {
+                    self.pads.push(pad);
+                }
+                Some(message)
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let idx = self.pad_index(which)?;
+                let pad = self.pads[idx].take()?;
+                Some(format!(
+                    "{} (player {}) disconnected",
+                    pad.controller.name(),
+                    idx + 1
+                ))
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                let idx = self.pad_index(which)?;
+                if let Some(pad) = &mut self.pads[idx] {
+                    pad.state.buttons.insert(button);
+                }
+                None
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                let idx = self.pad_index(which)?;
+                if let Some(pad) = &mut self.pads[idx] {
+                    pad.state.buttons.remove(&button);
+                }
+                None
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                let idx = self.pad_index(which)?;
+                if let Some(pad) = &mut self.pads[idx] {
+                    pad.state.axes.insert(axis, value);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn pad_index(&self, instance_id: u32) -> Option<usize> {
+        self.pads.iter().position(|pad| {
+            pad.as_ref()
+                .is_some_and(|pad| pad.controller.instance_id() == instance_id)
+        })
+    }
+
+    /// The keys held on every controller.
+    pub fn held(&self) -> u16 {
+        self.pads
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, pad)| {
+                let map = self.maps.get(idx).unwrap_or(&self.default_map);
+                pad.as_ref().map(|pad| map.held(&pad.state))
+            })
+            .fold(0, |keys, held| keys | held)
+    }
+}
+
+/// The slot a newly connected controller takes: the first one left by an
+/// unplugged controller, or a new one past the end.
+fn free_slot<T>(slots: &[Option<T>]) -> usize {
+    slots
+        .iter()
+        .position(Option::is_none)
+        .unwrap_or(slots.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inputs() {
+        assert_eq!("a".parse(), Ok(Input::Button(Button::A)));
+        assert_eq!("DPUp".parse(), Ok(Input::Button(Button::DPadUp)));
+        assert_eq!("leftx-".parse(), Ok(Input::Axis(Axis::LeftX, true)));
+        assert_eq!("righty+".parse(), Ok(Input::Axis(Axis::RightY, false)));
+        assert_eq!(
+            "lefttrigger".parse(),
+            Ok(Input::Axis(Axis::TriggerLeft, false))
+        );
+        assert!("leftx".parse::<Input>().is_err());
+        assert!("lefttrigger-".parse::<Input>().is_err());
+        assert!("z".parse::<Input>().is_err());
+    }
+
+    #[test]
+    fn reuses_the_first_free_slot() {
+        assert_eq!(free_slot::<u8>(&[]), 0);
+        assert_eq!(free_slot(&[Some(1), Some(2)]), 2);
+        assert_eq!(free_slot(&[Some(1), None, None]), 1);
+    }
+
+    #[test]
+    fn maps_buttons_and_sticks_to_keys() {
+        let map = ControllerMap::new(&ControllerLayer::default()).unwrap();
+        let mut state = PadState::default();
+        assert_eq!(map.held(&state), 0);
+
+        state.buttons.insert(Button::A);
+        state.buttons.insert(Button::DPadUp);
+        assert_eq!(map.held(&state), 1 << 0x5 | 1 << 0x2);
+
+        state.buttons.clear();
+        state.axes.insert(Axis::LeftX, i16::MAX);
+        state.axes.insert(Axis::LeftY, i16::MIN);
+        assert_eq!(map.held(&state), 1 << 0x6 | 1 << 0x2);
+    }
+
+    #[test]
+    fn ignores_sticks_inside_the_deadzone() {
+        let layer = ControllerLayer {
+            deadzone: Some(0.5),
+            ..Default::default()
+        };
+        let map = ControllerMap::new(&layer).unwrap();
+        let mut state = PadState::default();
+        state.axes.insert(Axis::LeftX, i16::MAX / 3);
+        state.axes.insert(Axis::LeftY, -i16::MAX / 3);
+        assert_eq!(map.held(&state), 0);
+
+        state.axes.insert(Axis::LeftY, -i16::MAX / 4 * 3);
+        assert_eq!(map.held(&state), 1 << 0x2);
+    }
+}
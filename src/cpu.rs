@@ -1,16 +1,36 @@
+use std::ops::Range;
+
 use rand::prelude::*;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use tracing::Level;
+
+use crate::{gpu::Gpu, trace::TraceEntry};
 
-use crate::{cpu::instruction::Instruction, gpu::Gpu};
+pub use self::bus::{Access, AccessKind, Bus};
+pub use self::error::CpuError;
+pub use self::op::Op;
+pub use self::quirks::{LoadStore, QuirkProfile, Quirks};
 
+mod bus;
+mod error;
 mod instruction;
+mod op;
+#[cfg(test)]
+mod opcode_tests;
+mod quirks;
+#[cfg(test)]
+mod test_support;
 
-const MEMORY_SIZE: usize = 4096;
+pub const MEMORY_SIZE: usize = 0x10000;
 const REGISTER_COUNT: usize = 16;
-const RESERVED_START: usize = 0x200;
-const RESERVED_END: usize = 352;
-const PROGRAM_START: usize = 0x200;
+pub const PROGRAM_START: usize = 0x200;
 const STEP_SIZE: usize = 2;
 const STACK_SIZE: usize = 16;
+const FLAG_REGISTER_COUNT: usize = 16;
+const AUDIO_PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
 
 const FONT_START_ADDR: usize = 0x050;
 const FONT_SPRITE_SIZE: usize = 5;
@@ -35,9 +55,36 @@ const FONT: [u8; FONT_BLOCK_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
-pub struct Cpu<'a> {
-    gpu: &'a mut Gpu,
-    pub memory: [u8; MEMORY_SIZE],
+const BIG_FONT_START_ADDR: usize = FONT_START_ADDR + FONT_BLOCK_SIZE;
+const BIG_FONT_SPRITE_SIZE: usize = 10;
+const BIG_FONT_BLOCK_SIZE: usize = BIG_FONT_SPRITE_SIZE * FONT_SPRITE_COUNT;
+const BIG_FONT: [u8; BIG_FONT_BLOCK_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// The CHIP-8 processor together with the display, keypad and timers it drives.
+///
+/// Serializing a `Cpu` captures the full machine state except for the quirks,
+/// which are configuration rather than state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cpu {
+    gpu: Gpu,
+    pub memory: Bus,
     registers: [u8; REGISTER_COUNT],
     pub address_register: usize,
     pub pc: usize,
@@ -45,16 +92,38 @@ pub struct Cpu<'a> {
     pub sp: usize,
     pub delay_timer: u8,
     pub sound_timer: u8,
+    pub flags: [u8; FLAG_REGISTER_COUNT],
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
     keys: u16,
     running: bool,
+    /// Code given by the exit instruction that stopped the program.
+    exit_code: Option<u8>,
+    waiting_for_vblank: bool,
+    rng: Pcg32,
+    #[serde(skip)]
     pub redraw: bool,
+    #[serde(skip)]
+    pub quirks: Quirks,
+    #[serde(skip)]
+    record_trace: bool,
+    /// Whether `0FNN` exits with code `NN` instead of being an unsupported
+    /// machine code call.
+    #[serde(skip)]
+    debug_exit: bool,
+    #[serde(skip)]
+    trace: Vec<TraceEntry>,
 }
 
-impl<'a> Cpu<'a> {
-    pub fn new(gpu: &'a mut Gpu) -> Self {
+impl Cpu {
+    pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut cpu = Self {
-            gpu,
-            memory: [0; MEMORY_SIZE],
+            gpu: Gpu::new(),
+            memory: Bus::new(MEMORY_SIZE),
             registers: [0; REGISTER_COUNT],
             address_register: 0,
             pc: PROGRAM_START,
@@ -62,16 +131,27 @@ impl<'a> Cpu<'a> {
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
+            flags: [0; FLAG_REGISTER_COUNT],
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
             keys: 0,
             running: true,
+            exit_code: None,
+            waiting_for_vblank: false,
+            rng: Pcg32::from_entropy(),
             redraw: false,
+            quirks,
+            record_trace: false,
+            debug_exit: false,
+            trace: Vec::new(),
         };
         cpu.reset();
         cpu
     }
 
     pub fn reset(&mut self) {
-        self.gpu.clear();
+        self.gpu.set_hires(false);
+        self.gpu.select_planes(1);
         self.registers.fill(0);
         self.address_register = 0;
         self.pc = PROGRAM_START;
@@ -79,70 +159,259 @@ impl<'a> Cpu<'a> {
         self.sp = 0;
         self.delay_timer = 0;
         self.sound_timer = 0;
+        self.audio_pattern.fill(0);
+        self.pitch = DEFAULT_PITCH;
         self.keys = 0;
         self.running = true;
+        self.exit_code = None;
+        self.waiting_for_vblank = false;
         self.redraw = false;
+        self.memory.fill(0);
         self.memory[FONT_START_ADDR..(FONT_START_ADDR + FONT.len())].copy_from_slice(&FONT);
+        self.memory[BIG_FONT_START_ADDR..(BIG_FONT_START_ADDR + BIG_FONT.len())]
+            .copy_from_slice(&BIG_FONT);
+    }
+
+    /// Reseeds the random number generator used by `CXNN`, making runs
+    /// reproducible.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Pcg32::seed_from_u64(seed);
     }
 
     pub fn register(&self, idx: usize) -> u8 {
         self.registers[idx]
     }
 
-    pub fn load(&mut self, program_bytes: &[u8]) {
-        let available = MEMORY_SIZE - RESERVED_START - RESERVED_END;
+    pub fn set_register(&mut self, idx: usize, value: u8) {
+        self.registers[idx] = value;
+    }
+
+    pub fn load(&mut self, program_bytes: &[u8]) -> Result<(), CpuError> {
+        let available = self.quirks.memory_size - PROGRAM_START;
         if program_bytes.len() > available {
-            panic!(
-                "Program too large! ({} bytes, available: {} bytes)",
-                program_bytes.len(),
-                available
-            );
+            return Err(CpuError::RomTooLarge {
+                size: program_bytes.len(),
+                available,
+            });
         }
-        let start_addr = RESERVED_START;
-        let end_addr = MEMORY_SIZE - RESERVED_END - 1;
-        println!("Loading program ({} bytes)", program_bytes.len());
-        println!(
-            "Start addr: {:#04X}, end addr: {:#04X}",
-            start_addr, end_addr
+        let start_addr = PROGRAM_START;
+        let end_addr = self.quirks.memory_size - 1;
+        tracing::debug!(
+            size = program_bytes.len(),
+            start = %format_args!("{:#04X}", start_addr),
+            end = %format_args!("{:#04X}", end_addr),
+            "loading program"
         );
         self.memory[start_addr..(program_bytes.len() + start_addr)].copy_from_slice(program_bytes);
+        Ok(())
     }
 
     fn read16(&self, addr: usize) -> u16 {
-        let hi = self.memory[addr];
-        let lo = self.memory[addr + 1];
+        let hi = self.memory[addr % MEMORY_SIZE];
+        let lo = self.memory[(addr + 1) % MEMORY_SIZE];
         ((hi as u16) << 8) | (lo as u16)
     }
 
-    pub fn screen(&self) -> &[bool] {
+    /// The instruction word at `addr`, read without going through the bus.
+    pub fn opcode_at(&self, addr: usize) -> u16 {
+        self.read16(addr)
+    }
+
+    /// Decodes the instruction at `addr` without executing it.
+    pub fn op_at(&self, addr: usize) -> Op {
+        Op::decode(self.read16(addr), self.read16(addr + STEP_SIZE))
+    }
+
+    /// Range of `len` bytes starting at `start`, or the first address that
+    /// falls outside of memory.
+    fn memory_range(start: usize, len: usize) -> Result<Range<usize>, usize> {
+        let end = start + len;
+        if end > MEMORY_SIZE {
+            Err(start.max(MEMORY_SIZE))
+        } else {
+            Ok(start..end)
+        }
+    }
+
+    /// Checks the invariants a deserialized CPU has to hold before it can
+    /// run, returning what is wrong with it.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.memory.len() != MEMORY_SIZE {
+            return Err(format!(
+                "memory is {} bytes, expected {}",
+                self.memory.len(),
+                MEMORY_SIZE
+            ));
+        }
+        if self.sp > STACK_SIZE {
+            return Err(format!(
+                "stack pointer {} is past the end of the stack",
+                self.sp
+            ));
+        }
+        self.gpu.validate()
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    pub fn screen(&self) -> &[u8] {
         self.gpu.screen()
     }
 
-    fn stack_push(&mut self, value: usize) {
-        self.stack[self.sp] = value;
+    pub fn screen_size(&self) -> (usize, usize) {
+        (self.gpu.width(), self.gpu.height())
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// The code passed to `0FNN` (`DBG:EXIT`), or 0 for `00FD`, once the
+    /// program has exited.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    /// Whether `DXYN` is waiting for the next frame under the display wait
+    /// quirk, in which case stepping does nothing until the timers tick.
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.gpu.planes()
+    }
+
+    /// Skips the next instruction, stepping over both words of `F000 NNNN`.
+    fn skip(&mut self) {
+        let next = self.read16(self.pc);
+        self.pc += if next == 0xF000 {
+            STEP_SIZE * 2
+        } else {
+            STEP_SIZE
+        };
+    }
+
+    fn stack_push(&mut self, value: usize) -> Option<()> {
+        *self.stack.get_mut(self.sp)? = value;
         self.sp += 1;
+        Some(())
     }
 
-    fn stack_pop(&mut self) -> usize {
-        self.sp -= 1;
-        self.stack[self.sp]
+    fn stack_pop(&mut self) -> Option<usize> {
+        self.sp = self.sp.checked_sub(1)?;
+        Some(self.stack[self.sp])
     }
 
-    pub fn step(&mut self) {
-        if !self.running {
-            return;
+    /// Executes a single instruction.
+    ///
+    /// On error the PC is left pointing at the faulting instruction.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        if !self.running || self.waiting_for_vblank {
+            return Ok(());
+        }
+
+        let addr = self.pc;
+        if addr + STEP_SIZE > MEMORY_SIZE {
+            return Err(CpuError::PcOutOfBounds { pc: addr });
         }
 
-        let instr = Instruction::new(self.read16(self.pc));
+        let opcode = self.read16(addr);
+        let len = if opcode == 0xF000 {
+            STEP_SIZE * 2
+        } else {
+            STEP_SIZE
+        };
+        // `F000 NNNN` has to fit in memory as a whole
+        if addr + len > MEMORY_SIZE {
+            return Err(CpuError::PcOutOfBounds { pc: addr });
+        }
+        self.memory.execute(addr, len);
+        let long = self.read16(addr + STEP_SIZE);
+        let before = (self.record_trace || tracing::enabled!(Level::TRACE))
+            .then_some((self.registers, self.address_register));
         self.pc += STEP_SIZE;
-        self.decode(&instr);
+        let result = self.decode(opcode, long, addr);
+        // A faulting instruction is traced too, as it's usually the most
+        // interesting one
+        if let Some((registers, address_register)) = before {
+            self.trace_step(addr, opcode, long, registers, address_register);
+        }
+        if let Err(err) = result {
+            self.pc = addr;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    pub fn debug_exit(&self) -> bool {
+        self.debug_exit
+    }
+
+    /// Enables `DBG:EXIT`: `0FNN` stops the program with exit code `NN`.
+    /// Off by default, as `0FNN` is also a valid machine code call that ROMs
+    /// for the COSMAC VIP may contain.
+    pub fn set_debug_exit(&mut self, enabled: bool) {
+        self.debug_exit = enabled;
+    }
 
-        if self.pc > MEMORY_SIZE {
-            panic!("PC outside of memory");
+    pub fn record_trace(&self) -> bool {
+        self.record_trace
+    }
+
+    /// Enables or disables recording a [`TraceEntry`] for every executed
+    /// instruction. Disabling also drops any entries that haven't been taken.
+    pub fn set_record_trace(&mut self, record: bool) {
+        self.record_trace = record;
+        if !record {
+            self.trace.clear();
+        }
+    }
+
+    /// Takes the entries recorded since the last call.
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        std::mem::take(&mut self.trace)
+    }
+
+    /// Records the instruction that was just executed at `addr`, given the
+    /// words it was fetched as and the registers from before it ran, and
+    /// emits it as a `tracing` event.
+    fn trace_step(
+        &mut self,
+        addr: usize,
+        opcode: u16,
+        long: u16,
+        registers: [u8; REGISTER_COUNT],
+        address_register: usize,
+    ) {
+        let entry = TraceEntry {
+            pc: addr as u16,
+            opcode,
+            long: (opcode == 0xF000).then_some(long),
+            registers: (0..REGISTER_COUNT)
+                .filter(|idx| registers[*idx] != self.registers[*idx])
+                .map(|idx| (idx as u8, self.registers[idx]))
+                .collect(),
+            address_register: (address_register != self.address_register)
+                .then_some(self.address_register as u16),
+        };
+        tracing::trace!(
+            pc = %format_args!("{:04X}", addr),
+            opcode = %format_args!("{:04X}", entry.opcode),
+            mnemonic = %entry.mnemonic(),
+            changed = %entry.changes(),
+            "step"
+        );
+        if self.record_trace {
+            self.trace.push(entry);
         }
     }
 
     pub fn tick_timers(&mut self) {
+        self.waiting_for_vblank = false;
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -151,286 +420,441 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    fn decode(&mut self, instruction: &Instruction) {
-        // println!("Decoding instruction: {:04X}", instruction.value());
-        let opcode = instruction.opcode();
-        let variant_1 = instruction.n();
-        let variant_2 = instruction.nn();
-        let variant_3 = instruction.nnn();
-        match (opcode, variant_1, variant_2, variant_3) {
-            (0, _, _, 0x0E0) => {
+    /// Executes the instruction word `opcode` fetched from `addr`. `long` is
+    /// the word after it, the operand of `F000`.
+    fn decode(&mut self, opcode: u16, long: u16, addr: usize) -> Result<(), CpuError> {
+        let out_of_bounds = |target| CpuError::MemoryOutOfBounds {
+            addr,
+            opcode,
+            target,
+        };
+        match Op::decode(opcode, long) {
+            Op::Clear => {
                 // CLR
                 self.gpu.clear();
                 self.redraw = true;
             }
 
-            (0, _, _, 0x0EE) => {
+            Op::Return => {
                 // RET
-                let return_addr = self.stack_pop();
+                let return_addr = self
+                    .stack_pop()
+                    .ok_or(CpuError::StackUnderflow { addr, opcode })?;
                 self.pc = return_addr;
             }
 
-            (0, _, _, _) => {
-                // SYS
-                let x = instruction.x();
-                let nn = instruction.nn();
-                if x == 0xF {
-                    println!("DBG:EXIT({})", nn);
-                    // exit(nn as i32);
-                    self.running = false;
-                } else {
-                    panic!("Machine code subroutines are not supported")
-                }
+            Op::ScrollDown { n } => {
+                // SCD
+                self.gpu.scroll_vertical(n as isize);
+                self.redraw = true;
+            }
+
+            Op::ScrollUp { n } => {
+                // SCU
+                self.gpu.scroll_vertical(-(n as isize));
+                self.redraw = true;
             }
 
-            (1, _, _, _) => {
+            Op::ScrollRight => {
+                // SCR
+                self.gpu.scroll_horizontal(4);
+                self.redraw = true;
+            }
+
+            Op::ScrollLeft => {
+                // SCL
+                self.gpu.scroll_horizontal(-4);
+                self.redraw = true;
+            }
+
+            Op::Exit => {
+                // EXIT
+                self.running = false;
+                self.exit_code = Some(0);
+            }
+
+            Op::Lores => {
+                // LOW
+                self.gpu.set_hires(false);
+                self.redraw = true;
+            }
+
+            Op::Hires => {
+                // HIGH
+                self.gpu.set_hires(true);
+                self.redraw = true;
+            }
+
+            Op::Unknown { opcode } if self.debug_exit && opcode & 0xFF00 == 0x0F00 => {
+                // DBG:EXIT
+                let code = (opcode & 0xFF) as u8;
+                tracing::info!(code, "DBG:EXIT");
+                self.running = false;
+                self.exit_code = Some(code);
+            }
+
+            Op::Jump { addr: target } => {
                 // JMP
-                let addr = instruction.nnn() as usize;
-                self.pc = addr;
+                self.pc = target as usize;
             }
 
-            (2, _, _, _) => {
+            Op::Call { addr: target } => {
                 // CALL
                 let return_addr = self.pc;
-                self.stack_push(return_addr);
-                let sub_addr = instruction.nnn() as usize;
-                self.pc = sub_addr;
+                self.stack_push(return_addr)
+                    .ok_or(CpuError::StackOverflow { addr, opcode })?;
+                self.pc = target as usize;
             }
 
-            (3, _, _, _) => {
+            Op::SkipEqImm { x, nn } => {
                 // SEQ
-                let x_val = self.registers[instruction.x() as usize];
-                let nn = instruction.nn();
+                let x_val = self.registers[x as usize];
                 if x_val == nn {
-                    self.pc += STEP_SIZE;
+                    self.skip();
                 }
             }
 
-            (4, _, _, _) => {
+            Op::SkipNeImm { x, nn } => {
                 // SNE
-                let x_val = self.registers[instruction.x() as usize];
-                let nn = instruction.nn();
+                let x_val = self.registers[x as usize];
                 if x_val != nn {
-                    self.pc += STEP_SIZE;
+                    self.skip();
                 }
             }
 
-            (5, 0, _, _) => {
+            Op::SkipEq { x, y } => {
                 // SEQ
-                let x_val = self.registers[instruction.x() as usize];
-                let y_val = self.registers[instruction.y() as usize];
+                let x_val = self.registers[x as usize];
+                let y_val = self.registers[y as usize];
                 if x_val == y_val {
-                    self.pc += STEP_SIZE;
+                    self.skip();
+                }
+            }
+
+            Op::SaveRange { x, y } => {
+                // SAVE range
+                let x = x as usize;
+                let y = y as usize;
+                let count = x.abs_diff(y) + 1;
+                let range =
+                    Self::memory_range(self.address_register, count).map_err(out_of_bounds)?;
+                for (offset, mem_idx) in range.enumerate() {
+                    let reg_idx = if x <= y { x + offset } else { x - offset };
+                    self.memory.write(mem_idx, self.registers[reg_idx]);
                 }
             }
 
-            (6, _, _, _) => {
+            Op::LoadRange { x, y } => {
+                // LOAD range
+                let x = x as usize;
+                let y = y as usize;
+                let count = x.abs_diff(y) + 1;
+                let range =
+                    Self::memory_range(self.address_register, count).map_err(out_of_bounds)?;
+                for (offset, mem_idx) in range.enumerate() {
+                    let reg_idx = if x <= y { x + offset } else { x - offset };
+                    self.registers[reg_idx] = self.memory.read(mem_idx);
+                }
+            }
+
+            Op::SetImm { x, nn } => {
                 // SET
-                self.registers[instruction.x() as usize] = instruction.nn();
+                self.registers[x as usize] = nn;
             }
 
-            (7, _, _, _) => {
+            Op::AddImm { x, nn } => {
                 // ADD
-                let x = instruction.x() as usize;
-                self.registers[x] = self.registers[x].wrapping_add(instruction.nn());
+                let x = x as usize;
+                self.registers[x] = self.registers[x].wrapping_add(nn);
             }
 
-            (8, 0, _, _) => {
+            Op::Set { x, y } => {
                 // SETR
-                let y_val = self.registers[instruction.y() as usize];
-                self.registers[instruction.x() as usize] = y_val;
+                let y_val = self.registers[y as usize];
+                self.registers[x as usize] = y_val;
             }
 
-            (8, 1, _, _) => {
+            Op::Or { x, y } => {
                 // OR
-                let x_val = self.registers[instruction.x() as usize];
-                let y_val = self.registers[instruction.y() as usize];
-                self.registers[instruction.x() as usize] = x_val | y_val;
+                let x_val = self.registers[x as usize];
+                let y_val = self.registers[y as usize];
+                self.registers[x as usize] = x_val | y_val;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
 
-            (8, 2, _, _) => {
+            Op::And { x, y } => {
                 // AND
-                let x_val = self.registers[instruction.x() as usize];
-                let y_val = self.registers[instruction.y() as usize];
-                self.registers[instruction.x() as usize] = x_val & y_val;
+                let x_val = self.registers[x as usize];
+                let y_val = self.registers[y as usize];
+                self.registers[x as usize] = x_val & y_val;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
 
-            (8, 3, _, _) => {
+            Op::Xor { x, y } => {
                 // XOR
-                let x_val = self.registers[instruction.x() as usize];
-                let y_val = self.registers[instruction.y() as usize];
-                self.registers[instruction.x() as usize] = x_val ^ y_val;
+                let x_val = self.registers[x as usize];
+                let y_val = self.registers[y as usize];
+                self.registers[x as usize] = x_val ^ y_val;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
 
-            (8, 4, _, _) => {
+            Op::Add { x, y } => {
                 // ADC
-                let x = instruction.x() as usize;
+                let x = x as usize;
                 let x_val = self.registers[x];
-                let y_val = self.registers[instruction.y() as usize];
+                let y_val = self.registers[y as usize];
                 let new = x_val.wrapping_add(y_val);
                 self.registers[x] = new;
                 self.registers[0xF] = if new < x_val { 1 } else { 0 };
             }
 
-            (8, 5, _, _) => {
+            Op::Sub { x, y } => {
                 // SUB
-                let x = instruction.x() as usize;
+                let x = x as usize;
                 let x_val = self.registers[x];
-                let y_val = self.registers[instruction.y() as usize];
+                let y_val = self.registers[y as usize];
                 let new = x_val.wrapping_sub(y_val);
                 self.registers[x] = new;
-                self.registers[0xF] = if new > x_val { 1 } else { 0 };
+                self.registers[0xF] = if x_val >= y_val { 1 } else { 0 };
             }
 
-            (8, 6, _, _) => {
+            Op::ShiftRight { x, y } => {
                 // SHR
-                let y_val = self.registers[instruction.y() as usize];
-                self.registers[0xF] = y_val & 0x1;
-                let shifted = y_val >> 1;
-                self.registers[instruction.x() as usize] = shifted;
+                let src = self.shift_source(x, y);
+                let shifted = src >> 1;
+                self.registers[x as usize] = shifted;
+                self.registers[0xF] = src & 0x1;
             }
 
-            (8, 7, _, _) => {
+            Op::SubN { x, y } => {
                 // SUBN
-                let x = instruction.x() as usize;
+                let x = x as usize;
                 let x_val = self.registers[x];
-                let y_val = self.registers[instruction.y() as usize];
+                let y_val = self.registers[y as usize];
                 let new = y_val.wrapping_sub(x_val);
                 self.registers[x] = new;
-                self.registers[0xF] = if new > y_val { 1 } else { 0 };
+                self.registers[0xF] = if y_val >= x_val { 1 } else { 0 };
             }
 
-            (8, 0xE, _, _) => {
+            Op::ShiftLeft { x, y } => {
                 // SHL
-                let y_val = self.registers[instruction.y() as usize];
-                self.registers[0xF] = (y_val >> 7) & 0x1;
-                let shifted = y_val << 1;
-                self.registers[instruction.x() as usize] = shifted;
+                let src = self.shift_source(x, y);
+                let shifted = src << 1;
+                self.registers[x as usize] = shifted;
+                self.registers[0xF] = (src >> 7) & 0x1;
             }
 
-            (9, 0, _, _) => {
+            Op::SkipNe { x, y } => {
                 // SNE
-                let x_val = self.registers[instruction.x() as usize];
-                let y_val = self.registers[instruction.y() as usize];
+                let x_val = self.registers[x as usize];
+                let y_val = self.registers[y as usize];
                 if x_val != y_val {
-                    self.pc += STEP_SIZE;
+                    self.skip();
                 }
             }
 
-            (0xA, _, _, _) => {
+            Op::SetI { addr: target } => {
                 // STO
-                self.address_register = instruction.nnn() as usize;
+                self.address_register = target as usize;
             }
 
-            (0xB, _, _, _) => {
+            Op::JumpOffset { x, addr: target } => {
                 // JMPR
-                self.pc = instruction.nnn() as usize + self.registers[0] as usize;
+                let reg = if self.quirks.jump { x } else { 0 };
+                self.pc = target as usize + self.registers[reg as usize] as usize;
             }
 
-            (0xC, _, _, _) => {
+            Op::Random { x, nn } => {
                 // RND
-                let num: u8 = rand::thread_rng().gen();
-                let mask = instruction.nn();
+                let num: u8 = self.rng.gen();
+                let mask = nn;
                 let masked = num & mask;
-                self.registers[instruction.x() as usize] = masked;
+                self.registers[x as usize] = masked;
             }
 
-            (0xD, _, _, _) => {
+            Op::Draw { x, y, n: 0 } => {
+                // DRW (16x16)
+                let x = self.registers[x as usize];
+                let y = self.registers[y as usize];
+                let size = self.gpu.sprite_len(32);
+                let range =
+                    Self::memory_range(self.address_register, size).map_err(out_of_bounds)?;
+                let sprite = self.memory.read_range(range);
+                let hit = self.gpu.draw_large_sprite(
+                    x as usize,
+                    y as usize,
+                    sprite,
+                    self.quirks.clipping,
+                );
+                self.registers[0xF] = if hit { 1 } else { 0 };
+                self.redraw = true;
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
+                }
+            }
+
+            Op::Draw { x, y, n } => {
                 // DRW
-                let x = self.registers[instruction.x() as usize];
-                let y = self.registers[instruction.y() as usize];
-                let size = instruction.n() as usize;
-                let sprite = &self.memory[self.address_register..(self.address_register + size)];
-                let hit = self.gpu.draw_sprite(x as usize, y as usize, sprite);
+                let x = self.registers[x as usize];
+                let y = self.registers[y as usize];
+                let size = self.gpu.sprite_len(n as usize);
+                let range =
+                    Self::memory_range(self.address_register, size).map_err(out_of_bounds)?;
+                let sprite = self.memory.read_range(range);
+                let hit =
+                    self.gpu
+                        .draw_sprite(x as usize, y as usize, sprite, self.quirks.clipping);
                 self.registers[0xF] = if hit { 1 } else { 0 };
                 self.redraw = true;
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
+                }
             }
 
-            (0xE, _, 0x9E, _) => {
+            Op::SkipKey { x } => {
                 // SKP
-                let key = self.registers[instruction.x() as usize];
+                let key = self.registers[x as usize];
                 let pressed = self.is_key_pressed(key);
                 if pressed {
-                    self.pc += STEP_SIZE;
+                    self.skip();
                 }
             }
 
-            (0xE, _, 0xA1, _) => {
+            Op::SkipNotKey { x } => {
                 // SKN
-                let key = self.registers[instruction.x() as usize];
+                let key = self.registers[x as usize];
                 let pressed = self.is_key_pressed(key);
                 if !pressed {
-                    self.pc += STEP_SIZE;
+                    self.skip();
                 }
             }
 
-            (0xF, _, 0x07, _) => {
+            Op::LongI { addr: target } => {
+                // LDI long
+                self.address_register = target as usize;
+                self.pc += STEP_SIZE;
+            }
+
+            Op::Plane { n } => {
+                // PLANE
+                self.gpu.select_planes(n);
+            }
+
+            Op::Audio => {
+                // AUDIO
+                let range = Self::memory_range(self.address_register, AUDIO_PATTERN_SIZE)
+                    .map_err(out_of_bounds)?;
+                self.audio_pattern
+                    .copy_from_slice(self.memory.read_range(range));
+            }
+
+            Op::GetDelay { x } => {
                 // LDT
-                self.registers[instruction.x() as usize] = self.delay_timer;
+                self.registers[x as usize] = self.delay_timer;
             }
 
-            (0xF, _, 0x0A, _) => {
+            Op::WaitKey { x } => {
                 // WFK
                 if let Some(key) = self.get_active_key() {
-                    self.registers[instruction.x() as usize] = key;
+                    self.registers[x as usize] = key;
                 } else {
                     self.pc -= STEP_SIZE;
                 }
             }
 
-            (0xF, _, 0x15, _) => {
+            Op::SetDelay { x } => {
                 // SDT
-                self.delay_timer = self.registers[instruction.x() as usize];
+                self.delay_timer = self.registers[x as usize];
             }
 
-            (0xF, _, 0x18, _) => {
+            Op::SetSound { x } => {
                 // SST
-                self.sound_timer = self.registers[instruction.x() as usize];
+                self.sound_timer = self.registers[x as usize];
             }
 
-            (0xF, _, 0x1E, _) => {
-                self.address_register += self.registers[instruction.x() as usize] as usize;
+            Op::AddI { x } => {
+                let offset = self.registers[x as usize] as usize;
+                self.address_register = (self.address_register + offset) % MEMORY_SIZE;
             }
 
-            (0xF, _, 0x29, _) => {
-                let digit = self.registers[instruction.x() as usize];
+            Op::Font { x } => {
+                let digit = self.registers[x as usize] & 0xF;
                 let offset = digit as usize * FONT_SPRITE_SIZE;
                 let addr = FONT_START_ADDR + offset;
                 self.address_register = addr;
             }
 
-            (0xF, _, 0x33, _) => {
+            Op::BigFont { x } => {
+                // HEX (big font)
+                let digit = self.registers[x as usize] & 0xF;
+                let offset = digit as usize * BIG_FONT_SPRITE_SIZE;
+                self.address_register = BIG_FONT_START_ADDR + offset;
+            }
+
+            Op::Pitch { x } => {
+                // PITCH
+                self.pitch = self.registers[x as usize];
+            }
+
+            Op::Bcd { x } => {
                 // BCD
-                let x_val = self.registers[instruction.x() as usize];
-                let store_idx = self.address_register;
+                let x_val = self.registers[x as usize];
+                let store_idx = Self::memory_range(self.address_register, 3)
+                    .map_err(out_of_bounds)?
+                    .start;
                 let hundreds = x_val / 100;
                 let tens = (x_val / 10) % 10;
                 let ones = x_val % 10;
-                self.memory[store_idx] = hundreds;
-                self.memory[store_idx + 1] = tens;
-                self.memory[store_idx + 2] = ones;
+                self.memory.write_range(store_idx, &[hundreds, tens, ones]);
             }
 
-            (0xF, _, 0x55, _) => {
-                let x_size = instruction.x() as usize;
-                for reg_idx in 0..=x_size {
-                    self.memory[self.address_register as usize + reg_idx] = self.registers[reg_idx];
-                }
-                self.address_register += x_size + 1;
+            Op::Store { x } => {
+                let x_size = x as usize;
+                let range =
+                    Self::memory_range(self.address_register, x_size + 1).map_err(out_of_bounds)?;
+                self.memory
+                    .write_range(range.start, &self.registers[..=x_size]);
+                self.address_register += self.quirks.load_store.increment(x_size);
             }
 
-            (0xF, _, 0x65, _) => {
-                let x_size = instruction.x() as usize;
-                for reg_idx in 0..=x_size {
-                    self.registers[reg_idx] = self.memory[self.address_register as usize + reg_idx];
-                }
-                self.address_register += x_size + 1;
+            Op::Load { x } => {
+                let x_size = x as usize;
+                let range =
+                    Self::memory_range(self.address_register, x_size + 1).map_err(out_of_bounds)?;
+                self.registers[..=x_size].copy_from_slice(self.memory.read_range(range));
+                self.address_register += self.quirks.load_store.increment(x_size);
+            }
+
+            Op::SaveFlags { x } => {
+                // SAVEFLAGS
+                let x_size = (x as usize).min(FLAG_REGISTER_COUNT - 1);
+                self.flags[..=x_size].copy_from_slice(&self.registers[..=x_size]);
+            }
+
+            Op::LoadFlags { x } => {
+                // LOADFLAGS
+                let x_size = (x as usize).min(FLAG_REGISTER_COUNT - 1);
+                self.registers[..=x_size].copy_from_slice(&self.flags[..=x_size]);
             }
 
-            _ => {
-                panic!("Unknown instruction {:04X}", instruction.value());
+            Op::Unknown { .. } => {
+                // Includes machine code subroutines, which are not supported
+                return Err(CpuError::UnknownOpcode { addr, opcode });
             }
         }
+
+        Ok(())
+    }
+
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        let reg = if self.quirks.shift { x } else { y };
+        self.registers[reg as usize]
     }
 
     fn is_key_pressed(&self, key: u8) -> bool {
@@ -440,7 +864,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn get_active_key(&self) -> Option<u8> {
-        for i in 0..0xF {
+        for i in 0..16 {
             let mask = 1 << i;
             if self.keys & mask == mask {
                 return Some(i);
@@ -450,6 +874,15 @@ impl<'a> Cpu<'a> {
         None
     }
 
+    /// The held keys, bit N being set while key N is held.
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    pub fn set_keys(&mut self, keys: u16) {
+        self.keys = keys;
+    }
+
     pub fn set_key(&mut self, key: u8, pressed: bool) {
         let mask = 1 << key;
         if pressed {
@@ -459,6 +892,10 @@ impl<'a> Cpu<'a> {
         }
     }
 
+    pub fn dump_screen(&self) {
+        self.gpu.dump();
+    }
+
     pub fn dump(&self) {
         self.dump_memory();
         self.dump_registers();
@@ -513,17 +950,167 @@ impl<'a> Cpu<'a> {
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn reads_16_correctly() {
-        let mut gpu = Gpu::new();
-        let mut cpu = Cpu::new(&mut gpu);
+        let mut cpu = Cpu::new();
         let program = [0x01_u8, 0x02, 0x03, 0x04];
-        cpu.load(&program);
+        cpu.load(&program).unwrap();
         let val16 = cpu.read16(0x200);
         assert_eq!(val16, 0x0102)
     }
+
+    #[test]
+    fn skips_over_long_load() {
+        // SE V0, 0; I := long 0x1234; V1 := 1
+        let program = [0x30_u8, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01];
+        let mut cpu = Cpu::new();
+        cpu.load(&program).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+        cpu.pc = 0x202;
+        cpu.step().unwrap();
+        assert_eq!(cpu.address_register, 0x1234);
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn shift_quirk_selects_source_register() {
+        let program = [0x60_u8, 0x04, 0x61, 0x03, 0x80, 0x16];
+
+        let mut cpu = Cpu::new();
+        cpu.load(&program).unwrap();
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.register(0), 0x01);
+        assert_eq!(cpu.register(0xF), 1);
+
+        let quirks = QuirkProfile::SuperChip.quirks();
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.load(&program).unwrap();
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.register(0), 0x02);
+        assert_eq!(cpu.register(0xF), 0);
+    }
+
+    #[test]
+    fn subtraction_sets_flag_without_borrow() {
+        // V0 := 5; V1 := 5; V0 -= V1; V2 := 5; V2 =- V3
+        let program = [
+            0x60_u8, 0x05, 0x61, 0x05, 0x80, 0x15, 0x62, 0x05, 0x82, 0x37,
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load(&program).unwrap();
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.register(0), 0);
+        assert_eq!(cpu.register(0xF), 1);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.register(2), 0xFB);
+        assert_eq!(cpu.register(0xF), 0);
+    }
+
+    #[test]
+    fn loads_audio_pattern() {
+        // I := 0x204; audio; pattern
+        let mut program = vec![0xA2, 0x04, 0xF0, 0x02];
+        program.extend(0..16);
+        let mut cpu = Cpu::new();
+        cpu.load(&program).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.audio_pattern[15], 15);
+    }
+
+    #[test]
+    fn rejects_too_large_program() {
+        let mut cpu = Cpu::new();
+        let program = vec![0_u8; MEMORY_SIZE];
+        let result = cpu.load(&program);
+        assert!(matches!(result, Err(CpuError::RomTooLarge { .. })));
+    }
+
+    #[test]
+    fn rejects_long_load_past_the_end_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.memory[(MEMORY_SIZE - 3)..(MEMORY_SIZE - 1)].copy_from_slice(&[0xF0, 0x00]);
+        cpu.pc = MEMORY_SIZE - 3;
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::PcOutOfBounds {
+                pc: MEMORY_SIZE - 3
+            })
+        );
+        cpu.pc = MEMORY_SIZE - 1;
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::PcOutOfBounds {
+                pc: MEMORY_SIZE - 1
+            })
+        );
+    }
+
+    #[test]
+    fn limits_program_size_to_the_profile_memory() {
+        let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+        let program = vec![0_u8; 0x1000 - PROGRAM_START];
+        cpu.load(&program).unwrap();
+        let program = vec![0_u8; 0x1000 - PROGRAM_START + 1];
+        assert_eq!(
+            cpu.load(&program),
+            Err(CpuError::RomTooLarge {
+                size: 0x1000 - PROGRAM_START + 1,
+                available: 0x1000 - PROGRAM_START,
+            })
+        );
+
+        let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+        cpu.load(&vec![0_u8; MEMORY_SIZE - PROGRAM_START]).unwrap();
+    }
+
+    #[test]
+    fn reports_unknown_opcode() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0x81, 0x2F]).unwrap();
+        let result = cpu.step();
+        assert_eq!(
+            result,
+            Err(CpuError::UnknownOpcode {
+                addr: 0x200,
+                opcode: 0x812F
+            })
+        );
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn reports_stack_underflow() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0x00, 0xEE]).unwrap();
+        let result = cpu.step();
+        assert!(matches!(result, Err(CpuError::StackUnderflow { .. })));
+    }
+
+    #[test]
+    fn reports_memory_out_of_bounds() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0xF0, 0x33]).unwrap();
+        cpu.address_register = MEMORY_SIZE - 1;
+        let result = cpu.step();
+        assert!(matches!(result, Err(CpuError::MemoryOutOfBounds { .. })));
+    }
 }
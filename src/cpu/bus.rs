@@ -0,0 +1,183 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut, Range},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The kind of memory access an instruction made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// Fetching the instruction itself.
+    Execute,
+}
+
+impl AccessKind {
+    pub const ALL: [AccessKind; 3] = [AccessKind::Read, AccessKind::Write, AccessKind::Execute];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+            AccessKind::Execute => "execute",
+        }
+    }
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A contiguous memory access made by a single instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub range: Range<usize>,
+}
+
+/// The CPU's view of memory.
+///
+/// Instructions go through [`Bus::read`], [`Bus::write`] and friends, which
+/// record every access while tracking is enabled so a debugger can watch
+/// memory traffic. Indexing through `Deref` bypasses the log and is meant for
+/// frontends and debug views.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bus {
+    memory: Vec<u8>,
+    #[serde(skip)]
+    tracking: bool,
+    #[serde(skip)]
+    accesses: Vec<Access>,
+}
+
+impl Bus {
+    pub fn new(size: usize) -> Self {
+        Self {
+            memory: vec![0; size],
+            tracking: false,
+            accesses: Vec::new(),
+        }
+    }
+
+    pub fn tracking(&self) -> bool {
+        self.tracking
+    }
+
+    /// Enables or disables recording accesses. Disabling also drops any
+    /// accesses that haven't been taken yet.
+    pub fn set_tracking(&mut self, tracking: bool) {
+        self.tracking = tracking;
+        if !tracking {
+            self.accesses.clear();
+        }
+    }
+
+    /// Accesses recorded since the last call to [`Bus::clear_accesses`].
+    pub fn accesses(&self) -> &[Access] {
+        &self.accesses
+    }
+
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear();
+    }
+
+    /// Records an access, clamped to the end of memory.
+    fn record(&mut self, kind: AccessKind, range: Range<usize>) {
+        if self.tracking {
+            let len = self.memory.len();
+            let range = range.start.min(len)..range.end.min(len);
+            self.accesses.push(Access { kind, range });
+        }
+    }
+
+    /// Records fetching an instruction of `len` bytes at `addr`.
+    pub fn execute(&mut self, addr: usize, len: usize) {
+        self.record(AccessKind::Execute, addr..(addr + len));
+    }
+
+    pub fn read(&mut self, addr: usize) -> u8 {
+        self.record(AccessKind::Read, addr..(addr + 1));
+        self.memory[addr]
+    }
+
+    pub fn write(&mut self, addr: usize, value: u8) {
+        self.record(AccessKind::Write, addr..(addr + 1));
+        self.memory[addr] = value;
+    }
+
+    pub fn read_range(&mut self, range: Range<usize>) -> &[u8] {
+        self.record(AccessKind::Read, range.clone());
+        &self.memory[range]
+    }
+
+    pub fn write_range(&mut self, start: usize, data: &[u8]) {
+        let range = start..(start + data.len());
+        self.record(AccessKind::Write, range.clone());
+        self.memory[range].copy_from_slice(data);
+    }
+}
+
+impl Deref for Bus {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.memory
+    }
+}
+
+impl DerefMut for Bus {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.memory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamps_accesses_to_memory() {
+        let mut bus = Bus::new(16);
+        bus.set_tracking(true);
+        bus.execute(14, 4);
+        assert_eq!(
+            bus.accesses(),
+            &[Access {
+                kind: AccessKind::Execute,
+                range: 14..16,
+            }]
+        );
+    }
+
+    #[test]
+    fn records_accesses_while_tracking() {
+        let mut bus = Bus::new(16);
+        bus.write(0, 1);
+        assert!(bus.accesses().is_empty());
+
+        bus.set_tracking(true);
+        bus.write_range(2, &[1, 2, 3]);
+        assert_eq!(bus.read_range(2..5), &[1, 2, 3]);
+        bus[8] = 9;
+        assert_eq!(
+            bus.accesses(),
+            &[
+                Access {
+                    kind: AccessKind::Write,
+                    range: 2..5,
+                },
+                Access {
+                    kind: AccessKind::Read,
+                    range: 2..5,
+                },
+            ]
+        );
+        bus.clear_accesses();
+        assert!(bus.accesses().is_empty());
+    }
+}
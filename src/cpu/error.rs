@@ -0,0 +1,91 @@
+use std::{error::Error, fmt};
+
+/// Faults raised while loading or executing a program.
+///
+/// Execution faults carry the address of the faulting instruction and its
+/// opcode so frontends can point at the offending code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    UnknownOpcode {
+        addr: usize,
+        opcode: u16,
+    },
+    StackOverflow {
+        addr: usize,
+        opcode: u16,
+    },
+    StackUnderflow {
+        addr: usize,
+        opcode: u16,
+    },
+    PcOutOfBounds {
+        pc: usize,
+    },
+    MemoryOutOfBounds {
+        addr: usize,
+        opcode: u16,
+        target: usize,
+    },
+    RomTooLarge {
+        size: usize,
+        available: usize,
+    },
+}
+
+impl CpuError {
+    /// Address of the instruction that caused the fault, if any.
+    pub fn addr(&self) -> Option<usize> {
+        match self {
+            CpuError::UnknownOpcode { addr, .. }
+            | CpuError::StackOverflow { addr, .. }
+            | CpuError::StackUnderflow { addr, .. }
+            | CpuError::MemoryOutOfBounds { addr, .. } => Some(*addr),
+            CpuError::PcOutOfBounds { pc } => Some(*pc),
+            CpuError::RomTooLarge { .. } => None,
+        }
+    }
+
+    /// Opcode of the instruction that caused the fault, if any.
+    pub fn opcode(&self) -> Option<u16> {
+        match self {
+            CpuError::UnknownOpcode { opcode, .. }
+            | CpuError::StackOverflow { opcode, .. }
+            | CpuError::StackUnderflow { opcode, .. }
+            | CpuError::MemoryOutOfBounds { opcode, .. } => Some(*opcode),
+            CpuError::PcOutOfBounds { .. } | CpuError::RomTooLarge { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { addr, opcode } => {
+                write!(f, "Unknown instruction {:04X} at {:04X}", opcode, addr)
+            }
+            CpuError::StackOverflow { addr, opcode } => {
+                write!(f, "Stack overflow by {:04X} at {:04X}", opcode, addr)
+            }
+            CpuError::StackUnderflow { addr, opcode } => {
+                write!(f, "Stack underflow by {:04X} at {:04X}", opcode, addr)
+            }
+            CpuError::PcOutOfBounds { pc } => write!(f, "PC outside of memory ({:04X})", pc),
+            CpuError::MemoryOutOfBounds {
+                addr,
+                opcode,
+                target,
+            } => write!(
+                f,
+                "Memory access outside of memory ({:X}) by {:04X} at {:04X}",
+                target, opcode, addr
+            ),
+            CpuError::RomTooLarge { size, available } => write!(
+                f,
+                "Program too large! ({} bytes, available: {} bytes)",
+                size, available
+            ),
+        }
+    }
+}
+
+impl Error for CpuError {}
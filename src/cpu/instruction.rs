@@ -7,10 +7,6 @@ impl Instruction {
         Self { value: content }
     }
 
-    pub fn value(&self) -> u16 {
-        self.value
-    }
-
     pub fn opcode(&self) -> u8 {
         ((self.value >> 12) & 0xF) as u8
     }
@@ -40,13 +36,6 @@ impl Instruction {
 mod tests {
     use super::*;
 
-    #[test]
-    fn gets_value() {
-        let instr = Instruction::new(0x1234);
-        let value = instr.value();
-        assert_eq!(value, 0x1234);
-    }
-
     #[test]
     fn gets_x() {
         let instr = Instruction::new(0x1234);
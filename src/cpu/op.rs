@@ -0,0 +1,248 @@
+use super::instruction::Instruction;
+
+/// A decoded instruction.
+///
+/// Register operands are register indices, not values. `LongI` carries the
+/// 16-bit address stored in the word after `F000`, which makes it the only
+/// four byte instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `00E0`
+    Clear,
+    /// `00EE`
+    Return,
+    /// `00CN` (SUPER-CHIP)
+    ScrollDown { n: u8 },
+    /// `00DN` (XO-CHIP)
+    ScrollUp { n: u8 },
+    /// `00FB` (SUPER-CHIP)
+    ScrollRight,
+    /// `00FC` (SUPER-CHIP)
+    ScrollLeft,
+    /// `00FD` (SUPER-CHIP)
+    Exit,
+    /// `00FE` (SUPER-CHIP)
+    Lores,
+    /// `00FF` (SUPER-CHIP)
+    Hires,
+    /// `1NNN`
+    Jump { addr: u16 },
+    /// `2NNN`
+    Call { addr: u16 },
+    /// `3XNN`
+    SkipEqImm { x: u8, nn: u8 },
+    /// `4XNN`
+    SkipNeImm { x: u8, nn: u8 },
+    /// `5XY0`
+    SkipEq { x: u8, y: u8 },
+    /// `5XY2` (XO-CHIP)
+    SaveRange { x: u8, y: u8 },
+    /// `5XY3` (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    /// `6XNN`
+    SetImm { x: u8, nn: u8 },
+    /// `7XNN`
+    AddImm { x: u8, nn: u8 },
+    /// `8XY0`
+    Set { x: u8, y: u8 },
+    /// `8XY1`
+    Or { x: u8, y: u8 },
+    /// `8XY2`
+    And { x: u8, y: u8 },
+    /// `8XY3`
+    Xor { x: u8, y: u8 },
+    /// `8XY4`
+    Add { x: u8, y: u8 },
+    /// `8XY5`
+    Sub { x: u8, y: u8 },
+    /// `8XY6`
+    ShiftRight { x: u8, y: u8 },
+    /// `8XY7`
+    SubN { x: u8, y: u8 },
+    /// `8XYE`
+    ShiftLeft { x: u8, y: u8 },
+    /// `9XY0`
+    SkipNe { x: u8, y: u8 },
+    /// `ANNN`
+    SetI { addr: u16 },
+    /// `BNNN`, or `BXNN` with the jump quirk
+    JumpOffset { x: u8, addr: u16 },
+    /// `CXNN`
+    Random { x: u8, nn: u8 },
+    /// `DXYN`, where `N = 0` draws a 16x16 sprite
+    Draw { x: u8, y: u8, n: u8 },
+    /// `EX9E`
+    SkipKey { x: u8 },
+    /// `EXA1`
+    SkipNotKey { x: u8 },
+    /// `F000 NNNN` (XO-CHIP)
+    LongI { addr: u16 },
+    /// `FN01` (XO-CHIP)
+    Plane { n: u8 },
+    /// `F002` (XO-CHIP)
+    Audio,
+    /// `FX07`
+    GetDelay { x: u8 },
+    /// `FX0A`
+    WaitKey { x: u8 },
+    /// `FX15`
+    SetDelay { x: u8 },
+    /// `FX18`
+    SetSound { x: u8 },
+    /// `FX1E`
+    AddI { x: u8 },
+    /// `FX29`
+    Font { x: u8 },
+    /// `FX30` (SUPER-CHIP)
+    BigFont { x: u8 },
+    /// `FX33`
+    Bcd { x: u8 },
+    /// `FX3A` (XO-CHIP)
+    Pitch { x: u8 },
+    /// `FX55`
+    Store { x: u8 },
+    /// `FX65`
+    Load { x: u8 },
+    /// `FX75` (SUPER-CHIP)
+    SaveFlags { x: u8 },
+    /// `FX85` (SUPER-CHIP)
+    LoadFlags { x: u8 },
+    /// Anything the CPU doesn't execute, including `0NNN` machine code calls.
+    Unknown { opcode: u16 },
+}
+
+impl Op {
+    /// Decodes the instruction word `opcode`. `next` is the word following it
+    /// in memory and is only used as the operand of `F000`.
+    pub fn decode(opcode: u16, next: u16) -> Self {
+        let instr = Instruction::new(opcode);
+        let x = instr.x();
+        let y = instr.y();
+        let n = instr.n();
+        let nn = instr.nn();
+        let addr = instr.nnn();
+        match (instr.opcode(), n, nn, addr) {
+            (0, _, _, 0x0E0) => Op::Clear,
+            (0, _, _, 0x0EE) => Op::Return,
+            (0, _, _, 0x0C0..=0x0CF) => Op::ScrollDown { n },
+            (0, _, _, 0x0D0..=0x0DF) => Op::ScrollUp { n },
+            (0, _, _, 0x0FB) => Op::ScrollRight,
+            (0, _, _, 0x0FC) => Op::ScrollLeft,
+            (0, _, _, 0x0FD) => Op::Exit,
+            (0, _, _, 0x0FE) => Op::Lores,
+            (0, _, _, 0x0FF) => Op::Hires,
+            (1, _, _, _) => Op::Jump { addr },
+            (2, _, _, _) => Op::Call { addr },
+            (3, _, _, _) => Op::SkipEqImm { x, nn },
+            (4, _, _, _) => Op::SkipNeImm { x, nn },
+            (5, 0, _, _) => Op::SkipEq { x, y },
+            (5, 2, _, _) => Op::SaveRange { x, y },
+            (5, 3, _, _) => Op::LoadRange { x, y },
+            (6, _, _, _) => Op::SetImm { x, nn },
+            (7, _, _, _) => Op::AddImm { x, nn },
+            (8, 0, _, _) => Op::Set { x, y },
+            (8, 1, _, _) => Op::Or { x, y },
+            (8, 2, _, _) => Op::And { x, y },
+            (8, 3, _, _) => Op::Xor { x, y },
+            (8, 4, _, _) => Op::Add { x, y },
+            (8, 5, _, _) => Op::Sub { x, y },
+            (8, 6, _, _) => Op::ShiftRight { x, y },
+            (8, 7, _, _) => Op::SubN { x, y },
+            (8, 0xE, _, _) => Op::ShiftLeft { x, y },
+            (9, 0, _, _) => Op::SkipNe { x, y },
+            (0xA, _, _, _) => Op::SetI { addr },
+            (0xB, _, _, _) => Op::JumpOffset { x, addr },
+            (0xC, _, _, _) => Op::Random { x, nn },
+            (0xD, _, _, _) => Op::Draw { x, y, n },
+            (0xE, _, 0x9E, _) => Op::SkipKey { x },
+            (0xE, _, 0xA1, _) => Op::SkipNotKey { x },
+            (0xF, _, _, 0x000) => Op::LongI { addr: next },
+            (0xF, _, 0x01, _) => Op::Plane { n: x },
+            (0xF, _, _, 0x002) => Op::Audio,
+            (0xF, _, 0x07, _) => Op::GetDelay { x },
+            (0xF, _, 0x0A, _) => Op::WaitKey { x },
+            (0xF, _, 0x15, _) => Op::SetDelay { x },
+            (0xF, _, 0x18, _) => Op::SetSound { x },
+            (0xF, _, 0x1E, _) => Op::AddI { x },
+            (0xF, _, 0x29, _) => Op::Font { x },
+            (0xF, _, 0x30, _) => Op::BigFont { x },
+            (0xF, _, 0x33, _) => Op::Bcd { x },
+            (0xF, _, 0x3A, _) => Op::Pitch { x },
+            (0xF, _, 0x55, _) => Op::Store { x },
+            (0xF, _, 0x65, _) => Op::Load { x },
+            (0xF, _, 0x75, _) => Op::SaveFlags { x },
+            (0xF, _, 0x85, _) => Op::LoadFlags { x },
+            _ => Op::Unknown { opcode },
+        }
+    }
+
+    /// Size of the instruction in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Op::LongI { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    /// Where a jump or call transfers control to. For `BNNN` this is the
+    /// base address, before the register offset is added.
+    pub fn branch_target(&self) -> Option<usize> {
+        match *self {
+            Op::Jump { addr } | Op::Call { addr } | Op::JumpOffset { addr, .. } => {
+                Some(addr as usize)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction conditionally skips the one after it.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Op::SkipEqImm { .. }
+                | Op::SkipNeImm { .. }
+                | Op::SkipEq { .. }
+                | Op::SkipNe { .. }
+                | Op::SkipKey { .. }
+                | Op::SkipNotKey { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_branch_targets() {
+        assert_eq!(Op::decode(0x1234, 0).branch_target(), Some(0x234));
+        assert_eq!(Op::decode(0x2456, 0).branch_target(), Some(0x456));
+        assert_eq!(Op::decode(0xB300, 0).branch_target(), Some(0x300));
+        assert_eq!(Op::decode(0xA300, 0).branch_target(), None);
+    }
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(Op::decode(0x8AB4, 0), Op::Add { x: 0xA, y: 0xB });
+        assert_eq!(Op::decode(0xD125, 0), Op::Draw { x: 1, y: 2, n: 5 });
+        assert_eq!(Op::decode(0x2345, 0), Op::Call { addr: 0x345 });
+        assert_eq!(Op::decode(0x00C3, 0), Op::ScrollDown { n: 3 });
+        assert_eq!(Op::decode(0xF002, 0), Op::Audio);
+    }
+
+    #[test]
+    fn decodes_long_load() {
+        let op = Op::decode(0xF000, 0x1234);
+        assert_eq!(op, Op::LongI { addr: 0x1234 });
+        assert_eq!(op.size(), 4);
+    }
+
+    #[test]
+    fn rejects_unknown_opcodes() {
+        for opcode in [
+            0x0123, 0x0F2A, 0x5121, 0x8128, 0x9121, 0xE1FF, 0xF1FF, 0xF100, 0xF102,
+        ] {
+            assert_eq!(Op::decode(opcode, 0), Op::Unknown { opcode });
+        }
+    }
+}
@@ -0,0 +1,766 @@
+//! One or more tests for every arm of `Cpu::decode`, in opcode order.
+
+use super::test_support::{assert_registers, pixel, CpuBuilder};
+use super::*;
+
+fn quirks(edit: impl FnOnce(&mut Quirks)) -> Quirks {
+    let mut quirks = Quirks::default();
+    edit(&mut quirks);
+    quirks
+}
+
+#[test]
+fn clear_screen_clears_selected_planes() {
+    let cpu = CpuBuilder::new().pixel(1, 1).program(&[0x00E0]).run(1);
+    assert!(!pixel(&cpu, 1, 1));
+    assert!(cpu.redraw);
+
+    // FN01 with plane 2 selected leaves plane 1 alone
+    let cpu = CpuBuilder::new()
+        .pixel(1, 1)
+        .program(&[0xF201, 0x00E0])
+        .run(2);
+    assert!(pixel(&cpu, 1, 1));
+}
+
+#[test]
+fn return_pops_the_stack() {
+    let cpu = CpuBuilder::new()
+        .stack(&[0x300, 0x400])
+        .program(&[0x00EE])
+        .run(1);
+    assert_eq!(cpu.pc, 0x400);
+    assert_eq!(cpu.sp, 1);
+
+    let (err, cpu) = CpuBuilder::new().program(&[0x00EE]).run_fault();
+    assert!(matches!(err, CpuError::StackUnderflow { addr: 0x200, .. }));
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn scrolls_down_and_up() {
+    let cpu = CpuBuilder::new().pixel(3, 0).program(&[0x00C2]).run(1);
+    assert!(pixel(&cpu, 3, 2));
+    assert!(!pixel(&cpu, 3, 0));
+
+    let cpu = CpuBuilder::new().pixel(3, 2).program(&[0x00D2]).run(1);
+    assert!(pixel(&cpu, 3, 0));
+    assert!(!pixel(&cpu, 3, 2));
+}
+
+#[test]
+fn scrolls_right_and_left() {
+    let cpu = CpuBuilder::new().pixel(0, 1).program(&[0x00FB]).run(1);
+    assert!(pixel(&cpu, 4, 1));
+    assert!(!pixel(&cpu, 0, 1));
+
+    let cpu = CpuBuilder::new().pixel(4, 1).program(&[0x00FC]).run(1);
+    assert!(pixel(&cpu, 0, 1));
+    // Pixels scrolled off the edge are lost rather than wrapping
+    let cpu = CpuBuilder::new().pixel(63, 1).program(&[0x00FB]).run(1);
+    assert!(!pixel(&cpu, 3, 1));
+}
+
+#[test]
+fn exit_stops_the_program() {
+    let cpu = CpuBuilder::new().program(&[0x00FD, 0x6001]).run(2);
+    assert!(!cpu.running());
+    assert_eq!(cpu.exit_code(), Some(0));
+    assert_eq!(cpu.pc, 0x202);
+    assert_eq!(cpu.register(0), 0);
+}
+
+#[test]
+fn switches_resolution() {
+    let cpu = CpuBuilder::new().program(&[0x00FF]).run(1);
+    assert_eq!(cpu.screen_size(), (128, 64));
+    let cpu = CpuBuilder::new().hires().program(&[0x00FE]).run(1);
+    assert_eq!(cpu.screen_size(), (64, 32));
+}
+
+#[test]
+fn debug_exit_reports_its_code() {
+    let cpu = CpuBuilder::new().debug_exit().program(&[0x0F2A]).run(1);
+    assert!(!cpu.running());
+    assert_eq!(cpu.exit_code(), Some(0x2A));
+}
+
+#[test]
+fn debug_exit_is_off_by_default() {
+    let (err, cpu) = CpuBuilder::new().program(&[0x0F2A]).run_fault();
+    assert_eq!(
+        err,
+        CpuError::UnknownOpcode {
+            addr: 0x200,
+            opcode: 0x0F2A
+        }
+    );
+    assert!(cpu.running());
+}
+
+#[test]
+fn machine_code_subroutines_are_unknown() {
+    let (err, _) = CpuBuilder::new().program(&[0x0123]).run_fault();
+    assert_eq!(
+        err,
+        CpuError::UnknownOpcode {
+            addr: 0x200,
+            opcode: 0x0123
+        }
+    );
+}
+
+#[test]
+fn jumps() {
+    let cpu = CpuBuilder::new().program(&[0x1ABC]).run(1);
+    assert_eq!(cpu.pc, 0xABC);
+}
+
+#[test]
+fn call_pushes_the_return_address() {
+    let cpu = CpuBuilder::new().program(&[0x2ABC]).run(1);
+    assert_eq!(cpu.pc, 0xABC);
+    assert_eq!(cpu.sp, 1);
+    assert_eq!(cpu.stack[0], 0x202);
+
+    let (err, cpu) = CpuBuilder::new()
+        .stack(&[0x300; STACK_SIZE])
+        .program(&[0x2ABC])
+        .run_fault();
+    assert!(matches!(err, CpuError::StackOverflow { addr: 0x200, .. }));
+    assert_eq!(cpu.sp, STACK_SIZE);
+}
+
+#[test]
+fn skips_if_equal_to_value() {
+    let cpu = CpuBuilder::new().register(3, 7).program(&[0x3307]).run(1);
+    assert_eq!(cpu.pc, 0x204);
+    let cpu = CpuBuilder::new().register(3, 7).program(&[0x3308]).run(1);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn skips_if_not_equal_to_value() {
+    let cpu = CpuBuilder::new().register(3, 7).program(&[0x4308]).run(1);
+    assert_eq!(cpu.pc, 0x204);
+    let cpu = CpuBuilder::new().register(3, 7).program(&[0x4307]).run(1);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn skips_if_registers_equal() {
+    let cpu = CpuBuilder::new()
+        .register(1, 7)
+        .register(2, 7)
+        .program(&[0x5120])
+        .run(1);
+    assert_eq!(cpu.pc, 0x204);
+    let cpu = CpuBuilder::new().register(1, 7).program(&[0x5120]).run(1);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn skips_over_both_words_of_long_load() {
+    let cpu = CpuBuilder::new()
+        .program(&[0x3000, 0xF000, 0x1234, 0x6101])
+        .run(2);
+    assert_eq!(cpu.register(1), 1);
+    assert_eq!(cpu.address_register, 0);
+}
+
+#[test]
+fn saves_register_ranges() {
+    let cpu = CpuBuilder::new()
+        .register(1, 0x11)
+        .register(2, 0x22)
+        .register(3, 0x33)
+        .i(0x300)
+        .program(&[0x5132, 0x5312])
+        .run(1);
+    assert_eq!(&cpu.memory[0x300..0x303], &[0x11, 0x22, 0x33]);
+    // I is left alone
+    assert_eq!(cpu.address_register, 0x300);
+
+    // Reversed ranges store the registers in reverse
+    let cpu = CpuBuilder::new()
+        .register(1, 0x11)
+        .register(3, 0x33)
+        .i(0x300)
+        .program(&[0x5312])
+        .run(1);
+    assert_eq!(&cpu.memory[0x300..0x303], &[0x33, 0x00, 0x11]);
+}
+
+#[test]
+fn loads_register_ranges() {
+    let cpu = CpuBuilder::new()
+        .memory(0x300, &[0x11, 0x22, 0x33])
+        .i(0x300)
+        .program(&[0x5133])
+        .run(1);
+    assert_registers(&cpu, &[(1, 0x11), (2, 0x22), (3, 0x33)]);
+
+    let cpu = CpuBuilder::new()
+        .memory(0x300, &[0x11, 0x22])
+        .i(0x300)
+        .program(&[0x5213])
+        .run(1);
+    assert_registers(&cpu, &[(2, 0x11), (1, 0x22)]);
+}
+
+#[test]
+fn sets_register() {
+    let cpu = CpuBuilder::new().program(&[0x6A42]).run(1);
+    assert_eq!(cpu.register(0xA), 0x42);
+}
+
+#[test]
+fn adds_value_without_touching_the_flag() {
+    let cpu = CpuBuilder::new()
+        .register(0, 0xFF)
+        .register(0xF, 7)
+        .program(&[0x7002])
+        .run(1);
+    assert_registers(&cpu, &[(0, 0x01), (0xF, 7)]);
+}
+
+#[test]
+fn copies_register() {
+    let cpu = CpuBuilder::new().register(1, 9).program(&[0x8010]).run(1);
+    assert_eq!(cpu.register(0), 9);
+}
+
+#[test]
+fn bitwise_operations() {
+    let run = |opcode| {
+        CpuBuilder::new()
+            .register(0, 0x0C)
+            .register(1, 0x0A)
+            .register(0xF, 7)
+            .program(&[opcode])
+            .run(1)
+    };
+    assert_registers(&run(0x8011), &[(0, 0x0E), (0xF, 7)]);
+    assert_registers(&run(0x8012), &[(0, 0x08), (0xF, 7)]);
+    assert_registers(&run(0x8013), &[(0, 0x06), (0xF, 7)]);
+}
+
+#[test]
+fn vf_reset_quirk_clears_flag_on_bitwise_operations() {
+    let vf_reset = quirks(|q| q.vf_reset = true);
+    for opcode in [0x8011, 0x8012, 0x8013] {
+        let cpu = CpuBuilder::with_quirks(vf_reset)
+            .register(0xF, 7)
+            .program(&[opcode])
+            .run(1);
+        assert_eq!(cpu.register(0xF), 0, "{:04X}", opcode);
+    }
+}
+
+#[test]
+fn addition_sets_carry() {
+    let run = |x, y| {
+        CpuBuilder::new()
+            .register(0, x)
+            .register(1, y)
+            .program(&[0x8014])
+            .run(1)
+    };
+    assert_registers(&run(0x10, 0x20), &[(0, 0x30), (0xF, 0)]);
+    assert_registers(&run(0xFF, 0x02), &[(0, 0x01), (0xF, 1)]);
+    assert_registers(&run(0xFF, 0x01), &[(0, 0x00), (0xF, 1)]);
+}
+
+#[test]
+fn addition_into_vf_keeps_the_carry() {
+    let cpu = CpuBuilder::new()
+        .register(0xF, 0xFF)
+        .register(1, 0x02)
+        .program(&[0x8F14])
+        .run(1);
+    assert_eq!(cpu.register(0xF), 1);
+    let cpu = CpuBuilder::new()
+        .register(0xF, 0x10)
+        .register(1, 0x02)
+        .program(&[0x8F14])
+        .run(1);
+    assert_eq!(cpu.register(0xF), 0);
+
+    // VF as the source is read before the flag is written
+    let cpu = CpuBuilder::new()
+        .register(0, 0x01)
+        .register(0xF, 0xFF)
+        .program(&[0x80F4])
+        .run(1);
+    assert_registers(&cpu, &[(0, 0x00), (0xF, 1)]);
+}
+
+#[test]
+fn subtraction_sets_no_borrow_flag() {
+    let run = |x, y| {
+        CpuBuilder::new()
+            .register(0, x)
+            .register(1, y)
+            .program(&[0x8015])
+            .run(1)
+    };
+    assert_registers(&run(0x30, 0x10), &[(0, 0x20), (0xF, 1)]);
+    assert_registers(&run(0x10, 0x30), &[(0, 0xE0), (0xF, 0)]);
+    assert_registers(&run(0x05, 0x05), &[(0, 0x00), (0xF, 1)]);
+}
+
+#[test]
+fn subtraction_into_vf_keeps_the_flag() {
+    let cpu = CpuBuilder::new()
+        .register(0xF, 0x05)
+        .register(1, 0x02)
+        .program(&[0x8F15])
+        .run(1);
+    assert_eq!(cpu.register(0xF), 1);
+    let cpu = CpuBuilder::new()
+        .register(0xF, 0x01)
+        .register(1, 0x02)
+        .program(&[0x8F15])
+        .run(1);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn reverse_subtraction_sets_no_borrow_flag() {
+    let run = |x, y| {
+        CpuBuilder::new()
+            .register(0, x)
+            .register(1, y)
+            .program(&[0x8017])
+            .run(1)
+    };
+    assert_registers(&run(0x10, 0x30), &[(0, 0x20), (0xF, 1)]);
+    assert_registers(&run(0x30, 0x10), &[(0, 0xE0), (0xF, 0)]);
+    assert_registers(&run(0x05, 0x05), &[(0, 0x00), (0xF, 1)]);
+
+    let cpu = CpuBuilder::new()
+        .register(0xF, 0x05)
+        .register(1, 0x02)
+        .program(&[0x8F17])
+        .run(1);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn shifts_right() {
+    // Without the shift quirk VY is shifted into VX
+    let cpu = CpuBuilder::new()
+        .register(0, 0xFF)
+        .register(1, 0x05)
+        .program(&[0x8016])
+        .run(1);
+    assert_registers(&cpu, &[(0, 0x02), (0xF, 1)]);
+
+    let shift = quirks(|q| q.shift = true);
+    let cpu = CpuBuilder::with_quirks(shift)
+        .register(0, 0x04)
+        .register(1, 0x05)
+        .program(&[0x8016])
+        .run(1);
+    assert_registers(&cpu, &[(0, 0x02), (0xF, 0)]);
+
+    // Into VF, the flag wins over the result
+    let cpu = CpuBuilder::with_quirks(shift)
+        .register(0xF, 0x03)
+        .program(&[0x8F06])
+        .run(1);
+    assert_eq!(cpu.register(0xF), 1);
+}
+
+#[test]
+fn shifts_left() {
+    let cpu = CpuBuilder::new()
+        .register(0, 0xFF)
+        .register(1, 0x81)
+        .program(&[0x801E])
+        .run(1);
+    assert_registers(&cpu, &[(0, 0x02), (0xF, 1)]);
+
+    let shift = quirks(|q| q.shift = true);
+    let cpu = CpuBuilder::with_quirks(shift)
+        .register(0, 0x41)
+        .register(1, 0x81)
+        .program(&[0x801E])
+        .run(1);
+    assert_registers(&cpu, &[(0, 0x82), (0xF, 0)]);
+
+    let cpu = CpuBuilder::with_quirks(shift)
+        .register(0xF, 0x40)
+        .program(&[0x8F0E])
+        .run(1);
+    assert_eq!(cpu.register(0xF), 0);
+}
+
+#[test]
+fn skips_if_registers_differ() {
+    let cpu = CpuBuilder::new().register(1, 7).program(&[0x9120]).run(1);
+    assert_eq!(cpu.pc, 0x204);
+    let cpu = CpuBuilder::new().program(&[0x9120]).run(1);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn sets_address_register() {
+    let cpu = CpuBuilder::new().program(&[0xA123]).run(1);
+    assert_eq!(cpu.address_register, 0x123);
+}
+
+#[test]
+fn jumps_with_offset() {
+    let cpu = CpuBuilder::new()
+        .register(0, 4)
+        .register(3, 8)
+        .program(&[0xB300])
+        .run(1);
+    assert_eq!(cpu.pc, 0x304);
+
+    let jump = quirks(|q| q.jump = true);
+    let cpu = CpuBuilder::with_quirks(jump)
+        .register(0, 4)
+        .register(3, 8)
+        .program(&[0xB300])
+        .run(1);
+    assert_eq!(cpu.pc, 0x308);
+}
+
+#[test]
+fn random_numbers_are_masked() {
+    let cpu = CpuBuilder::new().program(&[0xC000, 0xC10F]).run(2);
+    assert_eq!(cpu.register(0), 0);
+    assert_eq!(cpu.register(1) & 0xF0, 0);
+}
+
+#[test]
+fn draws_sprites_and_reports_collisions() {
+    let cpu = CpuBuilder::new()
+        .register(0, 3)
+        .register(1, 4)
+        .memory(0x300, &[0xC0])
+        .i(0x300)
+        .program(&[0xD011])
+        .run(1);
+    assert!(pixel(&cpu, 3, 4));
+    assert!(pixel(&cpu, 4, 4));
+    assert_eq!(cpu.register(0xF), 0);
+    assert!(cpu.redraw);
+
+    let cpu = CpuBuilder::new()
+        .register(0, 3)
+        .register(1, 4)
+        .pixel(4, 4)
+        .memory(0x300, &[0xC0])
+        .i(0x300)
+        .program(&[0xD011])
+        .run(1);
+    assert!(pixel(&cpu, 3, 4));
+    assert!(!pixel(&cpu, 4, 4));
+    assert_eq!(cpu.register(0xF), 1);
+}
+
+#[test]
+fn sprite_start_position_wraps() {
+    let cpu = CpuBuilder::with_quirks(quirks(|q| q.clipping = true))
+        .register(0, 64 + 3)
+        .register(1, 32 + 4)
+        .memory(0x300, &[0x80])
+        .i(0x300)
+        .program(&[0xD011])
+        .run(1);
+    assert!(pixel(&cpu, 3, 4));
+}
+
+#[test]
+fn sprites_wrap_around_the_edges() {
+    let cpu = CpuBuilder::new()
+        .register(0, 60)
+        .register(1, 31)
+        .memory(0x300, &[0xFF, 0xFF])
+        .i(0x300)
+        .program(&[0xD012])
+        .run(1);
+    for y in [31, 0] {
+        for x in [60, 63, 0, 3] {
+            assert!(pixel(&cpu, x, y), "{}, {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn clipping_quirk_cuts_sprites_at_the_edges() {
+    let cpu = CpuBuilder::with_quirks(quirks(|q| q.clipping = true))
+        .register(0, 60)
+        .register(1, 31)
+        .memory(0x300, &[0xFF, 0xFF])
+        .i(0x300)
+        .program(&[0xD012])
+        .run(1);
+    assert!(pixel(&cpu, 63, 31));
+    assert!(!pixel(&cpu, 0, 31));
+    assert!(!pixel(&cpu, 60, 0));
+}
+
+#[test]
+fn display_wait_quirk_pauses_until_the_next_frame() {
+    let mut cpu = CpuBuilder::with_quirks(quirks(|q| q.display_wait = true))
+        .program(&[0xD011, 0x6001])
+        .run(2);
+    assert!(cpu.waiting_for_vblank());
+    assert_eq!(cpu.register(0), 0);
+    cpu.tick_timers();
+    cpu.step().unwrap();
+    assert_eq!(cpu.register(0), 1);
+}
+
+#[test]
+fn draws_large_sprites() {
+    let mut sprite = [0; 32];
+    sprite[0] = 0xFF;
+    sprite[1] = 0xFF;
+    sprite[31] = 0x01;
+    let cpu = CpuBuilder::new()
+        .hires()
+        .memory(0x300, &sprite)
+        .i(0x300)
+        .program(&[0xD000])
+        .run(1);
+    assert!(pixel(&cpu, 0, 0));
+    assert!(pixel(&cpu, 15, 0));
+    assert!(!pixel(&cpu, 16, 0));
+    assert!(pixel(&cpu, 15, 15));
+    assert!(!pixel(&cpu, 14, 15));
+}
+
+#[test]
+fn draws_each_selected_plane_from_consecutive_data() {
+    let cpu = CpuBuilder::new()
+        .memory(0x300, &[0x80, 0x40])
+        .i(0x300)
+        .program(&[0xF301, 0xD011])
+        .run(2);
+    assert_eq!(cpu.screen()[0], 1);
+    assert_eq!(cpu.screen()[1], 2);
+}
+
+#[test]
+fn skips_if_key_pressed() {
+    let cpu = CpuBuilder::new()
+        .register(0, 0xF)
+        .key(0xF)
+        .program(&[0xE09E])
+        .run(1);
+    assert_eq!(cpu.pc, 0x204);
+    let cpu = CpuBuilder::new().register(0, 0xF).program(&[0xE09E]).run(1);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn skips_if_key_not_pressed() {
+    let cpu = CpuBuilder::new().register(0, 5).program(&[0xE0A1]).run(1);
+    assert_eq!(cpu.pc, 0x204);
+    let cpu = CpuBuilder::new()
+        .register(0, 5)
+        .key(5)
+        .program(&[0xE0A1])
+        .run(1);
+    assert_eq!(cpu.pc, 0x202);
+}
+
+#[test]
+fn loads_long_address() {
+    let cpu = CpuBuilder::new().program(&[0xF000, 0xBEEF]).run(1);
+    assert_eq!(cpu.address_register, 0xBEEF);
+    assert_eq!(cpu.pc, 0x204);
+}
+
+#[test]
+fn selects_planes() {
+    let cpu = CpuBuilder::new().program(&[0xF301]).run(1);
+    assert_eq!(cpu.planes(), 3);
+    let cpu = CpuBuilder::new().program(&[0xF001]).run(1);
+    assert_eq!(cpu.planes(), 0);
+}
+
+#[test]
+fn reads_and_writes_timers() {
+    let cpu = CpuBuilder::new()
+        .delay_timer(9)
+        .register(1, 4)
+        .register(2, 6)
+        .program(&[0xF007, 0xF115, 0xF218])
+        .run(3);
+    assert_eq!(cpu.register(0), 9);
+    assert_eq!(cpu.delay_timer, 4);
+    assert_eq!(cpu.sound_timer, 6);
+}
+
+#[test]
+fn waits_for_a_key() {
+    let cpu = CpuBuilder::new().program(&[0xF00A]).run(3);
+    assert_eq!(cpu.pc, 0x200);
+
+    let cpu = CpuBuilder::new().key(0xF).program(&[0xF00A]).run(1);
+    assert_eq!(cpu.pc, 0x202);
+    assert_eq!(cpu.register(0), 0xF);
+}
+
+#[test]
+fn adds_to_address_register() {
+    let cpu = CpuBuilder::new()
+        .i(0x0FFF)
+        .register(0, 1)
+        .register(0xF, 7)
+        .program(&[0xF01E])
+        .run(1);
+    // I isn't limited to 12 bits, and VF is left alone
+    assert_eq!(cpu.address_register, 0x1000);
+    assert_eq!(cpu.register(0xF), 7);
+}
+
+#[test]
+fn address_register_wraps_at_the_end_of_memory() {
+    let cpu = CpuBuilder::new()
+        .i(0xFFFF)
+        .register(0, 2)
+        .program(&[0xF01E])
+        .run(1);
+    assert_eq!(cpu.address_register, 0x0001);
+}
+
+#[test]
+fn points_at_font_characters() {
+    let cpu = CpuBuilder::new().register(0, 0xA).program(&[0xF029]).run(1);
+    assert_eq!(
+        cpu.address_register,
+        FONT_START_ADDR + 0xA * FONT_SPRITE_SIZE
+    );
+    // Only the low nibble selects the character
+    let cpu = CpuBuilder::new()
+        .register(0, 0x1A)
+        .program(&[0xF029])
+        .run(1);
+    assert_eq!(
+        cpu.address_register,
+        FONT_START_ADDR + 0xA * FONT_SPRITE_SIZE
+    );
+
+    let cpu = CpuBuilder::new().register(0, 0x2).program(&[0xF030]).run(1);
+    assert_eq!(
+        cpu.address_register,
+        BIG_FONT_START_ADDR + 2 * BIG_FONT_SPRITE_SIZE
+    );
+    assert_eq!(cpu.memory[cpu.address_register], BIG_FONT[20]);
+}
+
+#[test]
+fn sets_pitch() {
+    let cpu = CpuBuilder::new()
+        .register(0, 0x70)
+        .program(&[0xF03A])
+        .run(1);
+    assert_eq!(cpu.pitch, 0x70);
+}
+
+#[test]
+fn stores_decimal_digits() {
+    let cpu = CpuBuilder::new()
+        .register(0, 254)
+        .i(0x300)
+        .program(&[0xF033])
+        .run(1);
+    assert_eq!(&cpu.memory[0x300..0x303], &[2, 5, 4]);
+    assert_eq!(cpu.address_register, 0x300);
+
+    let cpu = CpuBuilder::new()
+        .register(0, 7)
+        .i(MEMORY_SIZE - 3)
+        .program(&[0xF033])
+        .run(1);
+    assert_eq!(&cpu.memory[(MEMORY_SIZE - 3)..], &[0, 0, 7]);
+}
+
+#[test]
+fn decimal_digits_past_the_end_of_memory_fault() {
+    let (err, cpu) = CpuBuilder::new()
+        .register(0, 254)
+        .i(MEMORY_SIZE - 2)
+        .memory(MEMORY_SIZE - 2, &[0xAA, 0xBB])
+        .program(&[0xF033])
+        .run_fault();
+    assert!(matches!(
+        err,
+        CpuError::MemoryOutOfBounds { addr: 0x200, .. }
+    ));
+    assert_eq!(&cpu.memory[(MEMORY_SIZE - 2)..], &[0xAA, 0xBB]);
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn saves_and_loads_registers() {
+    let cpu = CpuBuilder::new()
+        .register(0, 0x11)
+        .register(1, 0x22)
+        .register(2, 0x33)
+        .i(0x300)
+        .program(&[0xF255])
+        .run(1);
+    assert_eq!(&cpu.memory[0x300..0x304], &[0x11, 0x22, 0x33, 0x00]);
+    assert_eq!(cpu.address_register, 0x303);
+
+    let cpu = CpuBuilder::new()
+        .memory(0x300, &[0x11, 0x22, 0x33])
+        .i(0x300)
+        .program(&[0xF165])
+        .run(1);
+    assert_registers(&cpu, &[(0, 0x11), (1, 0x22), (2, 0x00)]);
+    assert_eq!(cpu.address_register, 0x302);
+}
+
+#[test]
+fn load_store_quirk_leaves_address_register_alone() {
+    let load_store = quirks(|q| q.load_store = LoadStore::Unchanged);
+    let cpu = CpuBuilder::with_quirks(load_store)
+        .i(0x300)
+        .program(&[0xF255, 0xF265])
+        .run(2);
+    assert_eq!(cpu.address_register, 0x300);
+}
+
+#[test]
+fn load_store_quirk_increments_by_x() {
+    let load_store = quirks(|q| q.load_store = LoadStore::IncrementByX);
+    let cpu = CpuBuilder::with_quirks(load_store)
+        .i(0x300)
+        .program(&[0xF255, 0xF165])
+        .run(2);
+    assert_eq!(cpu.address_register, 0x303);
+}
+
+#[test]
+fn saves_and_loads_flag_registers() {
+    let cpu = CpuBuilder::new()
+        .register(0, 0x11)
+        .register(1, 0x22)
+        .program(&[0xF175, 0x6000, 0x6100, 0xF185])
+        .run(4);
+    assert_eq!(&cpu.flags[..3], &[0x11, 0x22, 0x00]);
+    assert_registers(&cpu, &[(0, 0x11), (1, 0x22)]);
+}
+
+#[test]
+fn rejects_unknown_opcodes() {
+    for opcode in [0x5121, 0x8008, 0x9121, 0xE000, 0xF0FF] {
+        let (err, _) = CpuBuilder::new().program(&[opcode]).run_fault();
+        assert_eq!(
+            err,
+            CpuError::UnknownOpcode {
+                addr: 0x200,
+                opcode
+            }
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::{fmt, str::FromStr};
+
+use super::MEMORY_SIZE;
+
+/// Behaviour switches for opcodes that were implemented differently across
+/// CHIP-8 interpreters.
+///
+/// The default is the XO-CHIP profile, which gives the same behaviour the
+/// emulator has always had, with the full 64 KiB of XO-CHIP memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    /// How `FX55`/`FX65` change I.
+    pub load_store: LoadStore,
+    /// `BNNN` jumps to `XNN + VX` instead of `NNN + V0`.
+    pub jump: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to zero.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clipping: bool,
+    /// `DXYN` waits for the next frame before execution continues.
+    pub display_wait: bool,
+    /// Bytes of memory the interpreter gives programs, which limits the size
+    /// of the ROMs it can load.
+    pub memory_size: usize,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Self = Self {
+        shift: false,
+        load_store: LoadStore::Increment,
+        jump: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+        memory_size: 0x1000,
+    };
+
+    pub const CHIP_48: Self = Self {
+        shift: true,
+        load_store: LoadStore::IncrementByX,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+        memory_size: 0x1000,
+    };
+
+    pub const SUPER_CHIP: Self = Self {
+        shift: true,
+        load_store: LoadStore::Unchanged,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+        memory_size: 0x1000,
+    };
+
+    pub const XO_CHIP: Self = Self {
+        shift: false,
+        load_store: LoadStore::Increment,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+        memory_size: MEMORY_SIZE,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::XO_CHIP
+    }
+}
+
+/// How `FX55`/`FX65` change I after storing or loading V0 through VX.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoadStore {
+    /// I is incremented by X + 1, past the last register (COSMAC VIP).
+    #[default]
+    Increment,
+    /// I is incremented by X, ending on the last register (CHIP-48).
+    IncrementByX,
+    /// I is left unchanged (SUPER-CHIP 1.1).
+    Unchanged,
+}
+
+impl LoadStore {
+    /// How far I moves after an `FX55`/`FX65` with the given X.
+    pub fn increment(&self, x: usize) -> usize {
+        match self {
+            LoadStore::Increment => x + 1,
+            LoadStore::IncrementByX => x,
+            LoadStore::Unchanged => 0,
+        }
+    }
+}
+
+/// Named quirk presets matching well-known interpreters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuirkProfile {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    #[default]
+    XoChip,
+}
+
+impl QuirkProfile {
+    pub const ALL: [QuirkProfile; 4] = [
+        QuirkProfile::CosmacVip,
+        QuirkProfile::Chip48,
+        QuirkProfile::SuperChip,
+        QuirkProfile::XoChip,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuirkProfile::CosmacVip => "vip",
+            QuirkProfile::Chip48 => "chip48",
+            QuirkProfile::SuperChip => "schip",
+            QuirkProfile::XoChip => "xochip",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            QuirkProfile::CosmacVip => Quirks::COSMAC_VIP,
+            QuirkProfile::Chip48 => Quirks::CHIP_48,
+            QuirkProfile::SuperChip => Quirks::SUPER_CHIP,
+            QuirkProfile::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+impl From<QuirkProfile> for Quirks {
+    fn from(profile: QuirkProfile) -> Self {
+        profile.quirks()
+    }
+}
+
+impl fmt::Display for QuirkProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for QuirkProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" | "chip8" | "chip-8" => Ok(QuirkProfile::CosmacVip),
+            "chip48" | "chip-48" => Ok(QuirkProfile::Chip48),
+            "schip" | "superchip" | "super-chip" => Ok(QuirkProfile::SuperChip),
+            "xochip" | "xo-chip" => Ok(QuirkProfile::XoChip),
+            _ => {
+                let names: Vec<_> = QuirkProfile::ALL.iter().map(|p| p.name()).collect();
+                Err(format!(
+                    "Unknown quirk profile '{}' (expected one of: {})",
+                    s,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_xo_chip() {
+        let quirks = Quirks::default();
+        assert_eq!(quirks, Quirks::XO_CHIP);
+    }
+
+    #[test]
+    fn profiles_are_distinct() {
+        for (idx, profile) in QuirkProfile::ALL.iter().enumerate() {
+            for other in &QuirkProfile::ALL[idx + 1..] {
+                assert_ne!(
+                    profile.quirks(),
+                    other.quirks(),
+                    "{} and {}",
+                    profile,
+                    other
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parses_profile_names() {
+        for profile in QuirkProfile::ALL {
+            let parsed: QuirkProfile = profile.name().parse().unwrap();
+            assert_eq!(parsed, profile);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_profile() {
+        assert!("nope".parse::<QuirkProfile>().is_err());
+    }
+}
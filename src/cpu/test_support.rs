@@ -0,0 +1,126 @@
+//! Declarative setup of a [`Cpu`] for unit tests.
+
+use super::{Cpu, CpuError, Quirks, PROGRAM_START};
+
+/// Builds a [`Cpu`] in a given state, runs instructions on it and hands it
+/// back for assertions.
+///
+/// ```ignore
+/// let cpu = CpuBuilder::new()
+///     .register(0, 0xFF)
+///     .register(1, 0x02)
+///     .program(&[0x8014])
+///     .run(1);
+/// assert_eq!(cpu.register(0xF), 1);
+/// ```
+pub(crate) struct CpuBuilder {
+    cpu: Cpu,
+}
+
+impl CpuBuilder {
+    /// A CPU with no quirks, a fixed random seed and the PC at the program
+    /// start.
+    pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.seed_rng(0);
+        Self { cpu }
+    }
+
+    /// Places instruction words at the program start.
+    pub fn program(self, words: &[u16]) -> Self {
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        self.memory(PROGRAM_START, &bytes)
+    }
+
+    pub fn register(mut self, idx: usize, value: u8) -> Self {
+        self.cpu.set_register(idx, value);
+        self
+    }
+
+    /// Sets the address register `I`.
+    pub fn i(mut self, addr: usize) -> Self {
+        self.cpu.address_register = addr;
+        self
+    }
+
+    pub fn memory(mut self, addr: usize, bytes: &[u8]) -> Self {
+        self.cpu.memory[addr..(addr + bytes.len())].copy_from_slice(bytes);
+        self
+    }
+
+    /// Pushes return addresses, bottom of the stack first.
+    pub fn stack(mut self, addrs: &[usize]) -> Self {
+        self.cpu.stack[..addrs.len()].copy_from_slice(addrs);
+        self.cpu.sp = addrs.len();
+        self
+    }
+
+    pub fn key(mut self, key: u8) -> Self {
+        self.cpu.set_key(key, true);
+        self
+    }
+
+    pub fn delay_timer(mut self, value: u8) -> Self {
+        self.cpu.delay_timer = value;
+        self
+    }
+
+    pub fn debug_exit(mut self) -> Self {
+        self.cpu.set_debug_exit(true);
+        self
+    }
+
+    pub fn hires(mut self) -> Self {
+        self.cpu.gpu.set_hires(true);
+        self
+    }
+
+    /// Lights a pixel on the first plane.
+    pub fn pixel(mut self, x: usize, y: usize) -> Self {
+        self.cpu.gpu.set(x, y, 1, true);
+        self
+    }
+
+    /// Executes `count` instructions, panicking on a fault.
+    pub fn run(self, count: usize) -> Cpu {
+        let mut cpu = self.cpu;
+        for step in 0..count {
+            if let Err(err) = cpu.step() {
+                panic!("Instruction {} faulted: {}", step + 1, err);
+            }
+        }
+        cpu
+    }
+
+    /// Executes one instruction that is expected to fault, returning the
+    /// fault and the CPU.
+    pub fn run_fault(self) -> (CpuError, Cpu) {
+        let mut cpu = self.cpu;
+        let err = cpu.step().expect_err("Instruction didn't fault");
+        (err, cpu)
+    }
+}
+
+/// Whether the pixel at `x`, `y` is lit on any plane.
+pub(crate) fn pixel(cpu: &Cpu, x: usize, y: usize) -> bool {
+    let (width, _) = cpu.screen_size();
+    cpu.screen()[y * width + x] != 0
+}
+
+/// Asserts the value of several `V` registers at once.
+pub(crate) fn assert_registers(cpu: &Cpu, expected: &[(usize, u8)]) {
+    for (idx, value) in expected {
+        assert_eq!(
+            cpu.register(*idx),
+            *value,
+            "V{:X} is {:#04X}, expected {:#04X}",
+            idx,
+            cpu.register(*idx),
+            value
+        );
+    }
+}
@@ -0,0 +1,741 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use crate::{
+    cpu::{Access, AccessKind, CpuError, Op, MEMORY_SIZE},
+    Machine,
+};
+
+/// Comparison operators for [`Condition`]s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub const ALL: [Comparison; 6] = [
+        Comparison::Eq,
+        Comparison::Ne,
+        Comparison::Lt,
+        Comparison::Le,
+        Comparison::Gt,
+        Comparison::Ge,
+    ];
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+
+    pub fn test(&self, lhs: u8, rhs: u8) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// A breakpoint on a register value, such as `V3 == 0x10`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub register: u8,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+impl Condition {
+    pub fn holds(&self, machine: &Machine) -> bool {
+        let lhs = machine.cpu().register(self.register as usize);
+        self.comparison.test(lhs, self.value)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "V{:X} {} 0x{:02X}",
+            self.register, self.comparison, self.value
+        )
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /// Parses `VX OP VALUE`, where the value is decimal or `0x` hex.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let [register, comparison, value] = parts[..] else {
+            return Err(format!("Expected 'VX OP VALUE', found '{}'", s));
+        };
+        let register = register
+            .strip_prefix(['v', 'V'])
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+            .ok_or_else(|| format!("Invalid register '{}'", register))?;
+        let comparison = Comparison::ALL
+            .into_iter()
+            .find(|c| c.symbol() == comparison)
+            .ok_or_else(|| {
+                let symbols: Vec<_> = Comparison::ALL.iter().map(|c| c.symbol()).collect();
+                format!(
+                    "Unknown comparison '{}' (expected one of: {})",
+                    comparison,
+                    symbols.join(", ")
+                )
+            })?;
+        let parsed = match value.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => value.parse(),
+        };
+        let value = parsed.map_err(|_| format!("Invalid value '{}'", value))?;
+        Ok(Self {
+            register,
+            comparison,
+            value,
+        })
+    }
+}
+
+/// Watches an inclusive address range for the selected kinds of access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {
+            start: start.min(end),
+            end: start.max(end),
+            read: false,
+            write: false,
+            execute: false,
+        }
+    }
+
+    pub fn watches(&self, kind: AccessKind) -> bool {
+        match kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+            AccessKind::Execute => self.execute,
+        }
+    }
+
+    /// The first watched address touched by `access`, if it triggers this
+    /// watchpoint.
+    pub fn hit(&self, access: &Access) -> Option<usize> {
+        if !self.watches(access.kind) {
+            return None;
+        }
+        let start = access.range.start.max(self.start);
+        (start < access.range.end && start <= self.end).then_some(start)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{:04X}", self.start)?;
+        } else {
+            write!(f, "{:04X}-{:04X}", self.start, self.end)?;
+        }
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+        write!(
+            f,
+            " {}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.execute, 'x')
+        )
+    }
+}
+
+/// A memory access that paused execution, with the instruction that made it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub kind: AccessKind,
+    pub addr: usize,
+    pub pc: usize,
+    pub opcode: u16,
+}
+
+/// Why execution was paused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(usize),
+    Condition(Condition),
+    Watchpoint(Hit),
+    /// An instruction wrote to memory that had already been executed.
+    SelfModifying(Hit),
+    /// A step, step-over, step-out or run-to-address finished.
+    Step(usize),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(addr) => write!(f, "Breakpoint at {:04X}", addr),
+            Stop::Condition(condition) => write!(f, "Break on {}", condition),
+            Stop::Watchpoint(hit) => write!(
+                f,
+                "Watchpoint: {} of {:04X} by {:04X} ({:04X})",
+                hit.kind, hit.addr, hit.pc, hit.opcode
+            ),
+            Stop::SelfModifying(hit) => write!(
+                f,
+                "Self-modifying code: write to {:04X} by {:04X} ({:04X})",
+                hit.addr, hit.pc, hit.opcode
+            ),
+            Stop::Step(addr) => write!(f, "Paused at {:04X}", addr),
+        }
+    }
+}
+
+/// Where a pending step command should pause again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    /// After the next instruction.
+    Step,
+    /// When the PC reaches the address.
+    Address(usize),
+    /// When the PC reaches the address with the stack at the given depth,
+    /// i.e. when a stepped-over call returns.
+    Return { addr: usize, sp: usize },
+    /// When the stack drops below the given depth.
+    Out { sp: usize },
+}
+
+/// Execution control on top of a [`Machine`]: pausing, stepping and
+/// breakpoints.
+///
+/// Frontends call [`Debugger::run_frame`] instead of [`Machine::run_frame`];
+/// while paused it doesn't execute anything, so the frontend can keep
+/// rendering.
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    paused: bool,
+    target: Option<Target>,
+    breakpoints: BTreeSet<usize>,
+    /// Conditions together with whether they held after the last
+    /// instruction, so each one only triggers when it becomes true.
+    conditions: Vec<(Condition, bool)>,
+    watchpoints: Vec<Watchpoint>,
+    /// Address whose breakpoint is ignored for the first instruction after
+    /// resuming, so resuming from a breakpoint doesn't stop right away.
+    resume_from: Option<usize>,
+    /// Which addresses have been fetched as instructions.
+    executed: Vec<bool>,
+    /// Executed addresses that were written to afterwards.
+    modified_code: BTreeSet<usize>,
+    break_on_self_modifying: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.target = None;
+    }
+
+    pub fn resume(&mut self, machine: &Machine) {
+        self.paused = false;
+        self.target = None;
+        self.resume_from = Some(machine.cpu().pc);
+    }
+
+    pub fn toggle_pause(&mut self, machine: &Machine) {
+        if self.paused {
+            self.resume(machine);
+        } else {
+            self.pause();
+        }
+    }
+
+    /// Executes one instruction, then pauses.
+    pub fn step(&mut self, machine: &Machine) {
+        self.start(machine, Target::Step);
+    }
+
+    /// Like [`Debugger::step`], but runs a `2NNN` call until it returns.
+    pub fn step_over(&mut self, machine: &Machine) {
+        let cpu = machine.cpu();
+        let target = match cpu.op_at(cpu.pc) {
+            op @ Op::Call { .. } => Target::Return {
+                addr: cpu.pc + op.size(),
+                sp: cpu.sp,
+            },
+            _ => Target::Step,
+        };
+        self.start(machine, target);
+    }
+
+    /// Runs until the current subroutine returns with `00EE`. Outside of a
+    /// subroutine this is a single step.
+    pub fn step_out(&mut self, machine: &Machine) {
+        let target = match machine.cpu().sp {
+            0 => Target::Step,
+            sp => Target::Out { sp },
+        };
+        self.start(machine, target);
+    }
+
+    /// Runs until the PC reaches `addr`.
+    pub fn run_to(&mut self, machine: &Machine, addr: usize) {
+        self.start(machine, Target::Address(addr));
+    }
+
+    fn start(&mut self, machine: &Machine, target: Target) {
+        self.resume(machine);
+        self.target = Some(target);
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    pub fn has_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: usize) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    pub fn add_condition(&mut self, condition: Condition, machine: &Machine) {
+        let holds = condition.holds(machine);
+        self.conditions.push((condition, holds));
+    }
+
+    pub fn remove_condition(&mut self, idx: usize) {
+        if idx < self.conditions.len() {
+            self.conditions.remove(idx);
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, idx: usize) {
+        if idx < self.watchpoints.len() {
+            self.watchpoints.remove(idx);
+        }
+    }
+
+    /// Whether the instruction fetch at `addr` has been seen.
+    pub fn executed(&self, addr: usize) -> bool {
+        self.executed.get(addr).copied().unwrap_or(false)
+    }
+
+    /// Addresses that were overwritten after being executed.
+    pub fn modified_code(&self) -> &BTreeSet<usize> {
+        &self.modified_code
+    }
+
+    pub fn break_on_self_modifying(&self) -> bool {
+        self.break_on_self_modifying
+    }
+
+    pub fn set_break_on_self_modifying(&mut self, enabled: bool) {
+        self.break_on_self_modifying = enabled;
+    }
+
+    /// Forgets which code has run, e.g. after the machine was reset.
+    pub fn clear_history(&mut self) {
+        self.executed.clear();
+        self.modified_code.clear();
+    }
+
+    /// Emulates one frame like [`Machine::run_frame`], checking breakpoints
+    /// and step targets around every instruction. When execution stops in
+    /// the middle of a frame, the timers are not ticked.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<Option<Stop>, CpuError> {
+        if self.paused {
+            return Ok(None);
+        }
+
+        machine.cpu_mut().memory.set_tracking(true);
+        for _ in 0..machine.instructions_per_frame() {
+            let cpu = machine.cpu();
+            if !cpu.running() || cpu.waiting_for_vblank() {
+                break;
+            }
+
+            let pc = cpu.pc;
+            let resuming = self.resume_from.take() == Some(pc);
+            if !resuming && self.breakpoints.contains(&pc) {
+                return Ok(Some(self.stop(Stop::Breakpoint(pc))));
+            }
+
+            let opcode = cpu.opcode_at(pc);
+            machine.cpu_mut().memory.clear_accesses();
+            machine.step()?;
+
+            if let Some(stop) = self.check_accesses(machine.cpu().memory.accesses(), pc, opcode) {
+                return Ok(Some(self.stop(stop)));
+            }
+            if let Some(stop) = self.check(machine) {
+                return Ok(Some(self.stop(stop)));
+            }
+        }
+        machine.tick_timers();
+        Ok(None)
+    }
+
+    /// Records the memory accesses of the instruction at `pc` and checks them
+    /// against the watchpoints.
+    fn check_accesses(&mut self, accesses: &[Access], pc: usize, opcode: u16) -> Option<Stop> {
+        if self.executed.is_empty() {
+            self.executed = vec![false; MEMORY_SIZE];
+        }
+
+        let mut stop = None;
+        for access in accesses {
+            let range = access.range.start.min(MEMORY_SIZE)..access.range.end.min(MEMORY_SIZE);
+            let hit = |addr| Hit {
+                kind: access.kind,
+                addr,
+                pc,
+                opcode,
+            };
+            match access.kind {
+                AccessKind::Execute => self.executed[range].fill(true),
+                AccessKind::Write => {
+                    let modified = range.filter(|addr| self.executed[*addr]);
+                    for addr in modified {
+                        self.modified_code.insert(addr);
+                        if self.break_on_self_modifying && stop.is_none() {
+                            stop = Some(Stop::SelfModifying(hit(addr)));
+                        }
+                    }
+                }
+                AccessKind::Read => {}
+            }
+            if stop.is_none() {
+                stop = self
+                    .watchpoints
+                    .iter()
+                    .find_map(|watchpoint| watchpoint.hit(access))
+                    .map(|addr| Stop::Watchpoint(hit(addr)));
+            }
+        }
+        stop
+    }
+
+    /// Checks conditions and the step target after an instruction.
+    fn check(&mut self, machine: &Machine) -> Option<Stop> {
+        let mut stop = None;
+        for (condition, held) in self.conditions.iter_mut() {
+            let holds = condition.holds(machine);
+            if holds && !*held && stop.is_none() {
+                stop = Some(Stop::Condition(*condition));
+            }
+            *held = holds;
+        }
+        if stop.is_some() {
+            return stop;
+        }
+
+        let cpu = machine.cpu();
+        let reached = match self.target? {
+            Target::Step => true,
+            Target::Address(addr) => cpu.pc == addr,
+            Target::Return { addr, sp } => cpu.pc == addr && cpu.sp == sp,
+            Target::Out { sp } => cpu.sp < sp,
+        };
+        reached.then_some(Stop::Step(cpu.pc))
+    }
+
+    fn stop(&mut self, stop: Stop) -> Stop {
+        self.pause();
+        stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x200: V0 := 1; call 0x20A; V0 += 1; jump 0x204
+    // 0x20A: V1 := 2; V1 += 1; return
+    const PROGRAM: [u8; 16] = [
+        0x60, 0x01, 0x22, 0x0A, 0x70, 0x01, 0x12, 0x04, 0x00, 0x00, 0x61, 0x02, 0x71, 0x01, 0x00,
+        0xEE,
+    ];
+
+    fn machine() -> Machine {
+        let mut machine = Machine::default();
+        machine.load_rom(&PROGRAM).unwrap();
+        machine
+    }
+
+    #[test]
+    fn ignores_accesses_past_the_end_of_memory() {
+        let mut debugger = Debugger::new();
+        let accesses = [
+            Access {
+                kind: AccessKind::Execute,
+                range: (MEMORY_SIZE - 2)..(MEMORY_SIZE + 2),
+            },
+            Access {
+                kind: AccessKind::Write,
+                range: (MEMORY_SIZE - 1)..(MEMORY_SIZE + 1),
+            },
+        ];
+        debugger.check_accesses(&accesses, 0x200, 0xF000);
+        assert!(debugger.executed(MEMORY_SIZE - 1));
+    }
+
+    #[test]
+    fn faults_on_long_load_at_the_end_of_memory() {
+        let mut machine = machine();
+        machine.cpu_mut().memory[MEMORY_SIZE - 3] = 0xF0;
+        machine.cpu_mut().pc = MEMORY_SIZE - 3;
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Err(CpuError::PcOutOfBounds {
+                pc: MEMORY_SIZE - 3
+            })
+        );
+    }
+
+    #[test]
+    fn pauses_without_executing() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+        assert_eq!(machine.cpu().pc, 0x200);
+    }
+
+    #[test]
+    fn steps_one_instruction() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        debugger.step(&machine);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Step(0x202)))
+        );
+        assert!(debugger.paused());
+        debugger.step(&machine);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Step(0x20A)))
+        );
+    }
+
+    #[test]
+    fn steps_over_and_out_of_calls() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        debugger.run_to(&machine, 0x202);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Step(0x202)))
+        );
+
+        debugger.step_over(&machine);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Step(0x204)))
+        );
+        assert_eq!(machine.cpu().register(1), 3);
+
+        machine.reset().unwrap();
+        debugger.run_to(&machine, 0x20C);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Step(0x20C)))
+        );
+        debugger.step_out(&machine);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Step(0x204)))
+        );
+        assert_eq!(machine.cpu().sp, 0);
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x20C);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Breakpoint(0x20C)))
+        );
+        assert_eq!(machine.cpu().register(1), 2);
+
+        // Resuming runs past the breakpoint it stopped at
+        debugger.resume(&machine);
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+        assert_eq!(machine.cpu().register(1), 3);
+
+        // A breakpoint also interrupts stepping over a call
+        machine.reset().unwrap();
+        debugger.run_to(&machine, 0x202);
+        debugger.run_frame(&mut machine).unwrap();
+        debugger.step_over(&machine);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Breakpoint(0x20C)))
+        );
+    }
+
+    #[test]
+    fn stops_when_condition_becomes_true() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        let condition: Condition = "v0 >= 3".parse().unwrap();
+        debugger.add_condition(condition, &machine);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Condition(condition)))
+        );
+        assert_eq!(machine.cpu().register(0), 3);
+        debugger.resume(&machine);
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+    }
+
+    #[test]
+    fn stops_at_watchpoints() {
+        let mut machine = Machine::default();
+        // I := 0x300; V0 := 0x13; save v0; load v0; jump 0x300
+        machine
+            .load_rom(&[0xA3, 0x00, 0x60, 0x13, 0xF0, 0x55, 0xF0, 0x65, 0x13, 0x00])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        let mut watchpoint = Watchpoint::new(0x300, 0x30F);
+        watchpoint.write = true;
+        debugger.add_watchpoint(watchpoint);
+        let hit = Hit {
+            kind: AccessKind::Write,
+            addr: 0x300,
+            pc: 0x204,
+            opcode: 0xF055,
+        };
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(Stop::Watchpoint(hit)))
+        );
+        assert_eq!(machine.cpu().memory[0x300], 0x13);
+
+        // Reads and execution aren't watched
+        debugger.resume(&machine);
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+
+        debugger.remove_watchpoint(0);
+        let mut watchpoint = Watchpoint::new(0x300, 0x300);
+        watchpoint.execute = true;
+        debugger.add_watchpoint(watchpoint);
+        machine.reset().unwrap();
+        let stop = debugger.run_frame(&mut machine).unwrap();
+        assert!(matches!(
+            stop,
+            Some(Stop::Watchpoint(Hit {
+                kind: AccessKind::Execute,
+                pc: 0x300,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn detects_self_modifying_code() {
+        let mut machine = Machine::default();
+        // I := 0x208; V0 := 0x61; save v0; jump 0x208; V0 := 2; jump 0x200
+        machine
+            .load_rom(&[
+                0xA2, 0x08, 0x60, 0x61, 0xF0, 0x55, 0x12, 0x08, 0x60, 0x02, 0x12, 0x00,
+            ])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        debugger.run_frame(&mut machine).unwrap();
+        assert!(debugger.executed(0x208));
+        assert!(!debugger.executed(0x20C));
+        assert!(debugger.modified_code().contains(&0x208));
+
+        debugger.set_break_on_self_modifying(true);
+        let stop = debugger.run_frame(&mut machine).unwrap();
+        assert_eq!(
+            stop,
+            Some(Stop::SelfModifying(Hit {
+                kind: AccessKind::Write,
+                addr: 0x208,
+                pc: 0x204,
+                opcode: 0xF055,
+            }))
+        );
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let condition: Condition = "VA != 0x1F".parse().unwrap();
+        assert_eq!(
+            condition,
+            Condition {
+                register: 0xA,
+                comparison: Comparison::Ne,
+                value: 0x1F,
+            }
+        );
+        assert_eq!(condition.to_string(), "VA != 0x1F");
+        assert!("V0 = 1".parse::<Condition>().is_err());
+        assert!("V10 == 1".parse::<Condition>().is_err());
+        assert!("V0 == 256".parse::<Condition>().is_err());
+    }
+}
@@ -0,0 +1,547 @@
+use std::{collections::BTreeMap, fmt, fmt::Write, str::FromStr};
+
+use crate::cpu::{Op, PROGRAM_START};
+
+const DATA_BYTES_PER_LINE: usize = 8;
+const COMMENT_COLUMN: usize = 32;
+
+/// Mnemonic flavour used when rendering instructions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo assembly, e.g. `v0 += 0x01`.
+    #[default]
+    Octo,
+    /// The classic mnemonics from Cowgod's technical reference, e.g.
+    /// `ADD V0, #01`.
+    Cowgod,
+}
+
+impl Syntax {
+    pub const ALL: [Syntax; 2] = [Syntax::Octo, Syntax::Cowgod];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Syntax::Octo => "octo",
+            Syntax::Cowgod => "cowgod",
+        }
+    }
+
+    fn comment(&self) -> char {
+        match self {
+            Syntax::Octo => '#',
+            Syntax::Cowgod => ';',
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "octo" => Ok(Syntax::Octo),
+            "cowgod" | "classic" => Ok(Syntax::Cowgod),
+            _ => {
+                let names: Vec<_> = Syntax::ALL.iter().map(|s| s.name()).collect();
+                Err(format!(
+                    "Unknown syntax '{}' (expected one of: {})",
+                    s,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+/// Renders a single instruction, with addresses as plain numbers.
+pub fn mnemonic(op: &Op, syntax: Syntax) -> String {
+    render(op, syntax, &BTreeMap::new())
+}
+
+/// Renders a single instruction, naming addresses that have a label.
+pub fn mnemonic_with_labels(op: &Op, syntax: Syntax, labels: &BTreeMap<usize, String>) -> String {
+    render(op, syntax, labels)
+}
+
+/// Parses a symbol file as written by `reimu asm --symbols`: one hex address
+/// and a name per line. Blank lines and lines starting with `#` or `;` are
+/// skipped.
+pub fn parse_symbol_file(text: &str) -> Result<BTreeMap<usize, String>, String> {
+    let mut labels = BTreeMap::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        let (addr, name) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("Line {}: expected an address and a name", idx + 1))?;
+        let digits = addr.strip_prefix("0x").unwrap_or(addr);
+        let addr = usize::from_str_radix(digits, 16)
+            .map_err(|_| format!("Line {}: invalid address '{}'", idx + 1, addr))?;
+        labels.insert(addr, name.trim().to_string());
+    }
+    Ok(labels)
+}
+
+fn render(op: &Op, syntax: Syntax, labels: &BTreeMap<usize, String>) -> String {
+    match syntax {
+        Syntax::Octo => {
+            let target = |addr: u16| {
+                labels
+                    .get(&(addr as usize))
+                    .cloned()
+                    .unwrap_or_else(|| format!("0x{:03X}", addr))
+            };
+            render_octo(op, target)
+        }
+        Syntax::Cowgod => {
+            let target = |addr: u16| {
+                labels
+                    .get(&(addr as usize))
+                    .cloned()
+                    .unwrap_or_else(|| format!("#{:03X}", addr))
+            };
+            render_cowgod(op, target)
+        }
+    }
+}
+
+fn render_octo(op: &Op, target: impl Fn(u16) -> String) -> String {
+    match *op {
+        Op::Clear => "clear".to_string(),
+        Op::Return => "return".to_string(),
+        Op::ScrollDown { n } => format!("scroll-down {}", n),
+        Op::ScrollUp { n } => format!("scroll-up {}", n),
+        Op::ScrollRight => "scroll-right".to_string(),
+        Op::ScrollLeft => "scroll-left".to_string(),
+        Op::Exit => "exit".to_string(),
+        Op::Lores => "lores".to_string(),
+        Op::Hires => "hires".to_string(),
+        // Octo has no mnemonic for the debug exit, so emit the raw bytes
+        Op::Jump { addr } => format!("jump {}", target(addr)),
+        Op::Call { addr } => format!(":call {}", target(addr)),
+        // Octo conditions describe when the next instruction runs, which is
+        // the inverse of when it is skipped
+        Op::SkipEqImm { x, nn } => format!("if v{:x} != 0x{:02X} then", x, nn),
+        Op::SkipNeImm { x, nn } => format!("if v{:x} == 0x{:02X} then", x, nn),
+        Op::SkipEq { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        Op::SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Op::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        Op::SetImm { x, nn } => format!("v{:x} := 0x{:02X}", x, nn),
+        Op::AddImm { x, nn } => format!("v{:x} += 0x{:02X}", x, nn),
+        Op::Set { x, y } => format!("v{:x} := v{:x}", x, y),
+        Op::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        Op::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Op::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Op::Add { x, y } => format!("v{:x} += v{:x}", x, y),
+        Op::Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Op::ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Op::SubN { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Op::ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        Op::SkipNe { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        Op::SetI { addr } => format!("i := {}", target(addr)),
+        Op::JumpOffset { addr, .. } => format!("jump0 {}", target(addr)),
+        Op::Random { x, nn } => format!("v{:x} := random 0x{:02X}", x, nn),
+        Op::Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Op::SkipKey { x } => format!("if v{:x} -key then", x),
+        Op::SkipNotKey { x } => format!("if v{:x} key then", x),
+        Op::LongI { addr } => format!("i := long {}", target(addr)),
+        Op::Plane { n } => format!("plane {}", n),
+        Op::Audio => "audio".to_string(),
+        Op::GetDelay { x } => format!("v{:x} := delay", x),
+        Op::WaitKey { x } => format!("v{:x} := key", x),
+        Op::SetDelay { x } => format!("delay := v{:x}", x),
+        Op::SetSound { x } => format!("buzzer := v{:x}", x),
+        Op::AddI { x } => format!("i += v{:x}", x),
+        Op::Font { x } => format!("i := hex v{:x}", x),
+        Op::BigFont { x } => format!("i := bighex v{:x}", x),
+        Op::Bcd { x } => format!("bcd v{:x}", x),
+        Op::Pitch { x } => format!("pitch := v{:x}", x),
+        Op::Store { x } => format!("save v{:x}", x),
+        Op::Load { x } => format!("load v{:x}", x),
+        Op::SaveFlags { x } => format!("saveflags v{:x}", x),
+        Op::LoadFlags { x } => format!("loadflags v{:x}", x),
+        Op::Unknown { opcode } => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+    }
+}
+
+fn render_cowgod(op: &Op, target: impl Fn(u16) -> String) -> String {
+    match *op {
+        Op::Clear => "CLS".to_string(),
+        Op::Return => "RET".to_string(),
+        Op::ScrollDown { n } => format!("SCD {}", n),
+        Op::ScrollUp { n } => format!("SCU {}", n),
+        Op::ScrollRight => "SCR".to_string(),
+        Op::ScrollLeft => "SCL".to_string(),
+        Op::Exit => "EXIT".to_string(),
+        Op::Lores => "LOW".to_string(),
+        Op::Hires => "HIGH".to_string(),
+        Op::Jump { addr } => format!("JP {}", target(addr)),
+        Op::Call { addr } => format!("CALL {}", target(addr)),
+        Op::SkipEqImm { x, nn } => format!("SE V{:X}, #{:02X}", x, nn),
+        Op::SkipNeImm { x, nn } => format!("SNE V{:X}, #{:02X}", x, nn),
+        Op::SkipEq { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Op::SaveRange { x, y } => format!("SAVE V{:X}, V{:X}", x, y),
+        Op::LoadRange { x, y } => format!("LOAD V{:X}, V{:X}", x, y),
+        Op::SetImm { x, nn } => format!("LD V{:X}, #{:02X}", x, nn),
+        Op::AddImm { x, nn } => format!("ADD V{:X}, #{:02X}", x, nn),
+        Op::Set { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Op::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Op::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Op::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Op::Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Op::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Op::ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Op::SubN { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Op::ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Op::SkipNe { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Op::SetI { addr } => format!("LD I, {}", target(addr)),
+        Op::JumpOffset { addr, .. } => format!("JP V0, {}", target(addr)),
+        Op::Random { x, nn } => format!("RND V{:X}, #{:02X}", x, nn),
+        Op::Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Op::SkipKey { x } => format!("SKP V{:X}", x),
+        Op::SkipNotKey { x } => format!("SKNP V{:X}", x),
+        Op::LongI { addr } => format!("LD I, LONG {}", target(addr)),
+        Op::Plane { n } => format!("PLANE {}", n),
+        Op::Audio => "AUDIO".to_string(),
+        Op::GetDelay { x } => format!("LD V{:X}, DT", x),
+        Op::WaitKey { x } => format!("LD V{:X}, K", x),
+        Op::SetDelay { x } => format!("LD DT, V{:X}", x),
+        Op::SetSound { x } => format!("LD ST, V{:X}", x),
+        Op::AddI { x } => format!("ADD I, V{:X}", x),
+        Op::Font { x } => format!("LD F, V{:X}", x),
+        Op::BigFont { x } => format!("LD HF, V{:X}", x),
+        Op::Bcd { x } => format!("LD B, V{:X}", x),
+        Op::Pitch { x } => format!("PITCH V{:X}", x),
+        Op::Store { x } => format!("LD [I], V{:X}", x),
+        Op::Load { x } => format!("LD V{:X}, [I]", x),
+        Op::SaveFlags { x } => format!("LD R, V{:X}", x),
+        Op::LoadFlags { x } => format!("LD V{:X}, R", x),
+        Op::Unknown { opcode } if opcode >> 12 == 0 => format!("SYS #{:03X}", opcode),
+        Op::Unknown { opcode } => format!("DW #{:04X}", opcode),
+    }
+}
+
+/// Why an address got a label. When an address is referenced in several
+/// ways the first kind in this order wins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Entry,
+    Subroutine,
+    Jump,
+    Data,
+}
+
+/// A ROM split into code and data.
+///
+/// Code is found by recursive descent from the entry point: every reachable
+/// instruction is decoded, following jumps, calls and both outcomes of skip
+/// instructions. `BNNN` is followed to `NNN` since the offset isn't known
+/// statically. Whatever is never reached is treated as data.
+#[derive(Clone, Debug)]
+pub struct Disassembly {
+    origin: usize,
+    bytes: Vec<u8>,
+    ops: BTreeMap<usize, Op>,
+    labels: BTreeMap<usize, String>,
+}
+
+impl Disassembly {
+    /// Disassembles a ROM loaded at the program start address.
+    pub fn new(rom: &[u8]) -> Self {
+        Self::with_origin(rom, PROGRAM_START)
+    }
+
+    /// Disassembles `bytes` loaded at `origin`, starting execution there.
+    pub fn with_origin(bytes: &[u8], origin: usize) -> Self {
+        let mut disasm = Self {
+            origin,
+            bytes: bytes.to_vec(),
+            ops: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disasm.analyze();
+        disasm
+    }
+
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    fn end(&self) -> usize {
+        self.origin + self.bytes.len()
+    }
+
+    /// The instruction starting at `addr`, if it is reachable code.
+    pub fn op_at(&self, addr: usize) -> Option<&Op> {
+        self.ops.get(&addr)
+    }
+
+    /// Every reachable instruction by address.
+    pub fn ops(&self) -> &BTreeMap<usize, Op> {
+        &self.ops
+    }
+
+    pub fn labels(&self) -> &BTreeMap<usize, String> {
+        &self.labels
+    }
+
+    /// Whether the byte at `addr` is part of a reachable instruction.
+    pub fn is_code(&self, addr: usize) -> bool {
+        self.ops
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(start, op)| addr < start + op.size())
+    }
+
+    fn word(&self, addr: usize) -> Option<u16> {
+        if addr < self.origin || addr + 2 > self.end() {
+            return None;
+        }
+        let idx = addr - self.origin;
+        Some(u16::from_be_bytes([self.bytes[idx], self.bytes[idx + 1]]))
+    }
+
+    fn analyze(&mut self) {
+        let mut covered = vec![false; self.bytes.len()];
+        let mut refs = BTreeMap::new();
+        let mut pending = vec![self.origin];
+        refs.insert(self.origin, LabelKind::Entry);
+
+        let refer = |refs: &mut BTreeMap<usize, LabelKind>, addr: u16, kind| {
+            let kind_ref = refs.entry(addr as usize).or_insert(kind);
+            *kind_ref = (*kind_ref).min(kind);
+        };
+
+        while let Some(addr) = pending.pop() {
+            let opcode = match self.word(addr) {
+                Some(opcode) => opcode,
+                None => continue,
+            };
+            let op = Op::decode(opcode, self.word(addr + 2).unwrap_or(0));
+            if matches!(op, Op::Unknown { .. }) || addr + op.size() > self.end() {
+                continue;
+            }
+            let span = (addr - self.origin)..(addr - self.origin + op.size());
+            if covered[span.clone()].iter().any(|c| *c) {
+                continue;
+            }
+            covered[span].fill(true);
+            self.ops.insert(addr, op);
+
+            let next = addr + op.size();
+            match op {
+                Op::Jump { addr: target } | Op::JumpOffset { addr: target, .. } => {
+                    refer(&mut refs, target, LabelKind::Jump);
+                    pending.push(target as usize);
+                }
+                Op::Call { addr: target } => {
+                    refer(&mut refs, target, LabelKind::Subroutine);
+                    pending.push(target as usize);
+                    pending.push(next);
+                }
+                Op::Return | Op::Exit => {}
+                Op::SetI { addr: target } | Op::LongI { addr: target } => {
+                    refer(&mut refs, target, LabelKind::Data);
+                    pending.push(next);
+                }
+                _ if op.is_skip() => {
+                    // Mirrors the CPU, which skips both words of `F000 NNNN`
+                    let skipped = if self.word(next) == Some(0xF000) {
+                        4
+                    } else {
+                        2
+                    };
+                    pending.push(next);
+                    pending.push(next + skipped);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        for (addr, kind) in refs {
+            if addr < self.origin || addr >= self.end() {
+                continue;
+            }
+            // A reference into the middle of an instruction can't be labelled
+            if self.is_code(addr) && !self.ops.contains_key(&addr) {
+                continue;
+            }
+            let name = match kind {
+                LabelKind::Entry => "main".to_string(),
+                LabelKind::Subroutine => format!("sub_{:04X}", addr),
+                LabelKind::Jump => format!("label_{:04X}", addr),
+                LabelKind::Data => format!("data_{:04X}", addr),
+            };
+            self.labels.insert(addr, name);
+        }
+    }
+
+    /// Renders the whole ROM as source, with labels for every referenced
+    /// address and data emitted as byte lists.
+    pub fn listing(&self, syntax: Syntax) -> String {
+        let mut out = String::new();
+        let mut addr = self.origin;
+        while addr < self.end() {
+            if let Some(label) = self.labels.get(&addr) {
+                match syntax {
+                    Syntax::Octo => writeln!(out, ": {}", label),
+                    Syntax::Cowgod => writeln!(out, "{}:", label),
+                }
+                .unwrap();
+            }
+
+            let start = addr;
+            let text = match self.ops.get(&addr) {
+                Some(op) => {
+                    addr += op.size();
+                    render(op, syntax, &self.labels)
+                }
+                None => {
+                    addr += 1;
+                    while addr < self.end()
+                        && addr - start < DATA_BYTES_PER_LINE
+                        && !self.ops.contains_key(&addr)
+                        && !self.labels.contains_key(&addr)
+                    {
+                        addr += 1;
+                    }
+                    let data = &self.bytes[(start - self.origin)..(addr - self.origin)];
+                    render_data(data, syntax)
+                }
+            };
+
+            let bytes: String = self.bytes[(start - self.origin)..(addr - self.origin)]
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+            writeln!(
+                out,
+                "\t{:<width$}{} {:04X}  {}",
+                text,
+                syntax.comment(),
+                start,
+                bytes,
+                width = COMMENT_COLUMN
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+fn render_data(data: &[u8], syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Octo => {
+            let bytes: Vec<_> = data.iter().map(|b| format!("0x{:02X}", b)).collect();
+            bytes.join(" ")
+        }
+        Syntax::Cowgod => {
+            let bytes: Vec<_> = data.iter().map(|b| format!("#{:02X}", b)).collect();
+            format!("DB {}", bytes.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_symbol_files() {
+        let labels = parse_symbol_file("# symbols\n0200 main\n\n0x0210 draw\n").unwrap();
+        assert_eq!(labels.get(&0x200).map(String::as_str), Some("main"));
+        assert_eq!(labels.get(&0x210).map(String::as_str), Some("draw"));
+        assert_eq!(
+            parse_symbol_file("0200 main\nzz oops").unwrap_err(),
+            "Line 2: invalid address 'zz'"
+        );
+
+        let op = Op::decode(0x2210, 0);
+        assert_eq!(
+            mnemonic_with_labels(&op, Syntax::Octo, &labels),
+            ":call draw"
+        );
+    }
+
+    #[test]
+    fn renders_both_syntaxes() {
+        let cases = [
+            (0x00E0, "clear", "CLS"),
+            (0x3A42, "if va != 0x42 then", "SE VA, #42"),
+            (0x8126, "v1 >>= v2", "SHR V1, V2"),
+            (0xA123, "i := 0x123", "LD I, #123"),
+            (0xD015, "sprite v0 v1 5", "DRW V0, V1, 5"),
+            (0xF355, "save v3", "LD [I], V3"),
+        ];
+        for (opcode, octo, cowgod) in cases {
+            let op = Op::decode(opcode, 0);
+            assert_eq!(mnemonic(&op, Syntax::Octo), octo);
+            assert_eq!(mnemonic(&op, Syntax::Cowgod), cowgod);
+        }
+    }
+
+    #[test]
+    fn parses_syntax_names() {
+        for syntax in Syntax::ALL {
+            assert_eq!(syntax.name().parse::<Syntax>().unwrap(), syntax);
+        }
+        assert!("nope".parse::<Syntax>().is_err());
+    }
+
+    #[test]
+    fn separates_code_from_data() {
+        // i := sprite; sprite v0 v0 2; loop: jump loop; sprite: 0xF0 0x90
+        let rom = [0xA2, 0x06, 0xD0, 0x02, 0x12, 0x04, 0xF0, 0x90];
+        let disasm = Disassembly::new(&rom);
+        assert_eq!(disasm.ops().len(), 3);
+        assert!(disasm.is_code(0x205));
+        assert!(!disasm.is_code(0x206));
+        assert_eq!(disasm.labels()[&0x204], "label_0204");
+        assert_eq!(disasm.labels()[&0x206], "data_0206");
+    }
+
+    #[test]
+    fn follows_skips_and_calls() {
+        // if v0 == 0 skip; jump 0x20A; sub; exit; (data) 0xFF 0xFF; sub: return
+        let rom = [
+            0x30, 0x00, 0x12, 0x0A, 0x22, 0x0A, 0x00, 0xFD, 0xFF, 0xFF, 0x00, 0xEE,
+        ];
+        let disasm = Disassembly::new(&rom);
+        assert_eq!(disasm.op_at(0x204), Some(&Op::Call { addr: 0x20A }));
+        assert_eq!(disasm.op_at(0x206), Some(&Op::Exit));
+        assert!(!disasm.is_code(0x208));
+        assert_eq!(disasm.labels()[&0x20A], "sub_020A");
+    }
+
+    #[test]
+    fn skips_over_long_load() {
+        // if v0 == 0 skip; i := long 0x1234; exit
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+        let disasm = Disassembly::new(&rom);
+        assert_eq!(disasm.op_at(0x202), Some(&Op::LongI { addr: 0x1234 }));
+        assert_eq!(disasm.op_at(0x206), Some(&Op::Exit));
+        assert_eq!(disasm.op_at(0x204), None);
+    }
+
+    #[test]
+    fn lists_with_labels() {
+        let rom = [0xA2, 0x06, 0xD0, 0x02, 0x12, 0x04, 0xF0, 0x90];
+        let listing = Disassembly::new(&rom).listing(Syntax::Octo);
+        let lines: Vec<_> = listing.lines().map(|l| l.trim_end()).collect();
+        assert_eq!(lines[0], ": main");
+        assert!(lines[1].starts_with("\ti := data_0206"));
+        assert_eq!(lines[3], ": label_0204");
+        assert!(lines[4].starts_with("\tjump label_0204"));
+        assert_eq!(lines[5], ": data_0206");
+        assert!(lines[6].starts_with("\t0xF0 0x90"));
+        assert!(lines[6].ends_with("# 0206  F090"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use egui_sdl2_gl::egui::{self, Align, Color32, CtxRef, RichText, Sense, Ui};
+use reimu::{
+    cpu::{Op, MEMORY_SIZE},
+    debugger::Debugger,
+    disasm::{self, Syntax},
+    Machine,
+};
+
+/// Instructions shown above the centre of the view.
+const ROWS_BEFORE: usize = 16;
+const ROWS: usize = 48;
+
+const BREAKPOINT_COLOR: Color32 = Color32::from_rgb(0xE0, 0x40, 0x40);
+const LABEL_COLOR: Color32 = Color32::from_rgb(0xE0, 0xC0, 0x60);
+const PC_BACKGROUND: Color32 = Color32::from_rgb(0x30, 0x50, 0x80);
+
+/// State of the disassembly window between frames.
+pub struct DisasmView {
+    syntax: Syntax,
+    labels: BTreeMap<usize, String>,
+    /// Keep the view centred on the PC; turned off when following a jump.
+    follow_pc: bool,
+    center: usize,
+    /// Scroll the centre row into view on the next frame.
+    recentre: bool,
+    last_pc: Option<usize>,
+}
+
+impl DisasmView {
+    pub fn new(labels: BTreeMap<usize, String>) -> Self {
+        Self {
+            syntax: Syntax::default(),
+            labels,
+            follow_pc: true,
+            center: 0,
+            recentre: false,
+            last_pc: None,
+        }
+    }
+
+    pub fn show(&mut self, ctx: &CtxRef, machine: &Machine, debugger: &mut Debugger) {
+        egui::Window::new("Disassembly")
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                self.ui_controls(ui);
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(480.0)
+                    .show(ui, |ui| self.ui_rows(ui, machine, debugger));
+            });
+    }
+
+    fn go_to(&mut self, addr: usize) {
+        self.follow_pc = false;
+        self.center = addr;
+        self.recentre = true;
+    }
+
+    fn ui_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.follow_pc, "Follow PC");
+            if ui.small_button("⬆").clicked() {
+                self.go_to(self.center.saturating_sub(ROWS));
+            }
+            if ui.small_button("⬇").clicked() {
+                self.go_to((self.center + ROWS).min(MEMORY_SIZE - 2));
+            }
+            for syntax in Syntax::ALL {
+                ui.radio_value(&mut self.syntax, syntax, syntax.name());
+            }
+        });
+    }
+
+    fn ui_rows(&mut self, ui: &mut Ui, machine: &Machine, debugger: &mut Debugger) {
+        let cpu = machine.cpu();
+        let pc_moved = self.last_pc != Some(cpu.pc);
+        self.last_pc = Some(cpu.pc);
+        if self.follow_pc {
+            self.center = cpu.pc;
+        }
+        let mut scroll = (self.follow_pc && pc_moved) || std::mem::take(&mut self.recentre);
+
+        // Decoding from an even distance keeps the PC aligned with a row
+        let mut addr = self.center.saturating_sub(ROWS_BEFORE * 2);
+        for _ in 0..ROWS {
+            if addr + 1 >= MEMORY_SIZE {
+                break;
+            }
+            let op = cpu.op_at(addr);
+            if let Some(label) = self.labels.get(&addr) {
+                ui.label(
+                    RichText::new(format!("{}:", label))
+                        .monospace()
+                        .color(LABEL_COLOR),
+                );
+            }
+            let row = ui.horizontal(|ui| self.ui_row(ui, machine, debugger, addr, &op));
+            if scroll && addr >= self.center {
+                row.response.scroll_to_me(Align::Center);
+                scroll = false;
+            }
+            addr += op.size();
+        }
+    }
+
+    fn ui_row(
+        &mut self,
+        ui: &mut Ui,
+        machine: &Machine,
+        debugger: &mut Debugger,
+        addr: usize,
+        op: &Op,
+    ) {
+        let cpu = machine.cpu();
+
+        let gutter = if debugger.has_breakpoint(addr) {
+            RichText::new("●").color(BREAKPOINT_COLOR)
+        } else {
+            RichText::new("○").weak()
+        };
+        let gutter = ui
+            .add(egui::Label::new(gutter.monospace()).sense(Sense::click()))
+            .on_hover_text("Toggle breakpoint");
+        if gutter.clicked() {
+            debugger.toggle_breakpoint(addr);
+        }
+
+        let mut address = RichText::new(format!("{:04X}", addr)).monospace();
+        if addr == cpu.pc {
+            address = address.background_color(PC_BACKGROUND).strong();
+        }
+        ui.label(address);
+
+        let bytes: String = cpu.memory[addr..(addr + op.size()).min(MEMORY_SIZE)]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        ui.label(RichText::new(format!("{:<8}", bytes)).monospace().weak());
+
+        // Code that has never been fetched is dimmed, as it may well be data
+        let text = disasm::mnemonic_with_labels(op, self.syntax, &self.labels);
+        let mut text = RichText::new(text).monospace();
+        if !debugger.executed(addr) {
+            text = text.weak();
+        }
+        match op.branch_target() {
+            Some(target) => {
+                let link = ui
+                    .add(egui::Label::new(text.underline()).sense(Sense::click()))
+                    .on_hover_text(format!("Go to {:04X}", target));
+                if link.clicked() {
+                    self.go_to(target);
+                }
+            }
+            None => {
+                ui.label(text);
+            }
+        }
+    }
+}
@@ -1,66 +1,233 @@
+use serde::{Deserialize, Serialize};
+
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
-const SCREEN_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+const MAX_SCREEN_SIZE: usize = HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT;
+
+pub const PLANE_COUNT: usize = 2;
+pub const COLOR_COUNT: usize = 1 << PLANE_COUNT;
+const ALL_PLANES: u8 = (1 << PLANE_COUNT) - 1;
 
+/// Screen buffer with XO-CHIP bitplanes.
+///
+/// Each pixel holds a colour index where bit N is set when the pixel is lit
+/// on plane N. Plain CHIP-8 programs only ever touch the first plane.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Gpu {
-    screen: [bool; SCREEN_SIZE],
+    screen: Vec<u8>,
+    hires: bool,
+    planes: u8,
 }
 
 impl Gpu {
     pub fn new() -> Self {
         Self {
-            screen: [false; SCREEN_SIZE],
+            screen: vec![0; MAX_SCREEN_SIZE],
+            hires: false,
+            planes: 1,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    /// Switches between the 64x32 and 128x64 modes, clearing the screen.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.screen.fill(0);
+    }
+
+    /// Colour indices for every pixel of the current resolution.
+    pub fn screen(&self) -> &[u8] {
+        &self.screen[..self.width() * self.height()]
+    }
+
+    /// Checks a deserialized screen, returning what is wrong with it.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.screen.len() != MAX_SCREEN_SIZE {
+            return Err(format!(
+                "screen has {} pixels, expected {}",
+                self.screen.len(),
+                MAX_SCREEN_SIZE
+            ));
+        }
+        if self.planes & !ALL_PLANES != 0 {
+            return Err(format!("invalid plane mask {:#04b}", self.planes));
+        }
+        if self.screen.iter().any(|p| p & !ALL_PLANES != 0) {
+            return Err("screen has pixels on unknown planes".to_string());
         }
+        Ok(())
     }
 
-    pub fn screen(&self) -> &[bool] {
-        &self.screen
+    /// Bitmask of the planes affected by drawing, clearing and scrolling.
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Selects the drawing planes (`FN01`).
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ALL_PLANES;
+    }
+
+    /// Number of sprite bytes `draw_sprite` consumes for a sprite of `size` bytes
+    /// per plane, given the currently selected planes.
+    pub fn sprite_len(&self, size: usize) -> usize {
+        size * self.planes.count_ones() as usize
     }
 
     pub fn clear(&mut self) {
-        self.screen.fill(false);
+        let keep = !self.planes;
+        self.screen.iter_mut().for_each(|p| *p &= keep);
     }
 
-    pub fn set(&mut self, x: usize, y: usize, value: bool) -> bool {
-        let adj_x = x % SCREEN_WIDTH;
-        let adj_y = y % SCREEN_HEIGHT;
-        let idx = adj_y * SCREEN_WIDTH + adj_x;
-        let current = self.screen[idx];
+    pub fn set(&mut self, x: usize, y: usize, plane: u8, value: bool) -> bool {
+        let width = self.width();
+        let adj_x = x % width;
+        let adj_y = y % self.height();
+        let idx = adj_y * width + adj_x;
+        let current = self.screen[idx] & plane != 0;
         let hit = current && value;
-        self.screen[idx] ^= value;
+        if value {
+            self.screen[idx] ^= plane;
+        }
+        hit
+    }
+
+    /// Draws an 8xN sprite to every selected plane. When more than one plane
+    /// is selected, `sprite` holds the data for each plane in turn.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let size = sprite.len() / self.planes.count_ones().max(1) as usize;
+        let mut hit = false;
+        for (plane, data) in self.selected_planes().zip(sprite.chunks(size.max(1))) {
+            let rows = data.iter().map(|byte| (*byte as u16) << 8);
+            hit |= self.draw_rows(x, y, plane, rows, clip);
+        }
         hit
     }
 
-    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+    /// Draws a SUPER-CHIP 16x16 sprite, stored as 16 big-endian row words,
+    /// to every selected plane.
+    pub fn draw_large_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
         let mut hit = false;
+        for (plane, data) in self.selected_planes().zip(sprite.chunks(32)) {
+            let rows = data
+                .chunks(2)
+                .map(|pair| ((pair[0] as u16) << 8) | pair.get(1).copied().unwrap_or(0) as u16);
+            hit |= self.draw_rows(x, y, plane, rows, clip);
+        }
+        hit
+    }
+
+    fn selected_planes(&self) -> impl Iterator<Item = u8> {
+        let planes = self.planes;
+        (0..PLANE_COUNT)
+            .map(|idx| 1 << idx)
+            .filter(move |plane| planes & plane != 0)
+    }
 
-        for (row, byte) in sprite.iter().enumerate() {
-            for col in 0..8 {
-                let mask = 1 << col;
-                let masked = byte & mask;
-                let value = masked == mask;
-                let adj_col = 7 - col;
-                hit |= self.set(x + adj_col, y + row, value);
+    fn draw_rows(
+        &mut self,
+        x: usize,
+        y: usize,
+        plane: u8,
+        rows: impl Iterator<Item = u16>,
+        clip: bool,
+    ) -> bool {
+        let mut hit = false;
+        let width = self.width();
+        let height = self.height();
+        let x = x % width;
+        let y = y % height;
+
+        for (row, bits) in rows.enumerate() {
+            if clip && y + row >= height {
+                break;
+            }
+            for col in 0..16 {
+                let mask = 0x8000 >> col;
+                let value = bits & mask == mask;
+                if !value || (clip && x + col >= width) {
+                    continue;
+                }
+                hit |= self.set(x + col, y + row, plane, value);
             }
         }
 
         hit
     }
 
-    #[allow(dead_code)]
-    pub fn dump(&self) {
+    /// Scrolls the selected planes vertically; positive `rows` scroll down
+    /// (`00CN`), negative scroll up (`00DN`).
+    pub fn scroll_vertical(&mut self, rows: isize) {
+        let width = self.width();
+        let height = self.height() as isize;
+        self.scroll_by(|x, y| (x as isize, y as isize - rows), width, height);
+    }
+
+    /// Scrolls the selected planes horizontally; positive `cols` scroll right
+    /// (`00FB`), negative scroll left (`00FC`).
+    pub fn scroll_horizontal(&mut self, cols: isize) {
+        let width = self.width();
+        let height = self.height() as isize;
+        self.scroll_by(|x, y| (x as isize - cols, y as isize), width, height);
+    }
+
+    fn scroll_by(
+        &mut self,
+        source: impl Fn(usize, usize) -> (isize, isize),
+        width: usize,
+        height: isize,
+    ) {
+        let planes = self.planes;
+        let old = self.screen.clone();
+        for y in 0..height as usize {
+            for x in 0..width {
+                let (src_x, src_y) = source(x, y);
+                let moved = if (0..width as isize).contains(&src_x) && (0..height).contains(&src_y)
+                {
+                    old[src_y as usize * width + src_x as usize] & planes
+                } else {
+                    0
+                };
+                let idx = y * width + x;
+                self.screen[idx] = (old[idx] & !planes) | moved;
+            }
+        }
+    }
+
+    /// Renders the screen as text, using half-block characters so each
+    /// character covers two rows of pixels.
+    pub fn render_text(&self) -> String {
         const FULL: char = '█';
         const UPPER_HALF: char = '▀'; // '🮑';
         const LOWER_HALF: char = '▄'; // '🮒';
         const EMPTY: char = ' '; // '🮐';
 
-        for top_row in (0..SCREEN_HEIGHT).step_by(2) {
+        let width = self.width();
+        let mut text = String::new();
+        for top_row in (0..self.height()).step_by(2) {
             let bot_row = top_row + 1;
-            for col in 0..SCREEN_WIDTH {
-                let top_idx = top_row * SCREEN_WIDTH + col;
-                let bot_idx = bot_row * SCREEN_WIDTH + col;
-                let top_val = self.screen[top_idx];
-                let bot_val = self.screen[bot_idx];
+            for col in 0..width {
+                let top_idx = top_row * width + col;
+                let bot_idx = bot_row * width + col;
+                let top_val = self.screen[top_idx] != 0;
+                let bot_val = self.screen[bot_idx] != 0;
 
                 let chr = if top_val && bot_val {
                     FULL
@@ -72,9 +239,81 @@ impl Gpu {
                     EMPTY
                 };
 
-                print!("{}", chr);
+                text.push(chr);
             }
-            println!();
+            text.push('\n');
         }
+        text
+    }
+
+    pub fn dump(&self) {
+        print!("{}", self.render_text());
+    }
+}
+
+impl Default for Gpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_resolution() {
+        let mut gpu = Gpu::new();
+        assert_eq!(gpu.screen().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        gpu.set_hires(true);
+        assert_eq!(gpu.screen().len(), HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT);
+    }
+
+    #[test]
+    fn scrolls_down() {
+        let mut gpu = Gpu::new();
+        gpu.set(3, 0, 1, true);
+        gpu.scroll_vertical(2);
+        assert_eq!(gpu.screen()[3], 0);
+        assert_eq!(gpu.screen()[2 * SCREEN_WIDTH + 3], 1);
+        gpu.scroll_vertical(-1);
+        assert_eq!(gpu.screen()[SCREEN_WIDTH + 3], 1);
+    }
+
+    #[test]
+    fn scrolls_left_and_right() {
+        let mut gpu = Gpu::new();
+        gpu.set(10, 1, 1, true);
+        gpu.scroll_horizontal(4);
+        assert_eq!(gpu.screen()[SCREEN_WIDTH + 14], 1);
+        gpu.scroll_horizontal(-4);
+        assert_eq!(gpu.screen()[SCREEN_WIDTH + 10], 1);
+        assert_eq!(gpu.screen()[SCREEN_WIDTH + 14], 0);
+    }
+
+    #[test]
+    fn draws_large_sprite() {
+        let mut gpu = Gpu::new();
+        let sprite = [0xFF_u8; 32];
+        let hit = gpu.draw_large_sprite(0, 0, &sprite, true);
+        assert!(!hit);
+        assert_eq!(gpu.screen()[15], 1);
+        assert_eq!(gpu.screen()[16], 0);
+        assert!(gpu.draw_large_sprite(0, 0, &sprite, true));
+    }
+
+    #[test]
+    fn draws_to_selected_planes() {
+        let mut gpu = Gpu::new();
+        gpu.select_planes(3);
+        assert_eq!(gpu.sprite_len(1), 2);
+        gpu.draw_sprite(0, 0, &[0x80, 0xC0], false);
+        assert_eq!(gpu.screen()[0], 3);
+        assert_eq!(gpu.screen()[1], 2);
+
+        gpu.select_planes(2);
+        gpu.clear();
+        assert_eq!(gpu.screen()[0], 1);
+        assert_eq!(gpu.screen()[1], 0);
     }
 }
@@ -0,0 +1,327 @@
+//! Running a machine without a frontend, for scripts and automated tests.
+//!
+//! A [`Runner`] drives a [`Machine`] frame by frame, pressing keys from a
+//! [`KeyScript`] and stopping when the program exits, faults or reaches a
+//! frame or cycle limit.
+
+use std::{fmt, str::FromStr};
+
+use crate::{cpu::CpuError, Machine};
+
+/// A key held down from the start of one frame until the start of another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyPress {
+    pub key: u8,
+    /// First frame the key is held during.
+    pub start: u64,
+    /// Frame the key is released at, i.e. the first frame it is up again.
+    pub end: u64,
+}
+
+impl KeyPress {
+    fn held(&self, frame: u64) -> bool {
+        (self.start..self.end).contains(&frame)
+    }
+}
+
+/// Key presses to make at given frames.
+///
+/// Scripts are whitespace or comma separated presses of the form
+/// `FRAME:KEY`, holding the hex `KEY` for that one frame, or
+/// `START-END:KEY`, holding it from frame `START` until frame `END`. Frames
+/// count from 0 and `#` starts a comment that runs to the end of the line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    presses: Vec<KeyPress>,
+}
+
+impl KeyScript {
+    pub fn new(presses: Vec<KeyPress>) -> Self {
+        Self { presses }
+    }
+
+    pub fn presses(&self) -> &[KeyPress] {
+        &self.presses
+    }
+
+    /// Whether `key` is held during `frame`.
+    pub fn held(&self, key: u8, frame: u64) -> bool {
+        self.presses
+            .iter()
+            .any(|press| press.key == key && press.held(frame))
+    }
+}
+
+impl FromStr for KeyScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut presses = Vec::new();
+        let items = s
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|item| !item.is_empty());
+        for item in items {
+            let invalid = || {
+                format!(
+                    "Invalid key press '{}' (expected FRAME:KEY or START-END:KEY)",
+                    item
+                )
+            };
+            let (frames, key) = item.split_once(':').ok_or_else(invalid)?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or_else(invalid)?;
+            let (start, end) = match frames.split_once('-') {
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let frame: u64 = frames.parse().map_err(|_| invalid())?;
+                    (frame, frame + 1)
+                }
+            };
+            if end <= start {
+                return Err(format!("Key press '{}' ends before it starts", item));
+            }
+            presses.push(KeyPress { key, start, end });
+        }
+        Ok(Self { presses })
+    }
+}
+
+/// When to stop a program that doesn't exit by itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Frames(u64),
+    /// Instructions executed, including those spent waiting for the display.
+    Cycles(u64),
+}
+
+/// Why a [`Runner`] stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program executed `00FD` or `0FNN`, with this exit code.
+    Exited(u8),
+    Limit(Limit),
+    Fault(CpuError),
+}
+
+impl StopReason {
+    /// The process exit code to report: the program's own code when it
+    /// exited, 0 when it ran until the limit and 1 when it faulted.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StopReason::Exited(code) => *code as i32,
+            StopReason::Limit(_) => 0,
+            StopReason::Fault(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Exited(code) => write!(f, "Exited with code {}", code),
+            StopReason::Limit(Limit::Frames(frames)) => {
+                write!(f, "Reached the limit of {} frames", frames)
+            }
+            StopReason::Limit(Limit::Cycles(cycles)) => {
+                write!(f, "Reached the limit of {} cycles", cycles)
+            }
+            StopReason::Fault(err) => write!(f, "CPU fault: {}", err),
+        }
+    }
+}
+
+/// Runs a machine frame by frame without a frontend.
+#[derive(Clone, Debug, Default)]
+pub struct Runner {
+    script: KeyScript,
+    limit: Option<Limit>,
+    frames: u64,
+    cycles: u64,
+}
+
+impl Runner {
+    /// Creates a runner that presses keys from `script` and stops at
+    /// `limit`, or only when the program exits or faults without one.
+    pub fn new(script: KeyScript, limit: Option<Limit>) -> Self {
+        Self {
+            script,
+            limit,
+            frames: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Frames completed so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Runs a single frame, returning why the run is over once it is. A
+    /// cycle limit can end the run partway through a frame.
+    ///
+    /// Scripted runs treat `0FNN` as `DBG:EXIT`, so test ROMs can report a
+    /// result through the exit code.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Option<StopReason> {
+        machine.set_debug_exit(true);
+        if let Some(reason) = self.check(machine) {
+            return Some(reason);
+        }
+        for key in 0..16 {
+            machine.set_key(key, self.script.held(key, self.frames));
+        }
+        for _ in 0..machine.instructions_per_frame() {
+            if let Err(err) = machine.step() {
+                return Some(StopReason::Fault(err));
+            }
+            self.cycles += 1;
+            if let Some(reason) = self.check(machine) {
+                return Some(reason);
+            }
+        }
+        machine.tick_timers();
+        self.frames += 1;
+        self.check(machine)
+    }
+
+    /// Runs frames until the program exits, faults or reaches the limit.
+    pub fn run(&mut self, machine: &mut Machine) -> StopReason {
+        loop {
+            if let Some(reason) = self.run_frame(machine) {
+                return reason;
+            }
+        }
+    }
+
+    fn check(&self, machine: &Machine) -> Option<StopReason> {
+        if !machine.running() {
+            return Some(StopReason::Exited(machine.exit_code().unwrap_or(0)));
+        }
+        match self.limit {
+            Some(Limit::Frames(frames)) if self.frames >= frames => {
+                Some(StopReason::Limit(Limit::Frames(frames)))
+            }
+            Some(Limit::Cycles(cycles)) if self.cycles >= cycles => {
+                Some(StopReason::Limit(Limit::Cycles(cycles)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Summarises the machine state after a run: why it stopped, then the
+/// registers, `I`, `PC`, stack pointer and timers.
+pub fn report(machine: &Machine, runner: &Runner, reason: &StopReason) -> String {
+    let cpu = machine.cpu();
+    let registers: Vec<String> = (0..16)
+        .map(|idx| format!("V{:X}={:02X}", idx, cpu.register(idx)))
+        .collect();
+    format!(
+        "{} after {} frames ({} cycles)\n{}\nI={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}\n",
+        reason,
+        runner.frames(),
+        runner.cycles(),
+        registers.join(" "),
+        cpu.address_register,
+        cpu.pc,
+        cpu.sp,
+        cpu.delay_timer,
+        cpu.sound_timer
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_scripts() {
+        let script: KeyScript = "10:5, 20-30:a # hold A\n 40:F".parse().unwrap();
+        assert_eq!(
+            script.presses(),
+            &[
+                KeyPress {
+                    key: 5,
+                    start: 10,
+                    end: 11,
+                },
+                KeyPress {
+                    key: 0xA,
+                    start: 20,
+                    end: 30,
+                },
+                KeyPress {
+                    key: 0xF,
+                    start: 40,
+                    end: 41,
+                },
+            ]
+        );
+        assert!(script.held(0xA, 29));
+        assert!(!script.held(0xA, 30));
+
+        assert!("10".parse::<KeyScript>().is_err());
+        assert!("10:G".parse::<KeyScript>().is_err());
+        assert!("10:10".parse::<KeyScript>().is_err());
+        assert!("30-20:1".parse::<KeyScript>().is_err());
+        assert_eq!("".parse(), Ok(KeyScript::default()));
+    }
+
+    #[test]
+    fn stops_at_exit_with_its_code() {
+        let mut machine = Machine::default();
+        // V0 += 1; DBG:EXIT(7)
+        machine.load_rom(&[0x70, 0x01, 0x0F, 0x07]).unwrap();
+        let mut runner = Runner::new(KeyScript::default(), None);
+        assert_eq!(runner.run(&mut machine), StopReason::Exited(7));
+        assert_eq!(runner.cycles(), 2);
+        assert_eq!(runner.frames(), 0);
+        assert_eq!(StopReason::Exited(7).exit_code(), 7);
+    }
+
+    #[test]
+    fn stops_at_limits() {
+        let mut machine = Machine::default();
+        // loop: V0 += 1; jump loop
+        machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut runner = Runner::new(KeyScript::default(), Some(Limit::Frames(3)));
+        assert_eq!(
+            runner.run(&mut machine),
+            StopReason::Limit(Limit::Frames(3))
+        );
+        assert_eq!(runner.cycles(), 3 * machine.instructions_per_frame() as u64);
+
+        machine.reset().unwrap();
+        let mut runner = Runner::new(KeyScript::default(), Some(Limit::Cycles(5)));
+        assert_eq!(
+            runner.run(&mut machine),
+            StopReason::Limit(Limit::Cycles(5))
+        );
+        assert_eq!(machine.cpu().register(0), 3);
+        assert_eq!(runner.frames(), 0);
+    }
+
+    #[test]
+    fn presses_scripted_keys() {
+        let mut machine = Machine::default();
+        // V0 := key; DBG:EXIT(0)
+        machine.load_rom(&[0xF0, 0x0A, 0x0F, 0x00]).unwrap();
+        let script = "2-4:B".parse().unwrap();
+        let mut runner = Runner::new(script, Some(Limit::Frames(10)));
+        assert_eq!(runner.run(&mut machine), StopReason::Exited(0));
+        assert_eq!(machine.cpu().register(0), 0xB);
+        // Exits partway through the frame the key goes down
+        assert_eq!(runner.frames(), 2);
+    }
+}
@@ -0,0 +1,548 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use sdl2::keyboard::Keycode;
+use serde::{Deserialize, Serialize};
+
+use crate::controller::{self, Input};
+
+/// SDL key names mapped to CHIP-8 keys.
+pub type Bindings = BTreeMap<String, u8>;
+
+/// The CHIP-8 keys as laid out on the COSMAC VIP keypad, row by row.
+pub const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Host key names laid out like [`KEYPAD_LAYOUT`].
+const QWERTY_LAYOUT: [[&str; 4]; 4] = [
+    ["1", "2", "3", "4"],
+    ["Q", "W", "E", "R"],
+    ["A", "S", "D", "F"],
+    ["Z", "X", "C", "V"],
+];
+
+const NUMPAD_LAYOUT: [[&str; 4]; 4] = [
+    ["Keypad 7", "Keypad 8", "Keypad 9", "Keypad /"],
+    ["Keypad 4", "Keypad 5", "Keypad 6", "Keypad *"],
+    ["Keypad 1", "Keypad 2", "Keypad 3", "Keypad -"],
+    ["Keypad 0", "Keypad .", "Keypad Enter", "Keypad +"],
+];
+
+/// Arrow keys on the keys most games use for directions.
+const ARROWS: [(&str, u8); 4] = [("Up", 0x2), ("Left", 0x4), ("Right", 0x6), ("Down", 0x8)];
+
+/// Built-in keymaps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// The keypad laid out on the left side of a QWERTY keyboard, 1-4 to Z-V.
+    #[default]
+    Qwerty,
+    /// The keypad laid out on the numeric keypad, so that Keypad 8 is the
+    /// CHIP-8 key 2 above Keypad 5.
+    Numpad,
+    /// The QWERTY layout plus the arrow keys on 2, 4, 6 and 8.
+    Arrows,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Preset::Qwerty, Preset::Numpad, Preset::Arrows];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Qwerty => "qwerty",
+            Preset::Numpad => "numpad",
+            Preset::Arrows => "arrows",
+        }
+    }
+
+    pub fn bindings(&self) -> Bindings {
+        let layout = |names: &[[&str; 4]; 4]| -> Bindings {
+            names
+                .iter()
+                .flatten()
+                .zip(KEYPAD_LAYOUT.iter().flatten())
+                .map(|(name, key)| (name.to_string(), *key))
+                .collect()
+        };
+        match self {
+            Preset::Qwerty => layout(&QWERTY_LAYOUT),
+            Preset::Numpad => layout(&NUMPAD_LAYOUT),
+            Preset::Arrows => {
+                let mut bindings = layout(&QWERTY_LAYOUT);
+                bindings.extend(ARROWS.iter().map(|(name, key)| (name.to_string(), *key)));
+                bindings
+            }
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Controller inputs for up, down, left and right: the D-pad and the left
+/// stick.
+const CONTROLLER_DIRECTIONS: [[&str; 2]; 4] = [
+    ["dpup", "lefty-"],
+    ["dpdown", "lefty+"],
+    ["dpleft", "leftx-"],
+    ["dpright", "leftx+"],
+];
+
+/// Built-in controller layouts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControllerPreset {
+    /// Directions on 2, 8, 4 and 6, A, B, X and Y on 5, 0, 7 and 9, the
+    /// shoulder buttons on A and B, and back and start on E and F.
+    #[default]
+    Directions,
+    /// Up and down on 1 and 4, and A and B on 7 and A: the left column of
+    /// the keypad, for the left player of games like Pong.
+    Left,
+    /// Up and down on C and D, and A and B on E and F: the right column of
+    /// the keypad, for the right player.
+    Right,
+}
+
+impl ControllerPreset {
+    pub fn bindings(&self) -> Bindings {
+        let (directions, buttons): (&[u8], &[(&str, u8)]) = match self {
+            ControllerPreset::Directions => (
+                &[0x2, 0x8, 0x4, 0x6],
+                &[
+                    ("a", 0x5),
+                    ("b", 0x0),
+                    ("x", 0x7),
+                    ("y", 0x9),
+                    ("leftshoulder", 0xA),
+                    ("rightshoulder", 0xB),
+                    ("back", 0xE),
+                    ("start", 0xF),
+                ],
+            ),
+            ControllerPreset::Left => (&[0x1, 0x4], &[("a", 0x7), ("b", 0xA)]),
+            ControllerPreset::Right => (&[0xC, 0xD], &[("a", 0xE), ("b", 0xF)]),
+        };
+        let mut bindings = Bindings::new();
+        for (inputs, key) in CONTROLLER_DIRECTIONS.iter().zip(directions) {
+            for input in inputs {
+                bindings.insert(input.to_string(), *key);
+            }
+        }
+        for (input, key) in buttons {
+            bindings.insert(input.to_string(), *key);
+        }
+        bindings
+    }
+}
+
+/// The controller of one player. Keys are controller inputs: buttons by
+/// their SDL mapping names (`a`, `dpup`, `start`, ...), stick directions as
+/// `leftx-` or `righty+`, and `lefttrigger` and `righttrigger`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ControllerLayer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<ControllerPreset>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: Bindings,
+    /// How far a stick or trigger has to move to press its key, between 0
+    /// and 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadzone: Option<f32>,
+}
+
+impl ControllerLayer {
+    /// Like [`Layer::bindings`], falling back to the default preset.
+    pub fn bindings(&self) -> Bindings {
+        match self.preset {
+            Some(preset) => {
+                let mut bindings = preset.bindings();
+                bindings.extend(self.keys.clone());
+                bindings
+            }
+            None if self.keys.is_empty() => ControllerPreset::default().bindings(),
+            None => self.keys.clone(),
+        }
+    }
+
+    pub fn deadzone(&self) -> f32 {
+        self.deadzone.unwrap_or(controller::DEFAULT_DEADZONE)
+    }
+
+    fn validate(&self) -> Result<()> {
+        validate_keys(&self.keys)?;
+        for name in self.keys.keys() {
+            name.parse::<Input>().map_err(|err| anyhow!(err))?;
+        }
+        if !(0.0..1.0).contains(&self.deadzone()) {
+            bail!("Deadzone {} is out of range, expected 0-1", self.deadzone());
+        }
+        Ok(())
+    }
+}
+
+fn validate_keys(bindings: &Bindings) -> Result<()> {
+    for (name, key) in bindings.iter() {
+        if *key > 0xF {
+            bail!("Key '{}' is mapped to {:#X}, expected 0x0-0xF", name, key);
+        }
+    }
+    Ok(())
+}
+
+/// One keymap in a [`KeymapConfig`], either the global one or a ROM
+/// override.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    /// For ROM overrides, the ROM's file name as a reminder of what the hash
+    /// belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<Preset>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: Bindings,
+    /// One entry per player, in the order the controllers were connected.
+    #[serde(default, rename = "controller", skip_serializing_if = "Vec::is_empty")]
+    pub controllers: Vec<ControllerLayer>,
+}
+
+impl Layer {
+    /// Replaces the keyboard bindings, keeping the controllers.
+    fn set_keys(&mut self, bindings: Bindings) {
+        self.preset = None;
+        self.keys = bindings;
+    }
+
+    /// With a preset, the preset's bindings plus `keys`. Without one, exactly
+    /// `keys`, or `inherited` if there are none.
+    fn bindings(&self, inherited: &Bindings) -> Bindings {
+        match self.preset {
+            Some(preset) => {
+                let mut bindings = preset.bindings();
+                bindings.extend(self.keys.clone());
+                bindings
+            }
+            None if self.keys.is_empty() => inherited.clone(),
+            None => self.keys.clone(),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        validate_keys(&self.keys)?;
+        for (idx, controller) in self.controllers.iter().enumerate() {
+            controller
+                .validate()
+                .with_context(|| format!("Invalid controller for player {}", idx + 1))?;
+        }
+        Ok(())
+    }
+}
+
+/// On-disk keymap configuration: a global keymap, and overrides for
+/// individual ROMs keyed by the SHA-1 of the ROM.
+///
+/// ```toml
+/// preset = "qwerty"
+///
+/// [keys]
+/// Space = 0x5
+///
+/// [roms.0123456789abcdef0123456789abcdef01234567]
+/// name = "pong.ch8"
+/// preset = "arrows"
+///
+/// [[roms.0123456789abcdef0123456789abcdef01234567.controller]]
+/// preset = "left"
+///
+/// [[roms.0123456789abcdef0123456789abcdef01234567.controller]]
+/// preset = "right"
+/// ```
+///
+/// Key names are SDL key names. A keymap with a preset adds its `keys` to the
+/// preset's; one with only `keys` maps exactly those. Without either, the
+/// global keymap is the `qwerty` preset and ROMs use the global keymap. ROMs
+/// without controllers of their own use the global ones, and players without
+/// a controller entry get the `directions` preset.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeymapConfig {
+    #[serde(flatten)]
+    global: Layer,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    roms: BTreeMap<String, Layer>,
+}
+
+impl KeymapConfig {
+    /// Loads the configuration at `path`, resolving every key name.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read keymap '{}'", path.display()))?;
+        let config = Self::parse(&contents)
+            .with_context(|| format!("Invalid keymap '{}'", path.display()))?;
+        Keymap::resolve(&config.global_bindings())
+            .with_context(|| format!("Invalid keymap '{}'", path.display()))?;
+        for hash in config.roms.keys() {
+            Keymap::resolve(&config.bindings(hash)).with_context(|| {
+                format!("Invalid keymap for ROM {} in '{}'", hash, path.display())
+            })?;
+        }
+        Ok(config)
+    }
+
+    /// Parses a configuration without resolving the key names.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)?;
+        config.global.validate()?;
+        for (hash, layer) in config.roms.iter() {
+            layer
+                .validate()
+                .with_context(|| format!("Invalid keymap for ROM {}", hash))?;
+        }
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        // Going through a `Value` puts plain values before tables, as TOML
+        // requires
+        Ok(toml::to_string_pretty(&toml::Value::try_from(self)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+        }
+        fs::write(path, self.to_toml()?)
+            .with_context(|| format!("Failed to write keymap '{}'", path.display()))
+    }
+
+    fn global_bindings(&self) -> Bindings {
+        self.global.bindings(&Preset::default().bindings())
+    }
+
+    /// The bindings for the ROM with the given hash.
+    pub fn bindings(&self, rom_hash: &str) -> Bindings {
+        let global = self.global_bindings();
+        match self.roms.get(rom_hash) {
+            Some(layer) => layer.bindings(&global),
+            None => global,
+        }
+    }
+
+    /// The keymap for the ROM with the given hash.
+    pub fn keymap(&self, rom_hash: &str) -> Result<Keymap> {
+        Keymap::resolve(&self.bindings(rom_hash))
+    }
+
+    /// The controllers for the ROM with the given hash, by player.
+    pub fn controllers(&self, rom_hash: &str) -> &[ControllerLayer] {
+        match self.roms.get(rom_hash) {
+            Some(layer) if !layer.controllers.is_empty() => &layer.controllers,
+            _ => &self.global.controllers,
+        }
+    }
+
+    pub fn has_override(&self, rom_hash: &str) -> bool {
+        self.roms.contains_key(rom_hash)
+    }
+
+    pub fn set_global(&mut self, bindings: Bindings) {
+        self.global.set_keys(bindings);
+    }
+
+    /// Overrides the keymap of a ROM, `name` being its file name.
+    pub fn set_override(&mut self, rom_hash: &str, name: Option<String>, bindings: Bindings) {
+        let layer = self.roms.entry(rom_hash.to_string()).or_default();
+        layer.name = name;
+        layer.set_keys(bindings);
+    }
+
+    pub fn remove_override(&mut self, rom_hash: &str) {
+        self.roms.remove(rom_hash);
+    }
+}
+
+/// Where the keymap configuration is kept when none is given on the command
+/// line: `reimu/keymap.toml` in the user's configuration directory.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("reimu").join("keymap.toml"))
+}
+
+/// Mapping from host keyboard keys to the 16 CHIP-8 keys.
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    keys: HashMap<Keycode, u8>,
+}
+
+impl Keymap {
+    /// Looks up the SDL key of every binding.
+    pub fn resolve(bindings: &Bindings) -> Result<Self> {
+        let mut keys = HashMap::new();
+        for (name, key) in bindings.iter() {
+            let keycode =
+                Keycode::from_name(name).ok_or_else(|| anyhow!("Unknown key name '{}'", name))?;
+            keys.insert(keycode, *key);
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn from_preset(preset: Preset) -> Self {
+        Self::resolve(&preset.bindings()).expect("Preset key names are valid")
+    }
+
+    pub fn bindings(&self) -> Bindings {
+        self.keys
+            .iter()
+            .map(|(keycode, key)| (keycode.name(), *key))
+            .collect()
+    }
+
+    pub fn get(&self, keycode: &Keycode) -> Option<&u8> {
+        self.keys.get(keycode)
+    }
+
+    /// The host keys bound to a CHIP-8 key, sorted by name.
+    pub fn keys_for(&self, key: u8) -> Vec<Keycode> {
+        let mut keycodes: Vec<Keycode> = self
+            .keys
+            .iter()
+            .filter(|(_, bound)| **bound == key)
+            .map(|(keycode, _)| *keycode)
+            .collect();
+        keycodes.sort_by_key(|keycode| keycode.name());
+        keycodes
+    }
+
+    /// Binds a host key to a CHIP-8 key in place of its current binding.
+    pub fn bind(&mut self, keycode: Keycode, key: u8) {
+        self.keys.insert(keycode, key);
+    }
+
+    /// Removes every host key bound to a CHIP-8 key.
+    pub fn clear(&mut self, key: u8) {
+        self.keys.retain(|_, bound| *bound != key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn parses_bindings() {
+        let config = KeymapConfig::parse("[keys]\nUp = 0x2\nSpace = 5\n").unwrap();
+        let bindings = config.bindings(HASH);
+        assert_eq!(bindings.get("Up"), Some(&0x2));
+        assert_eq!(bindings.get("Space"), Some(&0x5));
+        assert_eq!(bindings.get("Q"), None);
+    }
+
+    #[test]
+    fn rejects_out_of_range_key() {
+        assert!(KeymapConfig::parse("[keys]\nQ = 0x10\n").is_err());
+        let rom = format!("[roms.{}.keys]\nQ = 0x10\n", HASH);
+        assert!(KeymapConfig::parse(&rom).is_err());
+    }
+
+    #[test]
+    fn presets_cover_every_key() {
+        for preset in Preset::ALL {
+            let bindings = preset.bindings();
+            for key in 0..16 {
+                assert!(bindings.values().any(|bound| *bound == key), "{}", preset);
+            }
+        }
+        let arrows = Preset::Arrows.bindings();
+        assert_eq!(arrows.get("Up"), Some(&0x2));
+        assert_eq!(arrows.get("Q"), Some(&0x4));
+        assert_eq!(Preset::Numpad.bindings().get("Keypad 8"), Some(&0x2));
+    }
+
+    #[test]
+    fn defaults_to_qwerty() {
+        let config = KeymapConfig::parse("").unwrap();
+        assert_eq!(config.bindings(HASH), Preset::Qwerty.bindings());
+    }
+
+    #[test]
+    fn roms_override_the_global_keymap() {
+        let contents = format!(
+            "preset = \"numpad\"\n\n[roms.{}]\npreset = \"qwerty\"\n\n[roms.{}.keys]\nSpace = 5\n",
+            HASH, HASH
+        );
+        let config = KeymapConfig::parse(&contents).unwrap();
+        assert_eq!(config.bindings("other"), Preset::Numpad.bindings());
+        let bindings = config.bindings(HASH);
+        assert_eq!(bindings.get("Space"), Some(&0x5));
+        assert_eq!(bindings.get("Q"), Some(&0x4));
+        assert_eq!(bindings.get("Keypad 8"), None);
+    }
+
+    #[test]
+    fn roms_override_the_global_controllers() {
+        let contents = format!(
+            "[[controller]]\npreset = \"right\"\n\n\
+             [[roms.{}.controller]]\npreset = \"left\"\n\n\
+             [[roms.{}.controller]]\ndeadzone = 0.5\n[roms.{}.controller.keys]\na = 0xC\n",
+            HASH, HASH, HASH
+        );
+        let config = KeymapConfig::parse(&contents).unwrap();
+        let global = config.controllers("other");
+        assert_eq!(global.len(), 1);
+        assert_eq!(global[0].bindings().get("dpup"), Some(&0xC));
+
+        let players = config.controllers(HASH);
+        assert_eq!(players[0].bindings().get("lefty-"), Some(&0x1));
+        assert_eq!(players[1].bindings().len(), 1);
+        assert_eq!(players[1].deadzone(), 0.5);
+        assert_eq!(
+            ControllerLayer::default().bindings(),
+            ControllerPreset::Directions.bindings()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_controllers() {
+        assert!(KeymapConfig::parse("[[controller]]\n[controller.keys]\nz = 1\n").is_err());
+        assert!(KeymapConfig::parse("[[controller]]\n[controller.keys]\nleftx = 1\n").is_err());
+        assert!(KeymapConfig::parse("[[controller]]\ndeadzone = 1.5\n").is_err());
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let mut config = KeymapConfig::default();
+        config.global.controllers.push(ControllerLayer {
+            preset: Some(ControllerPreset::Left),
+            ..Default::default()
+        });
+        // Saving keyboard bindings keeps the controllers
+        config.set_global(Preset::Arrows.bindings());
+        assert_eq!(config.controllers(HASH).len(), 1);
+        let mut bindings = Bindings::new();
+        bindings.insert("Space".to_string(), 0x5);
+        config.set_override(HASH, Some("pong.ch8".to_string()), bindings.clone());
+
+        let parsed = KeymapConfig::parse(&config.to_toml().unwrap()).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.bindings(HASH), bindings);
+        assert!(parsed.has_override(HASH));
+    }
+}
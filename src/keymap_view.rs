@@ -0,0 +1,172 @@
+use std::path::PathBuf;
+
+use egui_sdl2_gl::egui::{self, CtxRef, RichText};
+use sdl2::keyboard::Keycode;
+
+use crate::keymap::{Keymap, KeymapConfig, Preset, KEYPAD_LAYOUT};
+
+/// Size of a key button in the rebinding dialog.
+const KEY_SIZE: [f32; 2] = [110.0, 36.0];
+
+/// The ROM the dialog saves overrides for.
+pub struct RomInfo {
+    pub hash: String,
+    pub name: Option<String>,
+}
+
+/// Dialog for rebinding the CHIP-8 keys and saving the keymap, either for
+/// every ROM or for the running one.
+pub struct KeymapView {
+    pub open: bool,
+    path: Option<PathBuf>,
+    rom: RomInfo,
+    /// The CHIP-8 key waiting for a host key press.
+    capturing: Option<u8>,
+    for_rom: bool,
+    message: Option<String>,
+}
+
+impl KeymapView {
+    /// `path` is where the configuration is saved.
+    pub fn new(path: Option<PathBuf>, rom: RomInfo, config: &KeymapConfig) -> Self {
+        let for_rom = config.has_override(&rom.hash);
+        Self {
+            open: false,
+            path,
+            rom,
+            capturing: None,
+            for_rom,
+            message: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.capturing = None;
+    }
+
+    /// Whether the next key press should go to [`capture`](Self::capture)
+    /// rather than to the emulator.
+    pub fn capturing(&self) -> bool {
+        self.capturing.is_some()
+    }
+
+    /// Binds the pressed host key to the CHIP-8 key being rebound. Escape
+    /// cancels.
+    pub fn capture(&mut self, keymap: &mut Keymap, keycode: Keycode) {
+        if let Some(key) = self.capturing.take() {
+            if keycode != Keycode::Escape {
+                keymap.bind(keycode, key);
+                self.message = None;
+            }
+        }
+    }
+
+    pub fn show(&mut self, ctx: &CtxRef, keymap: &mut Keymap, config: &mut KeymapConfig) {
+        let mut open = self.open;
+        egui::Window::new("Keymap")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(
+                    "Click a key and press the host key to bind to it. Right-click clears it.",
+                );
+                egui::Grid::new("keymap_keys").show(ui, |ui| {
+                    for row in KEYPAD_LAYOUT.iter() {
+                        for key in row.iter() {
+                            let text = if self.capturing == Some(*key) {
+                                format!("{:X}\nPress a key...", key)
+                            } else {
+                                let names: Vec<String> =
+                                    keymap.keys_for(*key).iter().map(|kc| kc.name()).collect();
+                                format!("{:X}\n{}", key, names.join(", "))
+                            };
+                            let response = ui.add_sized(KEY_SIZE, egui::Button::new(text));
+                            if response.clicked() {
+                                self.capturing = Some(*key);
+                            }
+                            if response.secondary_clicked() {
+                                keymap.clear(*key);
+                                self.capturing = None;
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Presets");
+                    for preset in Preset::ALL {
+                        if ui.button(preset.name()).clicked() {
+                            *keymap = Keymap::from_preset(preset);
+                            self.capturing = None;
+                        }
+                    }
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.for_rom, false, "All ROMs");
+                    let rom = match &self.rom.name {
+                        Some(name) => format!("Only {}", name),
+                        None => "Only this ROM".to_string(),
+                    };
+                    ui.radio_value(&mut self.for_rom, true, rom);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.message = Some(self.save(keymap, config));
+                    }
+                    if ui.button("Revert").clicked() {
+                        match config.keymap(&self.rom.hash) {
+                            Ok(saved) => *keymap = saved,
+                            Err(err) => self.message = Some(format!("{:#}", err)),
+                        }
+                        self.capturing = None;
+                    }
+                    let has_override = config.has_override(&self.rom.hash);
+                    if ui
+                        .add_enabled(has_override, egui::Button::new("Remove override"))
+                        .on_hover_text("Use the keymap for all ROMs with this ROM")
+                        .clicked()
+                    {
+                        config.remove_override(&self.rom.hash);
+                        self.for_rom = false;
+                        self.message = Some(self.write(config));
+                        if let Ok(global) = config.keymap(&self.rom.hash) {
+                            *keymap = global;
+                        }
+                    }
+                });
+                if let Some(message) = &self.message {
+                    ui.label(RichText::new(message).weak());
+                }
+            });
+        self.open = open;
+        if !self.open {
+            self.capturing = None;
+        }
+    }
+
+    /// Stores the keymap in the configuration and writes it out, returning a
+    /// message to show.
+    fn save(&self, keymap: &Keymap, config: &mut KeymapConfig) -> String {
+        if self.for_rom {
+            config.set_override(&self.rom.hash, self.rom.name.clone(), keymap.bindings());
+        } else {
+            config.set_global(keymap.bindings());
+        }
+        self.write(config)
+    }
+
+    fn write(&self, config: &KeymapConfig) -> String {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return "No configuration directory to save to".to_string(),
+        };
+        match config.save(path) {
+            Ok(()) => format!("Saved to {}", path.display()),
+            Err(err) => format!("{:#}", err),
+        }
+    }
+}
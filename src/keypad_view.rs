@@ -0,0 +1,90 @@
+use egui_sdl2_gl::egui::{self, CtxRef, RichText, Stroke};
+
+use crate::keymap::KEYPAD_LAYOUT;
+
+/// Size of a key on the on-screen keypad.
+const KEY_SIZE: [f32; 2] = [48.0, 48.0];
+
+/// The COSMAC VIP keypad on screen. Shows which keys the program sees held,
+/// and presses keys while they are held down with the mouse or a finger.
+/// Latched keys stay held until they are clicked again, so keys can be held
+/// while the keyboard is busy elsewhere.
+pub struct KeypadView {
+    pub open: bool,
+    /// Clicks toggle keys instead of holding them.
+    latch_mode: bool,
+    /// Keys held down with the pointer, bit N for key N.
+    held: u16,
+    latched: u16,
+}
+
+impl KeypadView {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            latch_mode: false,
+            held: 0,
+            latched: 0,
+        }
+    }
+
+    /// The keys pressed on the keypad, bit N for key N.
+    pub fn keys(&self) -> u16 {
+        if self.open {
+            self.held | self.latched
+        } else {
+            0
+        }
+    }
+
+    /// Shows the keypad with the keys in `pressed` lit, from every input.
+    pub fn show(&mut self, ctx: &CtxRef, pressed: u16) {
+        self.held = 0;
+        let mut open = self.open;
+        egui::Window::new("Keypad")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("keypad_keys").show(ui, |ui| {
+                    for row in KEYPAD_LAYOUT.iter() {
+                        for key in row.iter() {
+                            let mask = 1 << key;
+                            let mut button =
+                                egui::Button::new(RichText::new(format!("{:X}", key)).heading());
+                            if pressed & mask != 0 {
+                                button = button.fill(ui.visuals().selection.bg_fill);
+                            }
+                            if self.latched & mask != 0 {
+                                button = button
+                                    .stroke(Stroke::new(2.0, ui.visuals().selection.stroke.color));
+                            }
+                            let response = ui.add_sized(KEY_SIZE, button);
+                            // Right-click latches too, for mice outside latch mode
+                            if (self.latch_mode && response.clicked())
+                                || response.secondary_clicked()
+                            {
+                                self.latched ^= mask;
+                            } else if !self.latch_mode
+                                && response.is_pointer_button_down_on()
+                                && ui.input().pointer.primary_down()
+                            {
+                                self.held |= mask;
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.latch_mode, "Latch")
+                        .on_hover_text("Clicking a key keeps it held until it is clicked again");
+                    if ui
+                        .add_enabled(self.latched != 0, egui::Button::new("Release all"))
+                        .clicked()
+                    {
+                        self.latched = 0;
+                    }
+                });
+            });
+        self.open = open;
+    }
+}
@@ -0,0 +1,19 @@
+//! Core of the reimu CHIP-8 emulator, with SUPER-CHIP and XO-CHIP support.
+//!
+//! The core has no windowing, audio or input dependencies. Frontends drive a
+//! [`Machine`] by feeding it key state, running frames and reading back the
+//! framebuffer.
+
+pub mod asm;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod gpu;
+pub mod headless;
+pub mod machine;
+pub mod octo;
+pub mod rewind;
+pub mod savestate;
+pub mod trace;
+
+pub use machine::Machine;
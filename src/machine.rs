@@ -0,0 +1,289 @@
+use crate::{
+    cpu::{Cpu, CpuError, Quirks},
+    savestate::{self, SaveState, SaveStateError},
+};
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 15;
+
+/// A complete, owned CHIP-8 machine: the CPU with its display, keypad and
+/// timers, plus the loaded ROM so the machine can be reset.
+///
+/// This is the entry point for frontends and tests; it has no knowledge of
+/// windows, audio devices or input backends.
+pub struct Machine {
+    cpu: Cpu,
+    rom: Vec<u8>,
+    rom_hash: String,
+    instructions_per_frame: usize,
+    frame: u64,
+}
+
+impl Machine {
+    pub fn new(quirks: Quirks) -> Self {
+        Self {
+            cpu: Cpu::with_quirks(quirks),
+            rom: Vec::new(),
+            rom_hash: savestate::rom_hash(&[]),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            frame: 0,
+        }
+    }
+
+    /// Resets the machine and loads `rom` at the program start address.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), CpuError> {
+        self.cpu.reset();
+        self.cpu.load(rom)?;
+        self.rom = rom.to_vec();
+        self.rom_hash = savestate::rom_hash(rom);
+        self.frame = 0;
+        Ok(())
+    }
+
+    /// Resets the machine and reloads the current ROM.
+    pub fn reset(&mut self) -> Result<(), CpuError> {
+        self.cpu.reset();
+        self.frame = 0;
+        self.cpu.load(&self.rom)
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// SHA-1 of the loaded ROM, as a lowercase hex string.
+    pub fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    /// Captures the complete machine state.
+    pub fn save_state(&self) -> SaveState {
+        SaveState::new(self.rom_hash.clone(), self.cpu.clone())
+    }
+
+    /// Restores a state captured with [`Machine::save_state`], refusing states
+    /// that were made for a different ROM.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), SaveStateError> {
+        if state.header.rom_hash != self.rom_hash {
+            return Err(SaveStateError::RomMismatch {
+                expected: self.rom_hash.clone(),
+                found: state.header.rom_hash.clone(),
+            });
+        }
+        let quirks = self.cpu.quirks;
+        let record_trace = self.cpu.record_trace();
+        let debug_exit = self.cpu.debug_exit();
+        self.cpu = state.cpu.clone();
+        self.cpu.quirks = quirks;
+        self.cpu.set_record_trace(record_trace);
+        self.cpu.set_debug_exit(debug_exit);
+        self.cpu.redraw = true;
+        Ok(())
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, ipf: usize) {
+        self.instructions_per_frame = ipf.max(1);
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        self.cpu.step()
+    }
+
+    /// Emulates one 60 Hz frame: runs the configured number of instructions,
+    /// then ticks the timers once.
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        for _ in 0..self.instructions_per_frame {
+            self.cpu.step()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Ticks the timers once, ending a frame that was stepped through
+    /// instruction by instruction.
+    pub fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+        self.frame += 1;
+        tracing::debug!(
+            frame = self.frame,
+            pc = %format_args!("{:04X}", self.cpu.pc),
+            delay_timer = self.cpu.delay_timer,
+            sound_timer = self.cpu.sound_timer,
+            "frame"
+        );
+    }
+
+    /// Number of frames completed since the ROM was loaded or reset.
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    /// Whether the program is still running, i.e. it hasn't executed an exit
+    /// instruction.
+    pub fn running(&self) -> bool {
+        self.cpu.running()
+    }
+
+    /// The code the program exited with, once it has stopped running.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.cpu.exit_code()
+    }
+
+    /// Colour indices of every pixel, row by row, at the current resolution.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.screen()
+    }
+
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.cpu.screen_size()
+    }
+
+    /// Returns whether the screen changed since the last call.
+    pub fn take_redraw(&mut self) -> bool {
+        std::mem::take(&mut self.cpu.redraw)
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.cpu.set_key(key, pressed);
+    }
+
+    /// Sets every key at once, bit N being set while key N is held.
+    pub fn set_keys(&mut self, keys: u16) {
+        self.cpu.set_keys(keys);
+    }
+
+    /// Enables `0FNN` as `DBG:EXIT`; see [`Cpu::set_debug_exit`].
+    pub fn set_debug_exit(&mut self, enabled: bool) {
+        self.cpu.set_debug_exit(enabled);
+    }
+
+    /// Whether the sound timer is running and a tone should be playing.
+    pub fn audio_active(&self) -> bool {
+        self.cpu.sound_timer > 0
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_frame() {
+        let mut machine = Machine::default();
+        // V0 := 5; delay := V0; loop: jump loop
+        machine
+            .load_rom(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04])
+            .unwrap();
+        machine.run_frame().unwrap();
+        assert_eq!(machine.cpu().register(0), 5);
+        assert_eq!(machine.cpu().delay_timer, 4);
+    }
+
+    #[test]
+    fn reports_sound() {
+        let mut machine = Machine::default();
+        // V0 := 2; sound := V0
+        machine.load_rom(&[0x60, 0x02, 0xF0, 0x18]).unwrap();
+        machine.step().unwrap();
+        assert!(!machine.audio_active());
+        machine.step().unwrap();
+        assert!(machine.audio_active());
+    }
+
+    #[test]
+    fn restores_state() {
+        let mut machine = Machine::default();
+        machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        machine.step().unwrap();
+        let state = machine.save_state();
+        machine.run_frame().unwrap();
+        assert_ne!(machine.cpu().register(0), 1);
+        machine.load_state(&state).unwrap();
+        assert_eq!(machine.cpu().register(0), 1);
+        assert_eq!(machine.cpu().pc, 0x202);
+    }
+
+    #[test]
+    fn refuses_state_for_other_rom() {
+        let mut machine = Machine::default();
+        machine.load_rom(&[0x12, 0x00]).unwrap();
+        let state = machine.save_state();
+        machine.load_rom(&[0x12, 0x02]).unwrap();
+        let result = machine.load_state(&state);
+        assert!(matches!(result, Err(SaveStateError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn resets_to_loaded_rom() {
+        let mut machine = Machine::default();
+        machine.load_rom(&[0x60, 0x05]).unwrap();
+        machine.step().unwrap();
+        machine.reset().unwrap();
+        assert_eq!(machine.cpu().register(0), 0);
+        assert_eq!(machine.cpu().pc, 0x200);
+        assert_eq!(machine.rom(), &[0x60, 0x05]);
+    }
+
+    #[test]
+    fn clears_memory_left_by_a_previous_rom() {
+        let mut machine = Machine::default();
+        machine.load_rom(&[0xAA; 16]).unwrap();
+        machine.cpu_mut().memory[0x800] = 0x55;
+        machine.load_rom(&[0x60, 0x05]).unwrap();
+        let memory = &machine.cpu().memory;
+        assert_eq!(&memory[0x200..0x202], &[0x60, 0x05]);
+        assert!(memory[0x202..0x210].iter().all(|byte| *byte == 0));
+        assert_eq!(memory[0x800], 0);
+    }
+
+    #[test]
+    fn records_a_trace() {
+        let mut machine = Machine::default();
+        // V0 := 5; I := 0x300; loop: jump loop
+        machine
+            .load_rom(&[0x60, 0x05, 0xA3, 0x00, 0x12, 0x04])
+            .unwrap();
+        machine.cpu_mut().set_record_trace(true);
+        machine.run_frame().unwrap();
+        assert_eq!(machine.frame_count(), 1);
+
+        let trace = machine.cpu_mut().take_trace();
+        assert_eq!(trace.len(), DEFAULT_INSTRUCTIONS_PER_FRAME);
+        assert_eq!(trace[0].pc, 0x200);
+        assert_eq!(trace[0].registers, vec![(0, 5)]);
+        assert_eq!(trace[1].address_register, Some(0x300));
+        assert_eq!(trace[2].changes(), "");
+        assert!(machine.cpu_mut().take_trace().is_empty());
+    }
+
+    #[test]
+    fn traces_instructions_that_overwrite_themselves() {
+        let mut machine = Machine::default();
+        // I := 0x202; save v1
+        machine.load_rom(&[0xA2, 0x02, 0xF1, 0x55]).unwrap();
+        machine.cpu_mut().set_record_trace(true);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.cpu().opcode_at(0x202), 0x0000);
+        let trace = machine.cpu_mut().take_trace();
+        assert_eq!(trace[1].opcode, 0xF155);
+    }
+}
@@ -1,68 +1,184 @@
 use std::{
-    collections::HashMap,
-    env, fs,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter},
     time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use cpu::Cpu;
+use anyhow::{anyhow, Context, Result};
+use audio::Beeper;
+use clap::Parser;
+use cli::{AsmArgs, Cli, Command, DisasmArgs, RunArgs};
+use controller::{ControllerMap, Controllers};
+use disasm_view::DisasmView;
 use egui_backend::{
-    egui::{self, Color32, CtxRef, Image, Ui},
+    egui::{self, Color32, Image, Ui},
     gl,
 };
 use egui_backend::{DpiScaling, ShaderVersion};
 use egui_sdl2_gl as egui_backend;
+use keymap::KeymapConfig;
+use keymap_view::{KeymapView, RomInfo};
+use keypad_view::KeypadView;
+use memory_view::MemoryView;
+use reimu::{
+    asm::Assembler,
+    cpu::{self, Cpu, CpuError},
+    debugger::{Condition, Debugger, Watchpoint},
+    disasm::{self, Disassembly},
+    gpu,
+    headless::{self, Limit, Runner},
+    octo,
+    rewind::RewindBuffer,
+    savestate,
+    trace::TraceWriter,
+    Machine,
+};
+use scheduler::Scheduler;
 use sdl2::{
     event::Event,
-    keyboard::Keycode,
+    keyboard::{Keycode, Mod},
     video::{GLProfile, SwapInterval},
 };
+use sprite_view::SpriteView;
 
+mod audio;
 mod cli;
-mod cpu;
-mod gpu;
+mod controller;
+mod disasm_view;
+mod keymap;
+mod keymap_view;
+mod keypad_view;
+mod memory_view;
+mod palette;
+mod scheduler;
+mod slots;
+mod sprite_view;
 mod util;
-
-const SCALING_FACTOR: u32 = 10;
-
-const RENDER_WIDTH: u32 = gpu::SCREEN_WIDTH as u32 * SCALING_FACTOR;
-const RENDER_HEIGHT: u32 = gpu::SCREEN_HEIGHT as u32 * SCALING_FACTOR;
+mod widgets;
 
 const WINDOW_WIDTH: u32 = 1280;
 const WINDOW_HEIGHT: u32 = 800;
 
 const TARGET_SPEED: usize = 60;
 
+const STATUS_DURATION: Duration = Duration::from_secs(3);
+
+const MIB: usize = 1024 * 1024;
+
+const FAST_FORWARD_SPEED: f64 = 4.0;
+const SLOW_MOTION_SPEED: f64 = 0.25;
+
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let path = &args[1];
-    let program = fs::read(path).unwrap();
-
-    let mut keymap = HashMap::new();
-    keymap.insert(Keycode::Num1, 0x1u8);
-    keymap.insert(Keycode::Num2, 0x2u8);
-    keymap.insert(Keycode::Num3, 0x3u8);
-    keymap.insert(Keycode::Num4, 0xCu8);
-    keymap.insert(Keycode::Q, 0x4u8);
-    keymap.insert(Keycode::W, 0x5u8);
-    keymap.insert(Keycode::E, 0x6u8);
-    keymap.insert(Keycode::R, 0xDu8);
-    keymap.insert(Keycode::A, 0x7u8);
-    keymap.insert(Keycode::S, 0x8u8);
-    keymap.insert(Keycode::D, 0x9u8);
-    keymap.insert(Keycode::F, 0xEu8);
-    keymap.insert(Keycode::Z, 0xAu8);
-    keymap.insert(Keycode::X, 0x0u8);
-    keymap.insert(Keycode::C, 0xBu8);
-    keymap.insert(Keycode::V, 0xFu8);
-
-    let mut gpu = gpu::Gpu::new();
-    let mut cpu = cpu::Cpu::new(&mut gpu);
-
-    cpu.load(&program);
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Disasm(args)) => disassemble(&args),
+        Some(Command::Asm(args)) => assemble(&args),
+        None => run(cli.run),
+    }
+}
+
+type Tracer = TraceWriter<BufWriter<File>>;
+
+fn run(cli: RunArgs) -> Result<()> {
+    if let Some(level) = cli.log {
+        tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(io::stderr)
+            .init();
+    }
+
+    let rom_path = cli.rom.clone().context("No ROM given")?;
+    let (program, mut labels) = if rom_path.extension().is_some_and(|ext| ext == "8o") {
+        let program = octo::compile_file(&rom_path)?;
+        let labels = program
+            .labels
+            .iter()
+            .map(|(name, addr)| (*addr, name.clone()))
+            .collect();
+        (program.bytes, labels)
+    } else {
+        let program = fs::read(&rom_path)
+            .with_context(|| format!("Failed to read ROM '{}'", rom_path.display()))?;
+        (program, BTreeMap::new())
+    };
+    if let Some(path) = &cli.symbols {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read symbol file '{}'", path.display()))?;
+        let symbols = disasm::parse_symbol_file(&text)
+            .map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+        labels.extend(symbols);
+    }
+
+    // The default configuration file is optional, but one given on the
+    // command line has to exist
+    let keymap_path = cli.keymap.clone().or_else(keymap::default_path);
+    let mut keymap_config = match &keymap_path {
+        Some(path) if cli.keymap.is_some() || path.exists() => KeymapConfig::load(path)?,
+        _ => KeymapConfig::default(),
+    };
+    let rom = RomInfo {
+        hash: savestate::rom_hash(&program),
+        name: rom_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+    };
+    let mut keymap = keymap_config.keymap(&rom.hash)?;
+    let controller_maps = keymap_config
+        .controllers(&rom.hash)
+        .iter()
+        .map(ControllerMap::new)
+        .collect::<Result<Vec<_>>>()?;
+    let mut keymap_view = KeymapView::new(keymap_path, rom, &keymap_config);
+
+    let mut machine = Machine::new(cli.profile.quirks());
+    machine.set_instructions_per_frame(cli.ipf);
+    machine.load_rom(&program)?;
+    if let Some(seed) = cli.seed {
+        machine.cpu_mut().seed_rng(seed);
+    }
+
+    let mut tracer = open_tracer(&cli)?;
+    machine.cpu_mut().set_record_trace(tracer.is_some());
+
+    if cli.headless {
+        return run_headless(&cli, &mut machine, &mut tracer);
+    }
+
+    let render_width = gpu::SCREEN_WIDTH as u32 * cli.scale;
+    let render_height = gpu::SCREEN_HEIGHT as u32 * cli.scale;
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+
+    let mut frequency = cli.frequency;
+    let mut volume = cli.volume;
+    let mut muted = cli.mute;
+    let mut beeper = match sdl_context
+        .audio()
+        .and_then(|audio| Beeper::new(&audio, frequency, volume))
+    {
+        Ok(mut beeper) => {
+            beeper.set_muted(muted);
+            Some(beeper)
+        }
+        Err(err) => {
+            eprintln!("Audio unavailable: {}", err);
+            None
+        }
+    };
+
+    let mut controllers = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(Controllers::new(subsystem, controller_maps)),
+        Err(err) => {
+            eprintln!("Game controllers unavailable: {}", err);
+            None
+        }
+    };
+    // Keys held on the keyboard, merged with the controllers and the
+    // on-screen keypad after events
+    let mut keyboard_keys: u16 = 0;
+
     let gl_attr = video_subsystem.gl_attr();
     gl_attr.set_context_profile(GLProfile::Core);
     gl_attr.set_context_version(3, 3);
@@ -92,24 +208,40 @@ fn main() -> Result<()> {
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let screen_initial = [Color32::BLACK; gpu::SCREEN_WIDTH * gpu::SCREEN_HEIGHT];
-    let screen_texture_id = egui_painter.new_user_texture(
-        (gpu::SCREEN_WIDTH, gpu::SCREEN_HEIGHT),
-        &screen_initial,
-        false,
-    );
-
-    let target_elapsed = Duration::from_nanos(util::ns_per_frame(TARGET_SPEED));
-    let mut total_elapsed = Duration::ZERO;
-
-    let mut show_debug = true;
-    let mut mem_offset: usize = 0;
+    let mut screen_size = machine.screen_size();
+    let screen_initial = vec![Color32::BLACK; screen_size.0 * screen_size.1];
+    let mut screen_texture_id = egui_painter.new_user_texture(screen_size, &screen_initial, false);
+
+    let mut scheduler = Scheduler::new(TARGET_SPEED);
+    let mut ipf = cli.ipf;
+    let mut speed = 1.0;
+    let mut fast_forward = false;
+    let mut slow_motion = false;
+    let mut rewinding = false;
+    let mut rewind = RewindBuffer::new(cli.rewind_budget * MIB);
+    let mut rewind_budget_mib = cli.rewind_budget;
+
+    let mut palette = cli.palette.unwrap_or_default();
+    let mut palette_changed = false;
+    let mut show_debug = cli.debug;
+    let mut debugger = Debugger::new();
+    if cli.paused {
+        debugger.pause();
+    }
+    let mut was_paused = debugger.paused();
+    let mut debug_form = DebugForm::default();
+    let mut disasm_view = DisasmView::new(labels);
+    let mut fault: Option<CpuError> = None;
+    let mut memory_view = MemoryView::new();
+    let mut sprite_view = SpriteView::new();
+    let mut keypad_view = KeypadView::new();
+    keypad_view.open = cli.keypad;
+    let mut slot: u8 = 0;
+    let mut status: Option<(String, Instant)> = None;
 
     let start_time = Instant::now();
 
     'main: loop {
-        let now = Instant::now();
-
         egui_state.input.time = Some(start_time.elapsed().as_secs_f64());
         egui_ctx.begin_frame(egui_state.input.take());
 
@@ -118,17 +250,72 @@ fn main() -> Result<()> {
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
 
-        cpu.step();
+        let effective_speed = if fast_forward {
+            speed * FAST_FORWARD_SPEED
+        } else if slow_motion {
+            speed * SLOW_MOTION_SPEED
+        } else {
+            speed
+        };
+        scheduler.set_speed(effective_speed);
+        let frames = scheduler.frames_due();
+
+        machine.set_instructions_per_frame(ipf);
+        if rewinding && !debugger.paused() {
+            for _ in 0..frames {
+                match rewind.rewind(&mut machine) {
+                    Ok(true) => fault = None,
+                    Ok(false) => break,
+                    Err(err) => {
+                        status = Some((err.to_string(), Instant::now()));
+                        rewind.clear();
+                        break;
+                    }
+                }
+            }
+        } else if fault.is_none() && !debugger.paused() {
+            if show_debug && frames > 0 {
+                memory_view.snapshot(machine.cpu());
+            }
+            let was_running = machine.running();
+            let mut stopped = false;
+            for _ in 0..frames {
+                match debugger.run_frame(&mut machine) {
+                    Ok(None) => rewind.push(&machine),
+                    Ok(Some(stop)) => {
+                        status = Some((stop.to_string(), Instant::now()));
+                        show_debug = true;
+                        stopped = true;
+                        break;
+                    }
+                    Err(err) => {
+                        eprintln!("CPU fault: {}", err);
+                        fault = Some(err);
+                        stopped = true;
+                        break;
+                    }
+                }
+            }
+            stopped |= was_running && !machine.running();
+            write_trace(&mut tracer, &mut machine, stopped)?;
+        }
 
-        if cpu.redraw {
-            cpu.redraw = false;
-            let screen = cpu.screen();
-            let grid: Vec<Color32> = screen
-                .iter()
-                .map(|p| if *p { Color32::WHITE } else { Color32::BLACK })
-                .collect();
+        if let Some(beeper) = beeper.as_mut() {
+            beeper.set_active(machine.audio_active() && fault.is_none() && !debugger.paused());
+        }
 
-            egui_painter.update_user_texture_data(screen_texture_id, &grid);
+        if machine.take_redraw() || palette_changed {
+            palette_changed = false;
+            let screen = machine.framebuffer();
+            let grid: Vec<Color32> = screen.iter().map(|p| palette.color(*p)).collect();
+
+            if machine.screen_size() != screen_size {
+                screen_size = machine.screen_size();
+                egui_painter.free_user_texture(screen_texture_id);
+                screen_texture_id = egui_painter.new_user_texture(screen_size, &grid, false);
+            } else {
+                egui_painter.update_user_texture_data(screen_texture_id, &grid);
+            }
         }
 
         egui::Window::new("Screen")
@@ -137,15 +324,110 @@ fn main() -> Result<()> {
             .show(&egui_ctx, |ui| {
                 ui.add(Image::new(
                     screen_texture_id,
-                    egui::vec2(RENDER_WIDTH as f32, RENDER_HEIGHT as f32),
+                    egui::vec2(render_width as f32, render_height as f32),
                 ));
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut keypad_view.open, "Keypad");
+                    ui.label(format!("Slot {}", slot));
+                    if let Some((message, time)) = &status {
+                        if time.elapsed() < STATUS_DURATION {
+                            ui.label(message);
+                        }
+                    }
+                });
+                ui.collapsing("Speed", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Instructions per frame");
+                        ui.add(egui::DragValue::new(&mut ipf).clamp_range(1..=1000));
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut speed, scheduler::MIN_SPEED..=scheduler::MAX_SPEED)
+                            .logarithmic(true)
+                            .text("Speed"),
+                    );
+                    if ui.button("Real time").clicked() {
+                        speed = 1.0;
+                    }
+                });
+                ui.collapsing("Rewind", |ui| {
+                    let budget = ui.add(
+                        egui::Slider::new(&mut rewind_budget_mib, 1..=256)
+                            .logarithmic(true)
+                            .suffix(" MiB")
+                            .text("Budget"),
+                    );
+                    if budget.changed() {
+                        rewind.set_budget(rewind_budget_mib * MIB);
+                    }
+                    ui.label(format!(
+                        "{} frames ({:.1} MiB)",
+                        rewind.len(),
+                        rewind.used() as f64 / MIB as f64
+                    ));
+                });
+                ui.collapsing("Audio", |ui| {
+                    let mut changed = ui.checkbox(&mut muted, "Mute").changed();
+                    changed |= ui
+                        .add(egui::Slider::new(&mut volume, 0.0..=1.0).text("Volume"))
+                        .changed();
+                    let freq_changed = ui
+                        .add(
+                            egui::Slider::new(&mut frequency, 50.0..=2000.0)
+                                .logarithmic(true)
+                                .text("Frequency"),
+                        )
+                        .changed();
+                    if let Some(beeper) = beeper.as_mut() {
+                        if changed {
+                            beeper.set_muted(muted);
+                            beeper.set_volume(volume);
+                        }
+                        if freq_changed {
+                            beeper.set_frequency(frequency);
+                        }
+                    }
+                });
+                ui.collapsing("Palette", |ui| {
+                    ui.horizontal(|ui| {
+                        for color in palette.colors.iter_mut() {
+                            if ui.color_edit_button_srgba(color).changed() {
+                                palette_changed = true;
+                            }
+                        }
+                    });
+                });
+            });
+
+        if let Some(err) = fault {
+            let mut reset = false;
+            egui::Window::new("Fault").show(&egui_ctx, |ui| {
+                ui_fault(ui, &err);
+                reset = ui.button("Reset").clicked();
             });
+            if reset {
+                machine.reset()?;
+                rewind.clear();
+                debugger.clear_history();
+                fault = None;
+            }
+        }
 
         if show_debug {
             egui::Window::new("CPU").show(&egui_ctx, |ui| {
-                ui_cpu_regs(ui, &mut cpu);
+                let editable = debugger.paused();
+                ui_cpu_regs(ui, machine.cpu_mut(), editable);
+                ui.separator();
+                ui_debugger(ui, &mut debugger, &machine, &mut debug_form);
             });
-            ui_memory(&egui_ctx, &mut cpu, mem_offset);
+            disasm_view.show(&egui_ctx, &machine, &mut debugger);
+            memory_view.show(&egui_ctx, machine.cpu_mut(), debugger.paused());
+            sprite_view.show(&egui_ctx, machine.cpu(), &palette);
+        }
+        if keypad_view.open {
+            keypad_view.show(&egui_ctx, machine.cpu().keys());
+        }
+        if keymap_view.open {
+            keymap_view.show(&egui_ctx, &mut keymap, &mut keymap_config);
         }
 
         let (egui_output, egui_paint_cmds) = egui_ctx.end_frame();
@@ -155,14 +437,28 @@ fn main() -> Result<()> {
 
         window.gl_swap_window();
 
-        total_elapsed += now.elapsed();
-        if total_elapsed >= target_elapsed {
-            total_elapsed -= target_elapsed;
-            cpu.tick_timers();
-        }
-
         for event in event_pump.poll_iter() {
+            let key_event = matches!(
+                event,
+                Event::KeyDown { .. } | Event::KeyUp { .. } | Event::TextInput { .. }
+            );
+            // A focused text field gets the keyboard to itself, so typing in
+            // it neither presses CHIP-8 keys nor triggers hotkeys
+            if key_event && egui_ctx.wants_keyboard_input() {
+                keyboard_keys = 0;
+                rewinding = false;
+                fast_forward = false;
+                slow_motion = false;
+                egui_state.process_input(&window, event, &mut egui_painter);
+                continue;
+            }
             match event {
+                Event::KeyDown {
+                    keycode: Some(kc), ..
+                } if keymap_view.capturing() => {
+                    keymap_view.capture(&mut keymap, kc);
+                    continue;
+                }
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
@@ -171,102 +467,512 @@ fn main() -> Result<()> {
                     break 'main;
                 }
                 Event::KeyUp {
-                    keycode: Some(Keycode::F1),
+                    keycode: Some(Keycode::F2),
                     ..
                 } => {
-                    cpu.dump();
+                    show_debug = !show_debug;
                 }
                 Event::KeyUp {
-                    keycode: Some(Keycode::F2),
+                    keycode: Some(Keycode::F3),
                     ..
                 } => {
-                    show_debug = !show_debug;
+                    debugger.toggle_pause(&machine);
                 }
                 Event::KeyUp {
-                    keycode: Some(Keycode::PageDown),
+                    keycode: Some(Keycode::F9),
                     ..
                 } => {
-                    if mem_offset < 0xF00 {
-                        mem_offset += 0x100;
+                    debugger.toggle_breakpoint(machine.cpu().pc);
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => {
+                    debugger.step_over(&machine);
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::F11),
+                    keymod,
+                    ..
+                } => {
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        debugger.step_out(&machine);
+                    } else {
+                        debugger.step(&machine);
                     }
                 }
                 Event::KeyUp {
-                    keycode: Some(Keycode::PageUp),
+                    keycode: Some(Keycode::F4),
                     ..
                 } => {
-                    if mem_offset > 0x000 {
-                        mem_offset -= 0x100;
+                    muted = !muted;
+                    if let Some(beeper) = beeper.as_mut() {
+                        beeper.set_muted(muted);
                     }
                 }
+                Event::KeyUp {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    let message = match slots::save(&machine, &rom_path, slot) {
+                        Ok(_) => format!("Saved state to slot {}", slot),
+                        Err(err) => format!("{:#}", err),
+                    };
+                    status = Some((message, Instant::now()));
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    slot = (slot + slots::SLOT_COUNT - 1) % slots::SLOT_COUNT;
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    slot = (slot + 1) % slots::SLOT_COUNT;
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    let message = match slots::load(&mut machine, &rom_path, slot) {
+                        Ok(_) => {
+                            fault = None;
+                            format!("Loaded state from slot {}", slot)
+                        }
+                        Err(err) => format!("{:#}", err),
+                    };
+                    status = Some((message, Instant::now()));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    // The debugger owns the machine while it is paused
+                    rewinding = !debugger.paused();
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding = false;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => {
+                    fast_forward = !debugger.paused();
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => {
+                    fast_forward = false;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backquote),
+                    ..
+                } => {
+                    slow_motion = !debugger.paused();
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backquote),
+                    ..
+                } => {
+                    slow_motion = false;
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    keymap_view.toggle();
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::PageDown),
+                    ..
+                } => {
+                    memory_view.page_down();
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::PageUp),
+                    ..
+                } => {
+                    memory_view.page_up();
+                }
                 Event::KeyDown {
                     keycode: Some(kc), ..
                 } => {
                     if let Some(key) = keymap.get(&kc) {
-                        cpu.set_key(*key, true);
+                        keyboard_keys |= 1 << key;
                     }
                 }
                 Event::KeyUp {
                     keycode: Some(kc), ..
                 } => {
                     if let Some(key) = keymap.get(&kc) {
-                        cpu.set_key(*key, false);
+                        keyboard_keys &= !(1 << key);
+                    }
+                }
+                Event::ControllerDeviceAdded { .. }
+                | Event::ControllerDeviceRemoved { .. }
+                | Event::ControllerButtonDown { .. }
+                | Event::ControllerButtonUp { .. }
+                | Event::ControllerAxisMotion { .. } => {
+                    let message = controllers
+                        .as_mut()
+                        .and_then(|controllers| controllers.handle_event(&event));
+                    if let Some(message) = message {
+                        status = Some((message, Instant::now()));
                     }
+                    continue;
                 }
-                _ => egui_state.process_input(&window, event, &mut egui_painter),
+                _ => {}
             }
+            // Keys go to egui as well, so it can move focus into text fields
+            egui_state.process_input(&window, event, &mut egui_painter);
         }
+        let controller_keys = controllers.as_ref().map_or(0, Controllers::held);
+        machine.set_keys(keyboard_keys | controller_keys | keypad_view.keys());
+
+        // Don't catch up on the frames that passed while paused
+        if was_paused && !debugger.paused() {
+            scheduler.reset();
+        }
+        was_paused = debugger.paused();
     }
 
+    write_trace(&mut tracer, &mut machine, true)?;
     Ok(())
 }
 
-fn ui_cpu_regs(ui: &mut Ui, cpu: &mut Cpu) {
+/// Runs the program without a window until it exits or reaches the limit,
+/// then reports the final state and exits with the program's exit code.
+fn run_headless(cli: &RunArgs, machine: &mut Machine, tracer: &mut Option<Tracer>) -> Result<()> {
+    let limit = match (cli.frames, cli.cycles) {
+        (Some(frames), _) => Some(Limit::Frames(frames)),
+        (None, Some(cycles)) => Some(Limit::Cycles(cycles)),
+        (None, None) => None,
+    };
+    let mut runner = Runner::new(cli.keys.clone().unwrap_or_default(), limit);
+    let reason = loop {
+        let stop = runner.run_frame(machine);
+        write_trace(tracer, machine, stop.is_some())?;
+        if let Some(reason) = stop {
+            break reason;
+        }
+    };
+
+    let screen = machine.cpu().gpu().render_text();
+    match &cli.screen {
+        Some(path) => fs::write(path, screen)
+            .with_context(|| format!("Failed to write '{}'", path.display()))?,
+        None => print!("{}", screen),
+    }
+    print!("{}", headless::report(machine, &runner, &reason));
+    std::process::exit(reason.exit_code());
+}
+
+/// Opens the execution trace file requested on the command line, if any.
+fn open_tracer(cli: &RunArgs) -> Result<Option<Tracer>> {
+    let path = match &cli.trace {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create trace file '{}'", path.display()))?;
+    let tracer = TraceWriter::new(BufWriter::new(file), cli.trace_format, cli.trace_last)
+        .with_context(|| format!("Failed to write trace file '{}'", path.display()))?;
+    Ok(Some(tracer))
+}
+
+/// Moves the instructions the CPU traced into the trace file. When execution
+/// has `stopped`, the ring buffer is written out and the file flushed.
+fn write_trace(tracer: &mut Option<Tracer>, machine: &mut Machine, stopped: bool) -> Result<()> {
+    let tracer = match tracer {
+        Some(tracer) => tracer,
+        None => return Ok(()),
+    };
+    for entry in machine.cpu_mut().take_trace() {
+        tracer.record(entry).context("Failed to write trace")?;
+    }
+    if stopped {
+        tracer.flush().context("Failed to write trace")?;
+    }
+    Ok(())
+}
+
+/// Prints or writes a disassembly listing of a ROM.
+fn disassemble(args: &DisasmArgs) -> Result<()> {
+    let rom = fs::read(&args.rom)
+        .with_context(|| format!("Failed to read ROM '{}'", args.rom.display()))?;
+    let listing = Disassembly::new(&rom).listing(args.syntax);
+    match &args.output {
+        Some(path) => fs::write(path, listing)
+            .with_context(|| format!("Failed to write '{}'", path.display()))?,
+        None => print!("{}", listing),
+    }
+    Ok(())
+}
+
+/// Assembles a source file into a ROM, and optionally a symbol file.
+fn assemble(args: &AsmArgs) -> Result<()> {
+    let program = Assembler::new(args.platform).assemble_file(&args.source)?;
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.source.with_extension("ch8"));
+    fs::write(&output, &program.bytes)
+        .with_context(|| format!("Failed to write '{}'", output.display()))?;
+    if let Some(path) = &args.symbols {
+        fs::write(path, program.symbol_file())
+            .with_context(|| format!("Failed to write '{}'", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Shows the CPU state, which can be edited while `editable` is set.
+fn ui_cpu_regs(ui: &mut Ui, cpu: &mut Cpu, editable: bool) {
     for row in 0..4 {
         ui.columns(8, |cols| {
             for col in 0..4 {
                 let idx = col * 4 + row;
                 cols[col * 2].label(format!("V{:X}", idx));
-                cols[col * 2 + 1].code(format!("{:02X}", cpu.register(idx)));
+                let mut value = cpu.register(idx);
+                if widgets::hex_value_u8(&mut cols[col * 2 + 1], &mut value, editable) {
+                    cpu.set_register(idx, value);
+                }
             }
         });
     }
     ui.columns(8, |cols| {
         cols[0].label("PC");
-        cols[1].code(format!("{:04X}", cpu.pc));
+        if widgets::hex_value(&mut cols[1], &mut cpu.pc, 4, editable) {
+            cpu.pc = cpu.pc.min(cpu::MEMORY_SIZE - 2);
+        }
         cols[2].label("I");
-        cols[3].code(format!("{:04X}", cpu.address_register));
+        widgets::hex_value(&mut cols[3], &mut cpu.address_register, 4, editable);
         cols[4].label("DT");
-        cols[5].code(format!("{:02X}", cpu.delay_timer));
+        widgets::hex_value_u8(&mut cols[5], &mut cpu.delay_timer, editable);
         cols[6].label("ST");
-        cols[7].code(format!("{:02X}", cpu.sound_timer));
+        widgets::hex_value_u8(&mut cols[7], &mut cpu.sound_timer, editable);
     });
     ui.horizontal(|ui| {
         ui.label("SP");
-        ui.code(format!("{:02X}", cpu.sp));
+        if widgets::hex_value(ui, &mut cpu.sp, 2, editable) {
+            cpu.sp = cpu.sp.min(cpu.stack.len());
+        }
+        ui.label("Planes");
+        ui.code(format!("{:X}", cpu.planes()));
+        ui.label("Pitch");
+        ui.code(format!("{:02X}", cpu.pitch));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Audio");
+        let pattern: Vec<String> = cpu
+            .audio_pattern
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        ui.code(pattern.join(""));
     });
-    for val in cpu.stack.iter() {
-        ui.code(format!("{:04X}", val));
+    for val in cpu.stack.iter_mut() {
+        widgets::hex_value(ui, val, 4, editable);
+    }
+}
+
+/// Text entered in the debugger controls, kept between frames.
+#[derive(Default)]
+struct DebugForm {
+    address: String,
+    condition: String,
+    watch: String,
+    watch_read: bool,
+    watch_write: bool,
+    watch_execute: bool,
+    error: Option<String>,
+}
+
+fn parse_address(text: &str) -> Result<usize, String> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    match usize::from_str_radix(digits, 16) {
+        Ok(addr) if addr < cpu::MEMORY_SIZE => Ok(addr),
+        _ => Err(format!("Invalid address '{}'", text)),
     }
 }
 
-fn ui_memory(egui_ctx: &CtxRef, cpu: &mut Cpu, base_offset: usize) {
-    egui::Window::new("Memory")
-        .min_width(500.0)
-        .show(egui_ctx, |ui| {
-            ui.columns(17, |cols| {
-                for idx in 0..16 {
-                    cols[idx + 1].code(format!("{:02X}", idx));
+/// Parses `ADDR` or `START-END`, both in hex.
+fn parse_address_range(text: &str) -> Result<(usize, usize), String> {
+    match text.split_once('-') {
+        Some((start, end)) => Ok((parse_address(start)?, parse_address(end)?)),
+        None => parse_address(text).map(|addr| (addr, addr)),
+    }
+}
+
+fn ui_debugger(ui: &mut Ui, debugger: &mut Debugger, machine: &Machine, form: &mut DebugForm) {
+    let paused = debugger.paused();
+    ui.horizontal(|ui| {
+        let label = if paused { "Resume" } else { "Pause" };
+        if ui.button(label).on_hover_text("F3").clicked() {
+            debugger.toggle_pause(machine);
+        }
+        if ui
+            .add_enabled(paused, egui::Button::new("Step"))
+            .on_hover_text("F11")
+            .clicked()
+        {
+            debugger.step(machine);
+        }
+        if ui
+            .add_enabled(paused, egui::Button::new("Step over"))
+            .on_hover_text("F10")
+            .clicked()
+        {
+            debugger.step_over(machine);
+        }
+        if ui
+            .add_enabled(paused, egui::Button::new("Step out"))
+            .on_hover_text("Shift+F11")
+            .clicked()
+        {
+            debugger.step_out(machine);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Address");
+        ui.add(egui::TextEdit::singleline(&mut form.address).desired_width(60.0));
+        if ui.button("Run to").clicked() {
+            match parse_address(&form.address) {
+                Ok(addr) => {
+                    form.error = None;
+                    debugger.run_to(machine, addr);
                 }
-            });
-            for row in 0..16 {
-                let offset = base_offset + row * 16;
-                ui.columns(17, |cols| {
-                    cols[0].code(format!("{:03X}", offset));
-                    for idx in 0..16 {
-                        let pos = offset + idx;
-                        cols[idx + 1].code(format!("{:02X}", cpu.memory[pos]));
-                    }
-                });
+                Err(err) => form.error = Some(err),
+            }
+        }
+        if ui.button("Breakpoint").clicked() {
+            match parse_address(&form.address) {
+                Ok(addr) => {
+                    form.error = None;
+                    debugger.add_breakpoint(addr);
+                }
+                Err(err) => form.error = Some(err),
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Condition");
+        ui.add(
+            egui::TextEdit::singleline(&mut form.condition)
+                .hint_text("V0 == 0x10")
+                .desired_width(100.0),
+        );
+        if ui.button("Add").clicked() {
+            match form.condition.parse::<Condition>() {
+                Ok(condition) => {
+                    form.error = None;
+                    form.condition.clear();
+                    debugger.add_condition(condition, machine);
+                }
+                Err(err) => form.error = Some(err),
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Watch");
+        ui.add(
+            egui::TextEdit::singleline(&mut form.watch)
+                .hint_text("300-30F")
+                .desired_width(80.0),
+        );
+        ui.checkbox(&mut form.watch_read, "R");
+        ui.checkbox(&mut form.watch_write, "W");
+        ui.checkbox(&mut form.watch_execute, "X");
+        if ui.button("Add").clicked() {
+            match parse_address_range(&form.watch) {
+                Ok((start, end)) => {
+                    form.error = None;
+                    let mut watchpoint = Watchpoint::new(start, end);
+                    watchpoint.read = form.watch_read;
+                    watchpoint.write = form.watch_write;
+                    watchpoint.execute = form.watch_execute;
+                    debugger.add_watchpoint(watchpoint);
+                }
+                Err(err) => form.error = Some(err),
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        let mut enabled = debugger.break_on_self_modifying();
+        if ui
+            .checkbox(&mut enabled, "Break on self-modifying code")
+            .changed()
+        {
+            debugger.set_break_on_self_modifying(enabled);
+        }
+        ui.label(format!(
+            "({} bytes modified)",
+            debugger.modified_code().len()
+        ));
+    });
+    if let Some(err) = &form.error {
+        ui.colored_label(Color32::RED, err);
+    }
+
+    let mut remove_breakpoint = None;
+    for addr in debugger.breakpoints() {
+        ui.horizontal(|ui| {
+            ui.code(format!("{:04X}", addr));
+            if ui.small_button("x").clicked() {
+                remove_breakpoint = Some(*addr);
+            }
+        });
+    }
+    if let Some(addr) = remove_breakpoint {
+        debugger.remove_breakpoint(addr);
+    }
+
+    let mut remove_condition = None;
+    for (idx, condition) in debugger.conditions().enumerate() {
+        ui.horizontal(|ui| {
+            ui.code(condition.to_string());
+            if ui.small_button("x").clicked() {
+                remove_condition = Some(idx);
             }
         });
+    }
+    if let Some(idx) = remove_condition {
+        debugger.remove_condition(idx);
+    }
+
+    let mut remove_watchpoint = None;
+    for (idx, watchpoint) in debugger.watchpoints().iter().enumerate() {
+        ui.horizontal(|ui| {
+            ui.code(watchpoint.to_string());
+            if ui.small_button("x").clicked() {
+                remove_watchpoint = Some(idx);
+            }
+        });
+    }
+    if let Some(idx) = remove_watchpoint {
+        debugger.remove_watchpoint(idx);
+    }
+}
+
+fn ui_fault(ui: &mut Ui, err: &CpuError) {
+    ui.label(err.to_string());
+    ui.columns(4, |cols| {
+        cols[0].label("Address");
+        match err.addr() {
+            Some(addr) => cols[1].code(format!("{:04X}", addr)),
+            None => cols[1].code("-"),
+        };
+        cols[2].label("Opcode");
+        match err.opcode() {
+            Some(opcode) => cols[3].code(format!("{:04X}", opcode)),
+            None => cols[3].code("-"),
+        };
+    });
 }
@@ -0,0 +1,229 @@
+use egui_sdl2_gl::egui::{self, Color32, CtxRef, RichText, Ui};
+use reimu::cpu::{Cpu, MEMORY_SIZE};
+
+use crate::widgets;
+
+const BYTES_PER_ROW: usize = 16;
+const ROWS: usize = 16;
+const PAGE_SIZE: usize = BYTES_PER_ROW * ROWS;
+
+const CHANGED_COLOR: Color32 = Color32::from_rgb(0xF0, 0x80, 0x40);
+const MATCH_BACKGROUND: Color32 = Color32::from_rgb(0x30, 0x60, 0x30);
+
+/// State of the memory window between frames.
+pub struct MemoryView {
+    offset: usize,
+    goto: String,
+    search: String,
+    /// Start of the highlighted search match, and its length.
+    found: Option<(usize, usize)>,
+    /// Memory as it was before the machine last ran, to highlight changes.
+    previous: Vec<u8>,
+    error: Option<String>,
+}
+
+impl MemoryView {
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            goto: String::new(),
+            search: String::new(),
+            found: None,
+            previous: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn page_up(&mut self) {
+        self.offset = self.offset.saturating_sub(PAGE_SIZE);
+    }
+
+    pub fn page_down(&mut self) {
+        self.offset = (self.offset + PAGE_SIZE).min(MEMORY_SIZE - PAGE_SIZE);
+    }
+
+    /// Remembers the current memory contents; call this before the machine
+    /// executes anything.
+    pub fn snapshot(&mut self, cpu: &Cpu) {
+        self.previous.clear();
+        self.previous.extend_from_slice(&cpu.memory);
+    }
+
+    fn changed(&self, cpu: &Cpu, addr: usize) -> bool {
+        self.previous
+            .get(addr)
+            .is_some_and(|byte| *byte != cpu.memory[addr])
+    }
+
+    fn go_to(&mut self, addr: usize) {
+        let row = addr - addr % BYTES_PER_ROW;
+        self.offset = row.min(MEMORY_SIZE - PAGE_SIZE);
+    }
+
+    /// Shows the window. Bytes can only be edited when `editable` is set,
+    /// i.e. while execution is paused.
+    pub fn show(&mut self, ctx: &CtxRef, cpu: &mut Cpu, editable: bool) {
+        egui::Window::new("Memory")
+            .min_width(500.0)
+            .show(ctx, |ui| {
+                self.ui_controls(ui, cpu);
+                ui.separator();
+                self.ui_rows(ui, cpu, editable);
+            });
+    }
+
+    fn ui_controls(&mut self, ui: &mut Ui, cpu: &Cpu) {
+        ui.horizontal(|ui| {
+            ui.label("Go to");
+            let goto = ui.add(egui::TextEdit::singleline(&mut self.goto).desired_width(50.0));
+            if goto.lost_focus() || ui.button("Go").clicked() {
+                match crate::parse_address(&self.goto) {
+                    Ok(addr) => {
+                        self.error = None;
+                        self.go_to(addr);
+                    }
+                    Err(err) if !self.goto.is_empty() => self.error = Some(err),
+                    Err(_) => {}
+                }
+            }
+            if ui.small_button("⬆").on_hover_text("PgUp").clicked() {
+                self.page_up();
+            }
+            if ui.small_button("⬇").on_hover_text("PgDn").clicked() {
+                self.page_down();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Find");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.search)
+                    .hint_text("A2 1E")
+                    .desired_width(120.0),
+            );
+            if ui.button("Next").clicked() {
+                self.find_next(cpu);
+            }
+        });
+        if let Some(err) = &self.error {
+            ui.colored_label(Color32::RED, err);
+        }
+    }
+
+    fn find_next(&mut self, cpu: &Cpu) {
+        let pattern = match parse_pattern(&self.search) {
+            Ok(pattern) => pattern,
+            Err(err) => {
+                self.error = Some(err);
+                return;
+            }
+        };
+        let from = match self.found {
+            Some((addr, _)) => addr + 1,
+            None => self.offset,
+        };
+        match find_pattern(&cpu.memory, &pattern, from) {
+            Some(addr) => {
+                self.error = None;
+                self.found = Some((addr, pattern.len()));
+                self.go_to(addr);
+            }
+            None => {
+                self.found = None;
+                self.error = Some(format!("'{}' not found", self.search.trim()));
+            }
+        }
+    }
+
+    fn ui_rows(&mut self, ui: &mut Ui, cpu: &mut Cpu, editable: bool) {
+        ui.horizontal(|ui| {
+            ui.label(RichText::new("    ").monospace());
+            for idx in 0..BYTES_PER_ROW {
+                ui.label(RichText::new(format!("{:02X}", idx)).monospace().weak());
+            }
+        });
+        for row in 0..ROWS {
+            let offset = self.offset + row * BYTES_PER_ROW;
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(format!("{:04X}", offset)).monospace().weak());
+                for addr in offset..(offset + BYTES_PER_ROW) {
+                    let color = self.changed(cpu, addr).then_some(CHANGED_COLOR);
+                    if editable {
+                        widgets::hex_edit_u8(ui, &mut cpu.memory[addr], color);
+                    } else {
+                        let mut text =
+                            RichText::new(format!("{:02X}", cpu.memory[addr])).monospace();
+                        if let Some(color) = color {
+                            text = text.color(color);
+                        }
+                        if self
+                            .found
+                            .is_some_and(|(start, len)| (start..(start + len)).contains(&addr))
+                        {
+                            text = text.background_color(MATCH_BACKGROUND);
+                        }
+                        ui.label(text);
+                    }
+                }
+                let ascii: String = cpu.memory[offset..(offset + BYTES_PER_ROW)]
+                    .iter()
+                    .map(|b| {
+                        if b.is_ascii_graphic() || *b == b' ' {
+                            *b as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                ui.label(RichText::new(ascii).monospace());
+            });
+        }
+    }
+}
+
+/// Parses hex bytes such as `A2 1E` or `a21e`.
+fn parse_pattern(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("Invalid byte pattern '{}'", text.trim()));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&digits[idx..(idx + 2)], 16)
+                .map_err(|_| format!("Invalid byte pattern '{}'", text.trim()))
+        })
+        .collect()
+}
+
+/// Finds the first occurrence of `pattern` at or after `from`, wrapping
+/// around to the start of memory.
+fn find_pattern(memory: &[u8], pattern: &[u8], from: usize) -> Option<usize> {
+    let matches = |addr: &usize| memory[*addr..].starts_with(pattern);
+    let from = from.min(memory.len());
+    (from..memory.len())
+        .find(matches)
+        .or_else(|| (0..from).find(matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_patterns() {
+        assert_eq!(parse_pattern("A2 1e"), Ok(vec![0xA2, 0x1E]));
+        assert_eq!(parse_pattern("a21e"), Ok(vec![0xA2, 0x1E]));
+        assert!(parse_pattern("A2 1").is_err());
+        assert!(parse_pattern("zz").is_err());
+        assert!(parse_pattern(" ").is_err());
+    }
+
+    #[test]
+    fn finds_patterns_with_wrapping() {
+        let memory = [0x12, 0x34, 0x00, 0x12, 0x34];
+        assert_eq!(find_pattern(&memory, &[0x12, 0x34], 0), Some(0));
+        assert_eq!(find_pattern(&memory, &[0x12, 0x34], 1), Some(3));
+        assert_eq!(find_pattern(&memory, &[0x12, 0x34], 4), Some(0));
+        assert_eq!(find_pattern(&memory, &[0x34, 0x12], 0), None);
+    }
+}
@@ -0,0 +1,1117 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    asm::{AsmError, Program},
+    cpu::{MEMORY_SIZE, PROGRAM_START},
+};
+
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// A whitespace-separated word of source, with its position (both 1-based).
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// The right-hand side of a comparison or assignment.
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Register(u8),
+    Value(i64),
+}
+
+#[derive(Clone, Debug)]
+enum Condition {
+    Eq(u8, Operand),
+    Ne(u8, Operand),
+    Lt(u8, Operand),
+    Gt(u8, Operand),
+    Le(u8, Operand),
+    Ge(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+/// An open `if ... begin` or `loop` block.
+#[derive(Clone, Debug)]
+enum Block {
+    If {
+        jump: usize,
+        token: Token,
+    },
+    Else {
+        jump: usize,
+        token: Token,
+    },
+    Loop {
+        start: usize,
+        breaks: Vec<usize>,
+        token: Token,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FixupKind {
+    /// The low 12 bits of the instruction word at the address.
+    Address,
+    /// The whole word at the address, for `i := long`.
+    Long,
+}
+
+/// A reference to a label that wasn't defined yet when it was used.
+#[derive(Clone, Debug)]
+struct Fixup {
+    addr: usize,
+    kind: FixupKind,
+    token: Token,
+}
+
+/// Compiles Octo source into a program image.
+pub fn compile(name: &str, source: &str) -> Result<Program, AsmError> {
+    Compiler::new(name, source).compile()
+}
+
+pub fn compile_file(path: &Path) -> Result<Program, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+    compile(&name, &source)
+}
+
+/// Single-pass Octo compiler. References to labels that are defined later
+/// are patched once the whole source has been read.
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: BTreeMap<String, Vec<Fixup>>,
+    blocks: Vec<Block>,
+    expansions: usize,
+    /// Whether a `:calc` has used `HERE`, which pins the code after the jump
+    /// to main in place.
+    here_used: bool,
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            tokens: tokenize(source),
+            rom: Vec::new(),
+            here: PROGRAM_START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: BTreeMap::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+            here_used: false,
+        }
+    }
+
+    fn error(&self, token: &Token, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn compile(mut self) -> Result<Program, AsmError> {
+        // Execution starts at 0x200, so unless main comes first it is
+        // reached through a jump
+        let start = Token {
+            text: "main".to_string(),
+            line: 1,
+            column: 1,
+        };
+        self.jump(0x1000, &start)?;
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let token = match block {
+                Block::If { token, .. } | Block::Else { token, .. } | Block::Loop { token, .. } => {
+                    token
+                }
+            };
+            return Err(self.error(token, format!("'{}' is never closed", token.text)));
+        }
+        if let Some(fixups) = self.fixups.get("main") {
+            if fixups.iter().any(|f| f.addr == PROGRAM_START) {
+                return Err(AsmError {
+                    file: self.file.clone(),
+                    line: 0,
+                    column: 0,
+                    message: "Program is missing a 'main' label".to_string(),
+                });
+            }
+        }
+        if let Some(fixup) = self.fixups.values().flatten().next() {
+            return Err(self.error(
+                &fixup.token,
+                format!("Undefined name '{}'", fixup.token.text),
+            ));
+        }
+
+        let labels = self.labels.into_iter().collect();
+        Ok(Program {
+            origin: PROGRAM_START,
+            bytes: self.rom,
+            labels,
+        })
+    }
+
+    fn next(&mut self, after: &Token) -> Result<Token, AsmError> {
+        self.tokens.pop_front().ok_or_else(|| {
+            self.error(
+                after,
+                format!("Unexpected end of input after '{}'", after.text),
+            )
+        })
+    }
+
+    /// Consumes the next token if it is `text`.
+    fn accept(&mut self, text: &str) -> bool {
+        if self.tokens.front().is_some_and(|t| t.text == text) {
+            self.tokens.pop_front();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, after: &Token, text: &str) -> Result<Token, AsmError> {
+        let token = self.next(after)?;
+        if token.text != text {
+            return Err(self.error(&token, format!("Expected '{}'", text)));
+        }
+        Ok(token)
+    }
+
+    fn emit(&mut self, byte: u8, token: &Token) -> Result<(), AsmError> {
+        if self.here >= MEMORY_SIZE {
+            return Err(self.error(token, "Program doesn't fit in memory"));
+        }
+        let idx = self.here - PROGRAM_START;
+        if idx >= self.rom.len() {
+            self.rom.resize(idx + 1, 0);
+        }
+        self.rom[idx] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16, token: &Token) -> Result<(), AsmError> {
+        let [hi, lo] = word.to_be_bytes();
+        self.emit(hi, token)?;
+        self.emit(lo, token)
+    }
+
+    fn patch(&mut self, addr: usize, word: u16) {
+        let idx = addr - PROGRAM_START;
+        self.rom[idx..(idx + 2)].copy_from_slice(&word.to_be_bytes());
+    }
+
+    /// Encodes a jump to `target`, for the jumps that close blocks.
+    fn jump_to(&self, target: usize, token: &Token) -> Result<u16, AsmError> {
+        if target > 0xFFF {
+            return Err(self.error(token, format!("Address 0x{:X} is too large", target)));
+        }
+        Ok(0x1000 | target as u16)
+    }
+
+    /// Emits `base | NNN` for an address operand, deferring labels that
+    /// aren't defined yet.
+    fn jump(&mut self, base: u16, token: &Token) -> Result<(), AsmError> {
+        let addr = self.here;
+        match self.address(token, FixupKind::Address)? {
+            Some(target) => {
+                if target > 0xFFF {
+                    return Err(self.error(token, format!("Address 0x{:X} is too large", target)));
+                }
+                self.emit_word(base | target as u16, token)
+            }
+            None => {
+                self.fixups
+                    .entry(token.text.clone())
+                    .or_default()
+                    .push(Fixup {
+                        addr,
+                        kind: FixupKind::Address,
+                        token: token.clone(),
+                    });
+                self.emit_word(base, token)
+            }
+        }
+    }
+
+    /// Resolves an address operand, or returns `None` for a name that may
+    /// still be defined as a label.
+    fn address(&self, token: &Token, kind: FixupKind) -> Result<Option<i64>, AsmError> {
+        if let Some(value) = self.lookup(&token.text) {
+            let max = if kind == FixupKind::Long {
+                0xFFFF
+            } else {
+                0xFFF
+            };
+            if !(0..=max).contains(&value) {
+                return Err(self.error(token, format!("Address {} is out of range", value)));
+            }
+            return Ok(Some(value));
+        }
+        if parse_number(&token.text).is_some() || !is_name(&token.text) {
+            return Err(self.error(
+                token,
+                format!("Expected an address, found '{}'", token.text),
+            ));
+        }
+        Ok(None)
+    }
+
+    fn lookup(&self, text: &str) -> Option<i64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|addr| *addr as i64))
+    }
+
+    fn value(&self, token: &Token, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = self
+            .lookup(&token.text)
+            .ok_or_else(|| self.error(token, format!("Undefined name '{}'", token.text)))?;
+        if !(min..=max).contains(&value) {
+            return Err(self.error(
+                token,
+                format!("Value {} is out of range ({} to {})", value, min, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        Ok(self.value(token, -128, 255)? as u8)
+    }
+
+    fn nibble(&self, token: &Token) -> Result<u16, AsmError> {
+        Ok(self.value(token, 0, 15)? as u16)
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn expect_register(&mut self, after: &Token) -> Result<u8, AsmError> {
+        let token = self.next(after)?;
+        self.register(&token.text).ok_or_else(|| {
+            self.error(
+                &token,
+                format!("Expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    fn operand(&self, token: &Token) -> Result<Operand, AsmError> {
+        match self.register(&token.text) {
+            Some(reg) => Ok(Operand::Register(reg)),
+            None => Ok(Operand::Value(self.byte(token)? as i64)),
+        }
+    }
+
+    fn define_label(&mut self, name: &Token, addr: usize) -> Result<(), AsmError> {
+        self.check_name(name)?;
+        if name.text == "main"
+            && addr == PROGRAM_START + 2
+            && self.rom.len() == 2
+            && self.labels.is_empty()
+            && !self.here_used
+        {
+            // main comes first, so the jump to it isn't needed. Labels and
+            // values taken from HERE before it would have to move too, so
+            // the jump stays when there are any.
+            self.rom.clear();
+            self.here = PROGRAM_START;
+            self.fixups.remove("main");
+            return self.define_label(name, PROGRAM_START);
+        }
+
+        self.labels.insert(name.text.clone(), addr);
+        for fixup in self.fixups.remove(&name.text).unwrap_or_default() {
+            let idx = fixup.addr - PROGRAM_START;
+            let word = u16::from_be_bytes([self.rom[idx], self.rom[idx + 1]]);
+            let word = match fixup.kind {
+                FixupKind::Address if addr > 0xFFF => {
+                    return Err(
+                        self.error(&fixup.token, format!("Address 0x{:X} is too large", addr))
+                    );
+                }
+                FixupKind::Address => (word & 0xF000) | addr as u16,
+                FixupKind::Long => addr as u16,
+            };
+            self.patch(fixup.addr, word);
+        }
+        Ok(())
+    }
+
+    fn check_name(&self, name: &Token) -> Result<(), AsmError> {
+        if !is_name(&name.text) || register(&name.text).is_some() {
+            return Err(self.error(name, format!("'{}' is not a valid name", name.text)));
+        }
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return Err(self.error(name, format!("'{}' is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        if let Some(reg) = self.register(&token.text) {
+            return self.register_statement(reg, &token);
+        }
+        if let Some(value) = parse_number(&token.text) {
+            if !(-128..=255).contains(&value) {
+                return Err(self.error(&token, format!("Value {} doesn't fit in a byte", value)));
+            }
+            return self.emit(value as u8, &token);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next(&token)?;
+                let here = self.here;
+                self.define_label(&name, here)?;
+            }
+            ":next" => {
+                let name = self.next(&token)?;
+                let here = self.here;
+                self.define_label(&name, here + 1)?;
+            }
+            ":const" => {
+                let name = self.next(&token)?;
+                let value = self.next(&name)?;
+                let value = self.lookup(&value.text).ok_or_else(|| {
+                    self.error(&value, format!("Undefined name '{}'", value.text))
+                })?;
+                self.check_name(&name)?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next(&token)?;
+                let reg = self.expect_register(&name)?;
+                if !is_name(&name.text) {
+                    return Err(self.error(&name, format!("'{}' is not a valid name", name.text)));
+                }
+                self.aliases.insert(name.text, reg);
+            }
+            ":org" => {
+                let addr = self.next(&token)?;
+                let value = self.value(&addr, PROGRAM_START as i64, MEMORY_SIZE as i64 - 1)?;
+                self.here = value as usize;
+            }
+            ":macro" => self.define_macro(&token)?,
+            ":calc" => {
+                let name = self.next(&token)?;
+                let open = self.expect(&name, "{")?;
+                let body = self.block_body(&open)?;
+                let value = self.calc(&body, &open)?;
+                self.here_used |= body.iter().any(|t| t.text == "HERE");
+                self.check_name(&name)?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let next = self.next(&token)?;
+                let value = if next.text == "{" {
+                    let body = self.block_body(&next)?;
+                    self.calc(&body, &next)?
+                } else {
+                    self.value(&next, -128, 255)?
+                };
+                self.emit(value as u8, &token)?;
+            }
+            ":call" => {
+                let target = self.next(&token)?;
+                self.jump(0x2000, &target)?;
+            }
+            "clear" => self.emit_word(0x00E0, &token)?,
+            "return" | ";" => self.emit_word(0x00EE, &token)?,
+            "exit" => self.emit_word(0x00FD, &token)?,
+            "lores" => self.emit_word(0x00FE, &token)?,
+            "hires" => self.emit_word(0x00FF, &token)?,
+            "scroll-right" => self.emit_word(0x00FB, &token)?,
+            "scroll-left" => self.emit_word(0x00FC, &token)?,
+            "audio" => self.emit_word(0xF002, &token)?,
+            "scroll-down" | "scroll-up" => {
+                let n = self.next(&token)?;
+                let base = if token.text == "scroll-down" {
+                    0x00C0
+                } else {
+                    0x00D0
+                };
+                let word = base | self.nibble(&n)?;
+                self.emit_word(word, &token)?;
+            }
+            "plane" => {
+                let n = self.next(&token)?;
+                let word = 0xF001 | (self.nibble(&n)? << 8);
+                self.emit_word(word, &token)?;
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = self.expect_register(&token)? as u16;
+                let low = match token.text.as_str() {
+                    "bcd" => 0x33,
+                    "saveflags" => 0x75,
+                    _ => 0x85,
+                };
+                self.emit_word(0xF000 | (x << 8) | low, &token)?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register(&token)? as u16;
+                let word = if self.accept("-") {
+                    let y = self.expect_register(&token)? as u16;
+                    let low = if token.text == "save" { 0x2 } else { 0x3 };
+                    0x5000 | (x << 8) | (y << 4) | low
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    0xF000 | (x << 8) | low
+                };
+                self.emit_word(word, &token)?;
+            }
+            "sprite" => {
+                let x = self.expect_register(&token)? as u16;
+                let y = self.expect_register(&token)? as u16;
+                let n = self.next(&token)?;
+                let word = 0xD000 | (x << 8) | (y << 4) | self.nibble(&n)?;
+                self.emit_word(word, &token)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.next(&token)?;
+                let base = match token.text.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                self.jump(base, &target)?;
+            }
+            "i" => self.index_statement(&token)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(&token, ":=")?;
+                let x = self.expect_register(&token)? as u16;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit_word(0xF000 | (x << 8) | low, &token)?;
+            }
+            "if" => {
+                let condition = self.condition(&token)?;
+                let body = self.next(&token)?;
+                match body.text.as_str() {
+                    "then" => self.skip_unless(&condition, &token)?,
+                    "begin" => {
+                        self.skip_if(&condition, &token)?;
+                        let jump = self.here;
+                        self.emit_word(0x1000, &token)?;
+                        self.blocks.push(Block::If { jump, token });
+                    }
+                    _ => return Err(self.error(&body, "Expected 'then' or 'begin'")),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let end_jump = self.here;
+                    self.emit_word(0x1000, &token)?;
+                    let word = self.jump_to(self.here, &token)?;
+                    self.patch(jump, word);
+                    self.blocks.push(Block::Else {
+                        jump: end_jump,
+                        token,
+                    });
+                }
+                _ => return Err(self.error(&token, "'else' without 'if ... begin'")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. } | Block::Else { jump, .. }) => {
+                    let word = self.jump_to(self.here, &token)?;
+                    self.patch(jump, word);
+                }
+                _ => return Err(self.error(&token, "'end' without 'if ... begin'")),
+            },
+            "loop" => {
+                let start = self.here;
+                self.blocks.push(Block::Loop {
+                    start,
+                    breaks: Vec::new(),
+                    token,
+                });
+            }
+            "while" => {
+                let condition = self.condition(&token)?;
+                self.skip_if(&condition, &token)?;
+                let jump = self.here;
+                self.emit_word(0x1000, &token)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return Err(self.error(&token, "'while' outside of a loop")),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    let word = self.jump_to(start, &token)?;
+                    self.emit_word(word, &token)?;
+                    let word = self.jump_to(self.here, &token)?;
+                    for jump in breaks {
+                        self.patch(jump, word);
+                    }
+                }
+                _ => return Err(self.error(&token, "'again' without 'loop'")),
+            },
+            text if self.macros.contains_key(text) => self.expand_macro(&token)?,
+            text if text.starts_with(':') => {
+                return Err(self.error(&token, format!("Unknown directive '{}'", text)));
+            }
+            // Any other name calls the label of that name
+            _ => self.jump(0x2000, &token)?,
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8, token: &Token) -> Result<(), AsmError> {
+        let op = self.next(token)?;
+        let rhs = self.next(&op)?;
+        let x = x as u16;
+        let y = self.register(&rhs.text).map(|y| y as u16);
+        let word = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | (x << 8) | (y << 4),
+            (":=", None) => match rhs.text.as_str() {
+                "random" => {
+                    let mask = self.next(&rhs)?;
+                    0xC000 | (x << 8) | self.byte(&mask)? as u16
+                }
+                "delay" => 0xF007 | (x << 8),
+                "key" => 0xF00A | (x << 8),
+                _ => 0x6000 | (x << 8) | self.byte(&rhs)? as u16,
+            },
+            ("+=", Some(y)) => 0x8004 | (x << 8) | (y << 4),
+            ("+=", None) => 0x7000 | (x << 8) | self.byte(&rhs)? as u16,
+            ("-=", Some(y)) => 0x8005 | (x << 8) | (y << 4),
+            ("-=", None) => 0x7000 | (x << 8) | (self.byte(&rhs)? as u16).wrapping_neg() & 0xFF,
+            ("=-", Some(y)) => 0x8007 | (x << 8) | (y << 4),
+            ("|=", Some(y)) => 0x8001 | (x << 8) | (y << 4),
+            ("&=", Some(y)) => 0x8002 | (x << 8) | (y << 4),
+            ("^=", Some(y)) => 0x8003 | (x << 8) | (y << 4),
+            (">>=", Some(y)) => 0x8006 | (x << 8) | (y << 4),
+            ("<<=", Some(y)) => 0x800E | (x << 8) | (y << 4),
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                return Err(self.error(&rhs, format!("Expected a register, found '{}'", rhs.text)));
+            }
+            _ => return Err(self.error(&op, format!("Unknown operator '{}'", op.text))),
+        };
+        self.emit_word(word, token)
+    }
+
+    fn index_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let op = self.next(token)?;
+        match op.text.as_str() {
+            "+=" => {
+                let x = self.expect_register(&op)? as u16;
+                self.emit_word(0xF01E | (x << 8), token)
+            }
+            ":=" => {
+                let rhs = self.next(&op)?;
+                match rhs.text.as_str() {
+                    "hex" | "bighex" => {
+                        let x = self.expect_register(&rhs)? as u16;
+                        let low = if rhs.text == "hex" { 0x29 } else { 0x30 };
+                        self.emit_word(0xF000 | (x << 8) | low, token)
+                    }
+                    "long" => {
+                        let target = self.next(&rhs)?;
+                        self.emit_word(0xF000, token)?;
+                        let addr = self.here;
+                        match self.address(&target, FixupKind::Long)? {
+                            Some(value) => self.emit_word(value as u16, token),
+                            None => {
+                                self.fixups
+                                    .entry(target.text.clone())
+                                    .or_default()
+                                    .push(Fixup {
+                                        addr,
+                                        kind: FixupKind::Long,
+                                        token: target,
+                                    });
+                                self.emit_word(0, token)
+                            }
+                        }
+                    }
+                    _ => self.jump(0xA000, &rhs),
+                }
+            }
+            _ => Err(self.error(&op, format!("Unknown operator '{}'", op.text))),
+        }
+    }
+
+    fn condition(&mut self, after: &Token) -> Result<Condition, AsmError> {
+        let x = self.expect_register(after)?;
+        let op = self.next(after)?;
+        let condition = match op.text.as_str() {
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let rhs = self.next(&op)?;
+                let rhs = self.operand(&rhs)?;
+                match op.text.as_str() {
+                    "==" => Condition::Eq(x, rhs),
+                    "!=" => Condition::Ne(x, rhs),
+                    "<" => Condition::Lt(x, rhs),
+                    ">" => Condition::Gt(x, rhs),
+                    "<=" => Condition::Le(x, rhs),
+                    _ => Condition::Ge(x, rhs),
+                }
+            }
+            _ => return Err(self.error(&op, format!("Unknown condition '{}'", op.text))),
+        };
+        Ok(condition)
+    }
+
+    /// Emits code that skips the next instruction when `condition` holds.
+    fn skip_if(&mut self, condition: &Condition, token: &Token) -> Result<(), AsmError> {
+        self.skip(condition, true, token)
+    }
+
+    /// Emits code that skips the next instruction unless `condition` holds.
+    fn skip_unless(&mut self, condition: &Condition, token: &Token) -> Result<(), AsmError> {
+        self.skip(condition, false, token)
+    }
+
+    fn skip(&mut self, condition: &Condition, when: bool, token: &Token) -> Result<(), AsmError> {
+        let (x, rhs, equal) = match *condition {
+            Condition::Eq(x, rhs) => (x, rhs, when),
+            Condition::Ne(x, rhs) => (x, rhs, !when),
+            Condition::Key(x) => {
+                let low = if when { 0x9E } else { 0xA1 };
+                return self.emit_word(0xE000 | ((x as u16) << 8) | low, token);
+            }
+            Condition::NotKey(x) => {
+                let low = if when { 0xA1 } else { 0x9E };
+                return self.emit_word(0xE000 | ((x as u16) << 8) | low, token);
+            }
+            // Ordering comparisons subtract into VF and test the borrow flag,
+            // which is 1 when the subtraction didn't borrow
+            Condition::Lt(x, rhs) => {
+                self.subtract(Operand::Register(x), rhs, token)?;
+                (0xF, Operand::Value(0), when)
+            }
+            Condition::Ge(x, rhs) => {
+                self.subtract(Operand::Register(x), rhs, token)?;
+                (0xF, Operand::Value(1), when)
+            }
+            Condition::Gt(x, rhs) => {
+                self.subtract(rhs, Operand::Register(x), token)?;
+                (0xF, Operand::Value(0), when)
+            }
+            Condition::Le(x, rhs) => {
+                self.subtract(rhs, Operand::Register(x), token)?;
+                (0xF, Operand::Value(1), when)
+            }
+        };
+        let x = (x as u16) << 8;
+        let word = match (rhs, equal) {
+            (Operand::Value(nn), true) => 0x3000 | x | nn as u16,
+            (Operand::Value(nn), false) => 0x4000 | x | nn as u16,
+            (Operand::Register(y), true) => 0x5000 | x | ((y as u16) << 4),
+            (Operand::Register(y), false) => 0x9000 | x | ((y as u16) << 4),
+        };
+        self.emit_word(word, token)
+    }
+
+    /// Emits `vf := lhs - rhs`, leaving the borrow flag in VF.
+    fn subtract(&mut self, lhs: Operand, rhs: Operand, token: &Token) -> Result<(), AsmError> {
+        let (first, second) = match (lhs, rhs) {
+            (Operand::Register(a), Operand::Register(b)) => {
+                (0x8F00 | ((a as u16) << 4), 0x8F05 | ((b as u16) << 4))
+            }
+            (Operand::Register(a), Operand::Value(b)) => {
+                (0x6F00 | (b as u16 & 0xFF), 0x8F07 | ((a as u16) << 4))
+            }
+            (Operand::Value(a), Operand::Register(b)) => {
+                (0x6F00 | (a as u16 & 0xFF), 0x8F05 | ((b as u16) << 4))
+            }
+            (Operand::Value(_), Operand::Value(_)) => {
+                unreachable!("conditions start with a register")
+            }
+        };
+        self.emit_word(first, token)?;
+        self.emit_word(second, token)
+    }
+
+    /// Reads the tokens up to the `}` matching an already consumed `{`.
+    fn block_body(&mut self, open: &Token) -> Result<Vec<Token>, AsmError> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        while let Some(token) = self.tokens.pop_front() {
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+        Err(self.error(open, "'{' is never closed"))
+    }
+
+    fn define_macro(&mut self, token: &Token) -> Result<(), AsmError> {
+        let name = self.next(token)?;
+        let mut params = Vec::new();
+        loop {
+            let param = self.next(&name)?;
+            if param.text == "{" {
+                let body = self.block_body(&param)?;
+                self.macros.insert(name.text, Macro { params, body });
+                return Ok(());
+            }
+            params.push(param.text);
+        }
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(token, "Too many macro expansions"));
+        }
+        let mac = self.macros[&token.text].clone();
+        let mut args = HashMap::new();
+        for param in &mac.params {
+            let arg = self.next(token)?;
+            args.insert(param.clone(), arg.text);
+        }
+        for body_token in mac.body.iter().rev() {
+            let mut body_token = body_token.clone();
+            if let Some(arg) = args.get(&body_token.text) {
+                body_token.text = arg.clone();
+            }
+            self.tokens.push_front(body_token);
+        }
+        Ok(())
+    }
+
+    /// Evaluates a `:calc` expression. Like Octo, operators have no
+    /// precedence and are applied right to left, so parentheses are needed
+    /// for anything else.
+    fn calc(&self, tokens: &[Token], open: &Token) -> Result<i64, AsmError> {
+        let mut pos = 0;
+        let value = self.calc_expr(tokens, &mut pos, open)?;
+        match tokens.get(pos) {
+            Some(token) => Err(self.error(token, "Unexpected token in expression")),
+            None => Ok(value),
+        }
+    }
+
+    fn calc_expr(&self, tokens: &[Token], pos: &mut usize, open: &Token) -> Result<i64, AsmError> {
+        let lhs = self.calc_term(tokens, pos, open)?;
+        let op = match tokens.get(*pos) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok(lhs),
+        };
+        *pos += 1;
+        let rhs = self.calc_expr(tokens, pos, open)?;
+        let value = match op.text.as_str() {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err(self.error(op, "Division by zero")),
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "&" => lhs & rhs,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => (lhs < rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            _ => return Err(self.error(op, format!("Unknown operator '{}'", op.text))),
+        };
+        Ok(value)
+    }
+
+    fn calc_term(&self, tokens: &[Token], pos: &mut usize, open: &Token) -> Result<i64, AsmError> {
+        let token = tokens
+            .get(*pos)
+            .ok_or_else(|| self.error(open, "Expected an expression"))?;
+        *pos += 1;
+        match token.text.as_str() {
+            "-" => Ok(self.calc_term(tokens, pos, open)?.wrapping_neg()),
+            "~" => Ok(!self.calc_term(tokens, pos, open)?),
+            "!" => Ok((self.calc_term(tokens, pos, open)? == 0) as i64),
+            "(" => {
+                let value = self.calc_expr(tokens, pos, open)?;
+                match tokens.get(*pos) {
+                    Some(close) if close.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.error(token, "'(' is never closed")),
+                }
+            }
+            "HERE" => Ok(self.here as i64),
+            text => self
+                .lookup(text)
+                .ok_or_else(|| self.error(token, format!("Undefined name '{}'", text))),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let mut start = None;
+        let chars: Vec<char> = line.chars().collect();
+        for idx in 0..=chars.len() {
+            let c = chars.get(idx).copied();
+            match (c, start) {
+                (Some('#'), None) => break,
+                (Some(c), None) if !c.is_whitespace() => start = Some(idx),
+                (None, Some(begin)) | (Some(_), Some(begin))
+                    if c.is_none_or(char::is_whitespace) =>
+                {
+                    tokens.push_back(Token {
+                        text: chars[begin..idx].iter().collect(),
+                        line: line_idx + 1,
+                        column: begin + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        disasm::{Disassembly, Syntax},
+        Machine,
+    };
+
+    fn bytes(source: &str) -> Vec<u8> {
+        compile("test.8o", source)
+            .unwrap_or_else(|err| panic!("{}", err))
+            .bytes
+    }
+
+    #[test]
+    fn compiles_statements() {
+        assert_eq!(
+            bytes(": main  clear  v0 := 5  v1 += v0  v2 -= 1  i := hex v1  sprite v0 v1 5"),
+            [0x00, 0xE0, 0x60, 0x05, 0x81, 0x04, 0x72, 0xFF, 0xF1, 0x29, 0xD0, 0x15]
+        );
+    }
+
+    #[test]
+    fn jumps_to_main_when_it_is_not_first() {
+        assert_eq!(
+            bytes(": sprite 0xF0 0b10010000  : main i := sprite"),
+            [0x12, 0x04, 0xF0, 0x90, 0xA2, 0x02]
+        );
+    }
+
+    #[test]
+    fn keeps_the_jump_to_main_for_labels_before_it() {
+        assert_eq!(bytes(": start : main jump start"), [0x12, 0x02, 0x12, 0x02]);
+        assert_eq!(
+            bytes(":calc origin { HERE } : main jump origin"),
+            [0x12, 0x02, 0x12, 0x02]
+        );
+        assert_eq!(bytes(": main jump main"), [0x12, 0x00]);
+    }
+
+    #[test]
+    fn resolves_forward_references() {
+        assert_eq!(
+            bytes(": main draw i := long data  : draw return  : data 1"),
+            [0x22, 0x06, 0xF0, 0x00, 0x02, 0x08, 0x00, 0xEE, 0x01]
+        );
+    }
+
+    #[test]
+    fn compiles_conditionals() {
+        assert_eq!(
+            bytes(": main if v0 == 3 then v1 := 1  if v0 key then clear"),
+            [0x40, 0x03, 0x61, 0x01, 0xE0, 0xA1, 0x00, 0xE0]
+        );
+        assert_eq!(
+            bytes(": main if v0 != v1 begin clear else return end exit"),
+            [0x90, 0x10, 0x12, 0x08, 0x00, 0xE0, 0x12, 0x0A, 0x00, 0xEE, 0x00, 0xFD]
+        );
+    }
+
+    #[test]
+    fn compiles_loops() {
+        assert_eq!(
+            bytes(": main loop v0 += 1 while v0 != 10 again"),
+            [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn supports_macros_calc_and_aliases() {
+        let source = "
+            :alias x v3
+            :const SPEED 2
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :macro step reg amount { reg += amount }
+            : main step x DOUBLE step x SPEED
+        ";
+        assert_eq!(bytes(source), [0x73, 0x06, 0x73, 0x02]);
+    }
+
+    #[test]
+    fn supports_next_and_org() {
+        assert_eq!(
+            bytes(": main :next target v0 := 0  i := target  :org 0x208 exit"),
+            [0x60, 0x00, 0xA2, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFD]
+        );
+    }
+
+    #[test]
+    fn rejects_blocks_past_the_jump_range() {
+        for source in [
+            ": main if v0 == 1 begin :org 0x1000 clear end",
+            ": main if v0 == 1 begin clear :org 0x1000 else clear end",
+            ": main :org 0x1000 loop clear again",
+            ": main loop while v0 != 1 :org 0x1000 again",
+        ] {
+            let err = compile("test.8o", source).unwrap_err();
+            assert!(err.message.ends_with("is too large"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn compiles_disassembler_output() {
+        let rom = [
+            0x60, 0x05, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x02, 0x22, 0x1C, 0xA2, 0x1E, 0xD0, 0x12,
+            0xF0, 0x00, 0x02, 0x1E, 0xF0, 0x29, 0xF0, 0x33, 0xF3, 0x55, 0xE2, 0xA1, 0x00, 0xFD,
+            0x00, 0xEE, 0xF0, 0x90,
+        ];
+        let listing = Disassembly::new(&rom).listing(Syntax::Octo);
+        assert_eq!(bytes(&listing), rom);
+    }
+
+    #[test]
+    fn reports_positions() {
+        let err = compile("test.8o", ": main\n  v0 := nope").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9));
+        assert_eq!(err.to_string(), "test.8o:2:9: Undefined name 'nope'");
+
+        let err = compile("test.8o", ": main\n  jump missing").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 8));
+
+        let err = compile("test.8o", ": main\n loop").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 2));
+
+        let err = compile("test.8o", "clear").unwrap_err();
+        assert_eq!(err.message, "Program is missing a 'main' label");
+    }
+
+    #[test]
+    fn runs_compiled_program() {
+        let source = "
+            : main
+                v0 := 0
+                v1 := 0
+                loop
+                    v0 += 1
+                    if v0 > 5 then v1 += 1
+                    while v0 < 10
+                again
+                v0 := 1
+                if v0 <= 1 begin v2 := 1 else v2 := 2 end
+                if v0 >= 2 then v3 := 1
+                exit
+        ";
+        let mut machine = Machine::default();
+        machine.load_rom(&bytes(source)).unwrap();
+        while machine.running() {
+            machine.step().unwrap();
+        }
+        let cpu = machine.cpu();
+        assert_eq!(cpu.register(0), 1);
+        assert_eq!(cpu.register(1), 5);
+        assert_eq!(cpu.register(2), 1);
+        assert_eq!(cpu.register(3), 0);
+    }
+}
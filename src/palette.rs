@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+use egui_sdl2_gl::egui::Color32;
+
+use reimu::gpu::COLOR_COUNT;
+
+/// Colours used to render each XO-CHIP plane combination.
+///
+/// Index 0 is the background, 1 is plane 1 only, 2 is plane 2 only and
+/// 3 is where both planes overlap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Color32; COLOR_COUNT],
+}
+
+impl Palette {
+    pub fn color(&self, index: u8) -> Color32 {
+        self.colors[index as usize % COLOR_COUNT]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: [
+                Color32::BLACK,
+                Color32::WHITE,
+                Color32::from_rgb(0xAA, 0xAA, 0xAA),
+                Color32::from_rgb(0x55, 0x55, 0x55),
+            ],
+        }
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parses a comma-separated list of two or four `RRGGBB` colours. With two
+    /// colours the plane 2 and overlap colours keep their defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        if parts.len() != 2 && parts.len() != COLOR_COUNT {
+            return Err(format!(
+                "Expected 2 or {} colours, got {}",
+                COLOR_COUNT,
+                parts.len()
+            ));
+        }
+        let mut palette = Palette::default();
+        for (color, part) in palette.colors.iter_mut().zip(parts) {
+            *color = parse_color(part)?;
+        }
+        Ok(palette)
+    }
+}
+
+fn parse_color(s: &str) -> Result<Color32, String> {
+    let hex = s.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or_else(|| format!("Invalid colour '{}', expected RRGGBB", s))?;
+    Ok(Color32::from_rgb(
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_four_colours() {
+        let palette: Palette = "#000000,ffffff,FF0000,00ff00".parse().unwrap();
+        assert_eq!(palette.color(2), Color32::from_rgb(0xFF, 0, 0));
+        assert_eq!(palette.color(3), Color32::from_rgb(0, 0xFF, 0));
+    }
+
+    #[test]
+    fn parses_two_colours() {
+        let palette: Palette = "112233,445566".parse().unwrap();
+        assert_eq!(palette.color(0), Color32::from_rgb(0x11, 0x22, 0x33));
+        assert_eq!(palette.color(2), Palette::default().color(2));
+    }
+
+    #[test]
+    fn rejects_bad_colour() {
+        assert!("000000,fffff".parse::<Palette>().is_err());
+        assert!("000000".parse::<Palette>().is_err());
+    }
+}
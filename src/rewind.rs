@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+
+use crate::{
+    savestate::{SaveState, SaveStateError},
+    Machine,
+};
+
+pub const DEFAULT_BUDGET: usize = 16 * 1024 * 1024;
+
+/// Ring buffer of machine snapshots taken at frame boundaries.
+///
+/// Only the newest snapshot is kept in full. Every older snapshot is stored as
+/// the run-length encoded XOR between it and the snapshot after it, which is
+/// tiny since little changes from one frame to the next. When the buffer
+/// grows past its memory budget the oldest snapshots are dropped.
+pub struct RewindBuffer {
+    head: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+    budget: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            head: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            budget,
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Number of snapshots that can currently be rewound to.
+    pub fn len(&self) -> usize {
+        match self.head {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// Approximate memory used by the stored snapshots, in bytes.
+    pub fn used(&self) -> usize {
+        self.head.as_ref().map_or(0, Vec::len) + self.delta_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.head = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Records the current state of `machine`.
+    pub fn push(&mut self, machine: &Machine) {
+        self.push_bytes(machine.save_state().to_bytes());
+    }
+
+    /// Removes the newest snapshot and restores `machine` to it. Returns
+    /// `Ok(false)` when there is no history left.
+    pub fn rewind(&mut self, machine: &mut Machine) -> Result<bool, SaveStateError> {
+        match self.pop_bytes() {
+            Some(bytes) => {
+                machine.load_state(&SaveState::from_bytes(&bytes)?)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn push_bytes(&mut self, state: Vec<u8>) {
+        if let Some(head) = self.head.take() {
+            let delta = encode_delta(&state, &head);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.head = Some(state);
+        self.evict();
+    }
+
+    fn pop_bytes(&mut self) -> Option<Vec<u8>> {
+        let head = self.head.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.len();
+            self.head = Some(decode_delta(&head, &delta));
+        }
+        Some(head)
+    }
+
+    fn evict(&mut self) {
+        while self.used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => {
+                    self.head = None;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET)
+    }
+}
+
+/// Encodes `target` relative to `base` as the XOR of the two, compressed as
+/// a sequence of `(zero run, literal length, literal bytes)` records. The
+/// target length is stored up front in case the two differ.
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = (0..target.len())
+        .map(|idx| target[idx] ^ base.get(idx).copied().unwrap_or(0))
+        .collect();
+
+    let mut out = Vec::new();
+    write_varint(&mut out, target.len());
+    let mut idx = 0;
+    while idx < xor.len() {
+        let zeros = xor[idx..].iter().take_while(|b| **b == 0).count();
+        idx += zeros;
+        let literal = xor[idx..].iter().take_while(|b| **b != 0).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literal);
+        out.extend_from_slice(&xor[idx..(idx + literal)]);
+        idx += literal;
+    }
+    out
+}
+
+fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut input = delta;
+    let len = read_varint(&mut input);
+    let mut out: Vec<u8> = (0..len)
+        .map(|idx| base.get(idx).copied().unwrap_or(0))
+        .collect();
+    let mut idx = 0;
+    while !input.is_empty() {
+        idx += read_varint(&mut input);
+        let literal = read_varint(&mut input);
+        for (offset, byte) in input[..literal].iter().enumerate() {
+            out[idx + offset] ^= byte;
+        }
+        input = &input[literal..];
+        idx += literal;
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trips() {
+        let base = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        let target = vec![0, 1, 9, 3, 4, 5, 8, 7, 1];
+        let delta = encode_delta(&base, &target);
+        assert_eq!(decode_delta(&base, &delta), target);
+    }
+
+    #[test]
+    fn unchanged_state_is_tiny() {
+        let state = vec![0xAB; 70_000];
+        let delta = encode_delta(&state, &state);
+        assert!(delta.len() < 8);
+    }
+
+    #[test]
+    fn pops_in_reverse_order() {
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        for value in 0..5_u8 {
+            buffer.push_bytes(vec![value; 16]);
+        }
+        assert_eq!(buffer.len(), 5);
+        for value in (0..5_u8).rev() {
+            assert_eq!(buffer.pop_bytes(), Some(vec![value; 16]));
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop_bytes(), None);
+    }
+
+    #[test]
+    fn evicts_oldest_when_over_budget() {
+        let mut buffer = RewindBuffer::new(64);
+        for value in 0..10_u8 {
+            buffer.push_bytes(vec![value; 32]);
+        }
+        assert!(buffer.used() <= 64);
+        assert!(buffer.len() < 10);
+        assert_eq!(buffer.pop_bytes(), Some(vec![9; 32]));
+    }
+
+    #[test]
+    fn rewinds_machine() {
+        let mut machine = Machine::default();
+        machine.set_instructions_per_frame(1);
+        machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut buffer = RewindBuffer::default();
+        for _ in 0..4 {
+            machine.run_frame().unwrap();
+            buffer.push(&machine);
+        }
+        machine.run_frame().unwrap();
+        for _ in 0..3 {
+            assert!(buffer.rewind(&mut machine).unwrap());
+        }
+        assert_eq!(machine.cpu().register(0), 1);
+        assert_eq!(machine.cpu().pc, 0x200);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::Cpu;
+
+const MAGIC: [u8; 4] = *b"RIMU";
+
+/// Version of the save state format. Bump whenever the serialized layout of
+/// [`Cpu`] or [`Header`] changes.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Returns the SHA-1 of a ROM image as a lowercase hex string.
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+/// Identifies what a save state was made from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub magic: [u8; 4],
+    pub version: u16,
+    pub rom_hash: String,
+}
+
+/// A snapshot of a running machine, tied to the ROM it was taken from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveState {
+    pub header: Header,
+    pub(crate) cpu: Cpu,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    InvalidFormat(String),
+    UnsupportedVersion(u16),
+    RomMismatch { expected: String, found: String },
+}
+
+impl SaveState {
+    pub(crate) fn new(rom_hash: String, cpu: Cpu) -> Self {
+        Self {
+            header: Header {
+                magic: MAGIC,
+                version: FORMAT_VERSION,
+                rom_hash,
+            },
+            cpu,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = bincode::serialize(&self.header).expect("header serializes");
+        bytes.extend(bincode::serialize(&self.cpu).expect("CPU state serializes"));
+        bytes
+    }
+
+    /// Decodes a save state, checking the header before decoding the rest so
+    /// states from other format versions are reported as such.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        let mut reader = bytes;
+        let header: Header = bincode::deserialize_from(&mut reader)
+            .map_err(|err| SaveStateError::InvalidFormat(err.to_string()))?;
+        if header.magic != MAGIC {
+            return Err(SaveStateError::InvalidFormat(
+                "not a reimu save state".to_string(),
+            ));
+        }
+        if header.version != FORMAT_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(header.version));
+        }
+        let cpu: Cpu = bincode::deserialize_from(&mut reader)
+            .map_err(|err| SaveStateError::InvalidFormat(err.to_string()))?;
+        cpu.validate().map_err(SaveStateError::InvalidFormat)?;
+        Ok(Self { header, cpu })
+    }
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::InvalidFormat(reason) => write!(f, "Invalid save state: {}", reason),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported save state version {} (expected {})",
+                version, FORMAT_VERSION
+            ),
+            SaveStateError::RomMismatch { expected, found } => write!(
+                f,
+                "Save state was made for a different ROM ({}, loaded ROM is {})",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_rom() {
+        assert_eq!(rom_hash(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut cpu = Cpu::new();
+        cpu.load(&[0x60, 0x42]).unwrap();
+        cpu.step().unwrap();
+        let state = SaveState::new(rom_hash(&[0x60, 0x42]), cpu);
+        let decoded = SaveState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(decoded.header, state.header);
+        assert_eq!(decoded.cpu.register(0), 0x42);
+        assert_eq!(decoded.cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn keeps_the_exit_code() {
+        let mut cpu = Cpu::new();
+        cpu.set_debug_exit(true);
+        cpu.load(&[0x0F, 0x07]).unwrap();
+        cpu.step().unwrap();
+        let state = SaveState::new(rom_hash(&[0x0F, 0x07]), cpu);
+        let decoded = SaveState::from_bytes(&state.to_bytes()).unwrap();
+        assert!(!decoded.cpu.running());
+        assert_eq!(decoded.cpu.exit_code(), Some(7));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut state = SaveState::new(rom_hash(&[]), Cpu::new());
+        state.header.version = FORMAT_VERSION + 1;
+        let result = SaveState::from_bytes(&state.to_bytes());
+        assert_eq!(
+            result.err(),
+            Some(SaveStateError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_state() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x5A5A_5A5A;
+        let mut bytes = SaveState::new(rom_hash(&[]), cpu).to_bytes();
+        // Find the stack pointer by its value, and check that it is the only
+        // thing wrong before setting it just past the end of the stack
+        let marker = 0x5A5A_5A5Au64.to_le_bytes();
+        let pos = bytes
+            .windows(marker.len())
+            .position(|window| window == marker)
+            .unwrap();
+        bytes[pos..(pos + 8)].copy_from_slice(&0u64.to_le_bytes());
+        assert!(SaveState::from_bytes(&bytes).is_ok());
+
+        bytes[pos..(pos + 8)].copy_from_slice(&17u64.to_le_bytes());
+        let result = SaveState::from_bytes(&bytes);
+        assert!(matches!(result, Err(SaveStateError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let result = SaveState::from_bytes(b"nope");
+        assert!(matches!(result, Err(SaveStateError::InvalidFormat(_))));
+    }
+}
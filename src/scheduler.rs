@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use crate::util;
+
+/// Upper bound on frames emulated in one update, so a stalled host (e.g. a
+/// window being dragged) doesn't make the emulator try to catch up forever.
+const MAX_FRAMES_PER_UPDATE: usize = 8;
+
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 8.0;
+
+/// Fixed-timestep clock that decides how many emulated frames are due,
+/// independently of how fast the host renders.
+pub struct Scheduler {
+    frame_duration: Duration,
+    accumulator: Duration,
+    last_update: Instant,
+    speed: f64,
+}
+
+impl Scheduler {
+    pub fn new(fps: usize) -> Self {
+        Self {
+            frame_duration: Duration::from_nanos(util::ns_per_frame(fps)),
+            accumulator: Duration::ZERO,
+            last_update: Instant::now(),
+            speed: 1.0,
+        }
+    }
+
+    /// Sets the emulation speed multiplier, where 1.0 is real time.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Returns the number of frames that should be emulated now.
+    pub fn frames_due(&mut self) -> usize {
+        let now = Instant::now();
+        let elapsed = now - self.last_update;
+        self.last_update = now;
+        self.advance(elapsed)
+    }
+
+    /// Discards any accumulated time, e.g. after being paused.
+    pub fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+        self.last_update = Instant::now();
+    }
+
+    fn advance(&mut self, elapsed: Duration) -> usize {
+        self.accumulator += elapsed.mul_f64(self.speed);
+        let mut frames = 0;
+        while self.accumulator >= self.frame_duration {
+            self.accumulator -= self.frame_duration;
+            frames += 1;
+        }
+        if frames > MAX_FRAMES_PER_UPDATE {
+            self.accumulator = Duration::ZERO;
+            frames = MAX_FRAMES_PER_UPDATE;
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_partial_frames() {
+        let mut scheduler = Scheduler::new(60);
+        let frame = scheduler.frame_duration;
+        assert_eq!(scheduler.advance(frame / 2), 0);
+        assert_eq!(scheduler.advance(frame - frame / 2), 1);
+        assert_eq!(scheduler.advance(frame * 3), 3);
+    }
+
+    #[test]
+    fn applies_speed() {
+        let mut scheduler = Scheduler::new(60);
+        let frame = scheduler.frame_duration;
+        scheduler.set_speed(2.0);
+        assert_eq!(scheduler.advance(frame), 2);
+        scheduler.set_speed(0.5);
+        assert_eq!(scheduler.advance(frame), 0);
+        assert_eq!(scheduler.advance(frame * 2), 1);
+    }
+
+    #[test]
+    fn caps_catch_up() {
+        let mut scheduler = Scheduler::new(60);
+        assert_eq!(
+            scheduler.advance(Duration::from_secs(10)),
+            MAX_FRAMES_PER_UPDATE
+        );
+        assert_eq!(scheduler.advance(Duration::ZERO), 0);
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use reimu::{savestate::SaveState, Machine};
+
+pub const SLOT_COUNT: u8 = 10;
+
+/// Save states live next to the ROM, as `<rom>.s<slot>`.
+pub fn path(rom: &Path, slot: u8) -> PathBuf {
+    let mut name = rom.as_os_str().to_owned();
+    name.push(format!(".s{}", slot));
+    PathBuf::from(name)
+}
+
+pub fn save(machine: &Machine, rom: &Path, slot: u8) -> Result<PathBuf> {
+    let path = path(rom, slot);
+    fs::write(&path, machine.save_state().to_bytes())
+        .with_context(|| format!("Failed to write save state '{}'", path.display()))?;
+    Ok(path)
+}
+
+pub fn load(machine: &mut Machine, rom: &Path, slot: u8) -> Result<PathBuf> {
+    let path = path(rom, slot);
+    let bytes = fs::read(&path)
+        .with_context(|| format!("Failed to read save state '{}'", path.display()))?;
+    let state = SaveState::from_bytes(&bytes)?;
+    machine.load_state(&state)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_states_next_to_rom() {
+        let path = path(Path::new("roms/pong.ch8"), 3);
+        assert_eq!(path, PathBuf::from("roms/pong.ch8.s3"));
+    }
+}
@@ -0,0 +1,425 @@
+use egui_sdl2_gl::egui::{
+    self, vec2, Color32, CtxRef, Rect, Response, RichText, Sense, Stroke, Ui,
+};
+use reimu::cpu::{Cpu, Op, MEMORY_SIZE};
+
+use crate::palette::Palette;
+
+/// Instructions searched ahead of the PC for the next `DXYN`.
+const DRAW_SEARCH_LIMIT: usize = 64;
+
+const SHEET_COLUMNS: usize = 8;
+const SHEET_ROWS: usize = 8;
+const SHEET_ZOOM: f32 = 3.0;
+/// Width in pixels of the screen preview showing where a sprite lands.
+const PREVIEW_WIDTH: f32 = 256.0;
+
+const GRID_COLOR: Color32 = Color32::from_rgb(0x40, 0x40, 0x40);
+const TARGET_COLOR: Color32 = Color32::from_rgb(0xE0, 0x40, 0x40);
+const SELECTED_COLOR: Color32 = Color32::from_rgb(0x60, 0xA0, 0xE0);
+
+/// Dimensions of a sprite as `DXYN` draws it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    /// 8 pixels wide and this many rows tall (`DXYN` with N > 0).
+    Narrow(usize),
+    /// The SUPER-CHIP 16x16 sprite drawn by `DXY0`.
+    Large,
+}
+
+impl Shape {
+    fn for_n(n: u8) -> Self {
+        if n == 0 {
+            Shape::Large
+        } else {
+            Shape::Narrow(n as usize)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            Shape::Narrow(_) => 8,
+            Shape::Large => 16,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Shape::Narrow(height) => *height,
+            Shape::Large => 16,
+        }
+    }
+
+    /// Bytes of sprite data per plane.
+    pub fn len(&self) -> usize {
+        self.width() / 8 * self.height()
+    }
+}
+
+/// Colour indices of every pixel of a sprite at `addr`, row by row, as it
+/// would be drawn with `planes` selected. Data past the end of memory reads
+/// as zero.
+fn sprite_pixels(memory: &[u8], addr: usize, shape: Shape, planes: u8) -> Vec<u8> {
+    let (width, height) = (shape.width(), shape.height());
+    let mut pixels = vec![0; width * height];
+    let selected = (0..2).map(|idx| 1u8 << idx).filter(|p| planes & p != 0);
+    for (idx, plane) in selected.enumerate() {
+        let start = addr + idx * shape.len();
+        for (pixel, color) in pixels.iter_mut().enumerate() {
+            let bit = pixel % width;
+            let byte = start + pixel / width * (width / 8) + bit / 8;
+            let set = memory
+                .get(byte)
+                .is_some_and(|value| value & (0x80 >> (bit % 8)) != 0);
+            if set {
+                *color |= plane;
+            }
+        }
+    }
+    pixels
+}
+
+/// The first `DXYN` at or after the PC in program order, within
+/// [`DRAW_SEARCH_LIMIT`] instructions, as its address and X, Y and N operands.
+fn next_draw(cpu: &Cpu) -> Option<(usize, u8, u8, u8)> {
+    let mut addr = cpu.pc;
+    for _ in 0..DRAW_SEARCH_LIMIT {
+        if addr + 1 >= MEMORY_SIZE {
+            break;
+        }
+        let op = cpu.op_at(addr);
+        if let Op::Draw { x, y, n } = op {
+            return Some((addr, x, y, n));
+        }
+        addr += op.size();
+    }
+    None
+}
+
+/// Paints a sprite's pixels with `zoom` screen pixels per sprite pixel.
+fn paint_pixels(
+    ui: &mut Ui,
+    pixels: &[u8],
+    shape: Shape,
+    zoom: f32,
+    palette: &Palette,
+) -> Response {
+    let size = vec2(shape.width() as f32, shape.height() as f32) * zoom;
+    let (rect, response) = ui.allocate_exact_size(size, Sense::click());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0.0, palette.color(0));
+    for (idx, color) in pixels.iter().enumerate() {
+        if *color == 0 {
+            continue;
+        }
+        let x = (idx % shape.width()) as f32 * zoom;
+        let y = (idx / shape.width()) as f32 * zoom;
+        let pixel = Rect::from_min_size(rect.min + vec2(x, y), vec2(zoom, zoom));
+        painter.rect_filled(pixel, 0.0, palette.color(*color));
+    }
+    response
+}
+
+/// What the sprite panel is showing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Anchor {
+    /// Whatever `I` points at.
+    AddressRegister,
+    Fixed(usize),
+}
+
+/// State of the sprite window between frames.
+pub struct SpriteView {
+    anchor: Anchor,
+    address: String,
+    height: usize,
+    large: bool,
+    zoom: f32,
+    sheet_offset: usize,
+    sheet_height: usize,
+    sheet_large: bool,
+    error: Option<String>,
+}
+
+impl SpriteView {
+    pub fn new() -> Self {
+        Self {
+            anchor: Anchor::AddressRegister,
+            address: String::new(),
+            height: 8,
+            large: false,
+            zoom: 8.0,
+            sheet_offset: 0x200,
+            sheet_height: 8,
+            sheet_large: false,
+            error: None,
+        }
+    }
+
+    fn shape(&self) -> Shape {
+        if self.large {
+            Shape::Large
+        } else {
+            Shape::Narrow(self.height)
+        }
+    }
+
+    fn sheet_shape(&self) -> Shape {
+        if self.sheet_large {
+            Shape::Large
+        } else {
+            Shape::Narrow(self.sheet_height)
+        }
+    }
+
+    fn sheet_page(&self) -> usize {
+        self.sheet_shape().len() * SHEET_COLUMNS * SHEET_ROWS
+    }
+
+    pub fn show(&mut self, ctx: &CtxRef, cpu: &Cpu, palette: &Palette) {
+        egui::Window::new("Sprites")
+            .default_width(300.0)
+            .show(ctx, |ui| {
+                self.ui_sprite(ui, cpu, palette);
+                ui.separator();
+                self.ui_next_draw(ui, cpu, palette);
+                ui.separator();
+                egui::CollapsingHeader::new("Sprite sheet")
+                    .default_open(false)
+                    .show(ui, |ui| self.ui_sheet(ui, cpu, palette));
+            });
+    }
+
+    fn ui_sprite(&mut self, ui: &mut Ui, cpu: &Cpu, palette: &Palette) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.anchor, Anchor::AddressRegister, "I");
+            let fixed = matches!(self.anchor, Anchor::Fixed(_));
+            if ui.radio(fixed, "Address").clicked() && !fixed {
+                self.apply_address(cpu);
+            }
+            let field = ui.add(
+                egui::TextEdit::singleline(&mut self.address)
+                    .hint_text("0300")
+                    .desired_width(50.0),
+            );
+            if field.lost_focus() {
+                self.apply_address(cpu);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.large, "16x16");
+            ui.add_enabled(
+                !self.large,
+                egui::Slider::new(&mut self.height, 1..=15).text("rows"),
+            );
+        });
+        ui.add(egui::Slider::new(&mut self.zoom, 2.0..=16.0).text("zoom"));
+        if let Some(err) = &self.error {
+            ui.colored_label(Color32::RED, err);
+        }
+
+        let addr = match self.anchor {
+            Anchor::AddressRegister => cpu.address_register,
+            Anchor::Fixed(addr) => addr,
+        };
+        let shape = self.shape();
+        ui.label(format!(
+            "{:04X}-{:04X}",
+            addr,
+            (addr + shape.len()).saturating_sub(1)
+        ));
+        let pixels = sprite_pixels(&cpu.memory, addr, shape, 1);
+        paint_pixels(ui, &pixels, shape, self.zoom, palette);
+    }
+
+    fn apply_address(&mut self, cpu: &Cpu) {
+        if self.address.trim().is_empty() {
+            self.address = format!("{:04X}", cpu.address_register);
+        }
+        match crate::parse_address(&self.address) {
+            Ok(addr) => {
+                self.error = None;
+                self.anchor = Anchor::Fixed(addr);
+            }
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    fn ui_next_draw(&mut self, ui: &mut Ui, cpu: &Cpu, palette: &Palette) {
+        let (addr, x, y, n) = match next_draw(cpu) {
+            Some(draw) => draw,
+            None => {
+                ui.label(
+                    RichText::new(format!(
+                        "No DXYN within {} instructions of the PC",
+                        DRAW_SEARCH_LIMIT
+                    ))
+                    .weak(),
+                );
+                return;
+            }
+        };
+        let gpu = cpu.gpu();
+        let shape = Shape::for_n(n);
+        let screen_x = cpu.register(x as usize) as usize % gpu.width();
+        let screen_y = cpu.register(y as usize) as usize % gpu.height();
+        let len = gpu.sprite_len(shape.len());
+
+        let at = if addr == cpu.pc {
+            "at the PC".to_string()
+        } else {
+            format!("at {:04X}", addr)
+        };
+        ui.label(format!("Next draw {}, using current registers:", at));
+        ui.monospace(format!(
+            "D{:X}{:X}{:X}: {} bytes from {:04X} to ({}, {})",
+            x, y, n, len, cpu.address_register, screen_x, screen_y
+        ));
+        if cpu.address_register + len > MEMORY_SIZE {
+            ui.colored_label(Color32::RED, "Sprite runs past the end of memory");
+        }
+
+        ui.horizontal(|ui| {
+            let pixels = sprite_pixels(&cpu.memory, cpu.address_register, shape, gpu.planes());
+            paint_pixels(ui, &pixels, shape, 4.0, palette);
+            self.paint_target(ui, cpu, palette, screen_x, screen_y, shape);
+        });
+    }
+
+    /// Paints a miniature of the screen with the sprite's destination
+    /// outlined, wrapping around the edges as `DXYN` would without clipping.
+    fn paint_target(
+        &self,
+        ui: &mut Ui,
+        cpu: &Cpu,
+        palette: &Palette,
+        x: usize,
+        y: usize,
+        shape: Shape,
+    ) {
+        let (width, height) = cpu.screen_size();
+        let scale = PREVIEW_WIDTH / width as f32;
+        let size = vec2(width as f32, height as f32) * scale;
+        let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+        let painter = ui.painter();
+        painter.rect_filled(rect, 0.0, palette.color(0));
+        for (idx, color) in cpu.screen().iter().enumerate() {
+            if *color == 0 {
+                continue;
+            }
+            let pos = vec2((idx % width) as f32, (idx / width) as f32) * scale;
+            let pixel = Rect::from_min_size(rect.min + pos, vec2(scale, scale));
+            painter.rect_filled(pixel, 0.0, palette.color(*color));
+        }
+        painter.rect_stroke(rect, 0.0, Stroke::new(1.0, GRID_COLOR));
+
+        // A sprite straddling an edge is outlined in up to four pieces
+        for (dx, dy) in [(0, 0), (width, 0), (0, height), (width, height)] {
+            let left = x as f32 - dx as f32;
+            let top = y as f32 - dy as f32;
+            let target = Rect::from_min_size(
+                rect.min + vec2(left, top) * scale,
+                vec2(shape.width() as f32, shape.height() as f32) * scale,
+            );
+            if target.intersects(rect) {
+                painter.rect_stroke(target.intersect(rect), 0.0, Stroke::new(1.0, TARGET_COLOR));
+            }
+        }
+    }
+
+    fn ui_sheet(&mut self, ui: &mut Ui, cpu: &Cpu, palette: &Palette) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.sheet_large, "16x16");
+            ui.add_enabled(
+                !self.sheet_large,
+                egui::Slider::new(&mut self.sheet_height, 1..=15).text("rows"),
+            );
+        });
+        let page = self.sheet_page();
+        ui.horizontal(|ui| {
+            ui.monospace(format!("{:04X}", self.sheet_offset));
+            if ui
+                .small_button("⬆")
+                .on_hover_text("Previous page")
+                .clicked()
+            {
+                self.sheet_offset = self.sheet_offset.saturating_sub(page);
+            }
+            if ui.small_button("⬇").on_hover_text("Next page").clicked() {
+                self.sheet_offset = (self.sheet_offset + page).min(MEMORY_SIZE - 1);
+            }
+            // Graphics are rarely aligned to the tile size, so allow nudging
+            if ui.small_button("-1").clicked() {
+                self.sheet_offset = self.sheet_offset.saturating_sub(1);
+            }
+            if ui.small_button("+1").clicked() {
+                self.sheet_offset = (self.sheet_offset + 1).min(MEMORY_SIZE - 1);
+            }
+        });
+
+        let shape = self.sheet_shape();
+        let selected = match self.anchor {
+            Anchor::AddressRegister => cpu.address_register,
+            Anchor::Fixed(addr) => addr,
+        };
+        for row in 0..SHEET_ROWS {
+            ui.horizontal(|ui| {
+                for column in 0..SHEET_COLUMNS {
+                    let addr = self.sheet_offset + (row * SHEET_COLUMNS + column) * shape.len();
+                    if addr >= MEMORY_SIZE {
+                        return;
+                    }
+                    let pixels = sprite_pixels(&cpu.memory, addr, shape, 1);
+                    let tile = paint_pixels(ui, &pixels, shape, SHEET_ZOOM, palette);
+                    let color = if addr == selected {
+                        SELECTED_COLOR
+                    } else {
+                        GRID_COLOR
+                    };
+                    ui.painter()
+                        .rect_stroke(tile.rect, 0.0, Stroke::new(1.0, color));
+                    let tile = tile.on_hover_text(format!("{:04X}", addr));
+                    if tile.clicked() {
+                        self.anchor = Anchor::Fixed(addr);
+                        self.address = format!("{:04X}", addr);
+                        self.large = self.sheet_large;
+                        self.height = self.sheet_height;
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_narrow_sprites() {
+        let memory = [0x81, 0x7E];
+        let pixels = sprite_pixels(&memory, 0, Shape::Narrow(3), 1);
+        assert_eq!(&pixels[..8], &[1, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(&pixels[8..16], &[0, 1, 1, 1, 1, 1, 1, 0]);
+        // Past the end of memory
+        assert_eq!(&pixels[16..], &[0; 8]);
+    }
+
+    #[test]
+    fn decodes_large_and_multi_plane_sprites() {
+        let mut memory = vec![0; 64];
+        memory[0] = 0x80;
+        memory[1] = 0x01;
+        memory[32] = 0x80;
+        let pixels = sprite_pixels(&memory, 0, Shape::Large, 3);
+        assert_eq!(pixels.len(), 256);
+        assert_eq!(pixels[0], 3);
+        assert_eq!(pixels[15], 1);
+        assert_eq!(pixels[16], 0);
+
+        // Only plane 2 selected: its data comes first
+        let pixels = sprite_pixels(&memory, 0, Shape::Large, 2);
+        assert_eq!(pixels[0], 2);
+        assert_eq!(pixels[15], 2);
+    }
+}
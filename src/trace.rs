@@ -0,0 +1,290 @@
+//! Execution traces: a record of every instruction the CPU executed and the
+//! registers it changed, written to a file as text or a compact binary format.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use crate::{
+    cpu::Op,
+    disasm::{self, Syntax},
+};
+
+/// Magic bytes and version at the start of a binary trace.
+pub const BINARY_MAGIC: &[u8; 4] = b"R8T\x01";
+
+const FLAG_LONG: u8 = 1 << 0;
+const FLAG_ADDRESS_REGISTER: u8 = 1 << 1;
+
+/// One executed instruction and the state it changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    /// The address word following `F000`.
+    pub long: Option<u16>,
+    /// Index and new value of every `V` register the instruction changed.
+    pub registers: Vec<(u8, u8)>,
+    /// New value of `I`, if the instruction changed it.
+    pub address_register: Option<u16>,
+}
+
+impl TraceEntry {
+    pub fn op(&self) -> Op {
+        Op::decode(self.opcode, self.long.unwrap_or(0))
+    }
+
+    pub fn mnemonic(&self) -> String {
+        disasm::mnemonic(&self.op(), Syntax::default())
+    }
+
+    /// The changed registers, e.g. `V0=01 VF=00 I=022A`.
+    pub fn changes(&self) -> String {
+        let registers = self
+            .registers
+            .iter()
+            .map(|(idx, value)| format!("V{:X}={:02X}", idx, value));
+        let address_register = self.address_register.map(|i| format!("I={:04X}", i));
+        registers
+            .chain(address_register)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Encodes the entry as a little-endian PC and opcode, a flags byte, a
+    /// bitmask of changed `V` registers, then the `F000` address word, the
+    /// new register values and the new `I` when present.
+    pub fn write_binary(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut flags = 0;
+        if self.long.is_some() {
+            flags |= FLAG_LONG;
+        }
+        if self.address_register.is_some() {
+            flags |= FLAG_ADDRESS_REGISTER;
+        }
+        let mask = self
+            .registers
+            .iter()
+            .fold(0u16, |mask, (idx, _)| mask | (1 << idx));
+        writer.write_all(&self.pc.to_le_bytes())?;
+        writer.write_all(&self.opcode.to_le_bytes())?;
+        writer.write_all(&[flags])?;
+        writer.write_all(&mask.to_le_bytes())?;
+        if let Some(long) = self.long {
+            writer.write_all(&long.to_le_bytes())?;
+        }
+        let mut registers = self.registers.clone();
+        registers.sort_unstable();
+        for (_, value) in registers {
+            writer.write_all(&[value])?;
+        }
+        if let Some(i) = self.address_register {
+            writer.write_all(&i.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let opcode = match self.long {
+            Some(long) => format!("{:04X}{:04X}", self.opcode, long),
+            None => format!("{:04X}", self.opcode),
+        };
+        let line = format!(
+            "{:04X}  {:<8}  {:<20}  {}",
+            self.pc,
+            opcode,
+            self.mnemonic(),
+            self.changes()
+        );
+        f.write_str(line.trim_end())
+    }
+}
+
+/// File format of an execution trace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line per instruction with its address, opcode, mnemonic and
+    /// changed registers.
+    #[default]
+    Text,
+    /// [`BINARY_MAGIC`] followed by [`TraceEntry::write_binary`] records.
+    Binary,
+}
+
+impl TraceFormat {
+    pub const ALL: [TraceFormat; 2] = [TraceFormat::Text, TraceFormat::Binary];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceFormat::Text => "text",
+            TraceFormat::Binary => "binary",
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => {
+                let names: Vec<_> = TraceFormat::ALL.iter().map(|f| f.name()).collect();
+                Err(format!(
+                    "Unknown trace format '{}' (expected one of: {})",
+                    s,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+/// Writes trace entries as they arrive, or keeps only the most recent ones
+/// in memory until [`TraceWriter::flush`] so a long run leaves just the
+/// instructions leading up to a crash or breakpoint.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    /// Entries held back in ring buffer mode, and the number to keep.
+    ring: Option<(VecDeque<TraceEntry>, usize)>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Creates a writer that writes every entry straight away, or keeps the
+    /// last `ring` entries when given.
+    pub fn new(mut writer: W, format: TraceFormat, ring: Option<usize>) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            writer.write_all(BINARY_MAGIC)?;
+        }
+        Ok(Self {
+            writer,
+            format,
+            ring: ring.map(|capacity| (VecDeque::with_capacity(capacity), capacity)),
+        })
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) -> io::Result<()> {
+        match &mut self.ring {
+            Some((entries, capacity)) => {
+                if entries.len() >= *capacity {
+                    entries.pop_front();
+                }
+                if *capacity > 0 {
+                    entries.push_back(entry);
+                }
+                Ok(())
+            }
+            None => self.write(&entry),
+        }
+    }
+
+    /// Writes out any entries held in the ring buffer and flushes the
+    /// underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some((entries, _)) = &mut self.ring {
+            let entries: Vec<_> = entries.drain(..).collect();
+            for entry in &entries {
+                self.write(entry)?;
+            }
+        }
+        self.writer.flush()
+    }
+
+    fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", entry),
+            TraceFormat::Binary => entry.write_binary(&mut self.writer),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pc: u16) -> TraceEntry {
+        TraceEntry {
+            pc,
+            opcode: 0x6A1F,
+            long: None,
+            registers: vec![(0xA, 0x1F)],
+            address_register: None,
+        }
+    }
+
+    #[test]
+    fn formats_text_lines() {
+        let mut entry = entry(0x200);
+        entry.address_register = Some(0x22A);
+        assert_eq!(entry.changes(), "VA=1F I=022A");
+        assert!(entry.to_string().starts_with("0200  6A1F      "));
+        assert!(entry.to_string().ends_with("VA=1F I=022A"));
+
+        let long = TraceEntry {
+            pc: 0x202,
+            opcode: 0xF000,
+            long: Some(0x1234),
+            registers: Vec::new(),
+            address_register: Some(0x1234),
+        };
+        assert!(long.to_string().starts_with("0202  F0001234  "));
+    }
+
+    #[test]
+    fn encodes_binary_records() {
+        let entry = TraceEntry {
+            pc: 0x1234,
+            opcode: 0xF000,
+            long: Some(0xABCD),
+            registers: vec![(0xF, 1), (0, 2)],
+            address_register: Some(0x0300),
+        };
+        let mut bytes = Vec::new();
+        entry.write_binary(&mut bytes).unwrap();
+        assert_eq!(
+            bytes,
+            [0x34, 0x12, 0x00, 0xF0, 0x03, 0x01, 0x80, 0xCD, 0xAB, 2, 1, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn ring_buffer_keeps_the_last_entries() {
+        let mut writer = TraceWriter::new(Vec::new(), TraceFormat::Binary, Some(2)).unwrap();
+        for pc in 0..5 {
+            writer.record(entry(0x200 + pc * 2)).unwrap();
+        }
+        writer.flush().unwrap();
+        let bytes = writer.into_inner();
+
+        let mut expected = BINARY_MAGIC.to_vec();
+        entry(0x206).write_binary(&mut expected).unwrap();
+        entry(0x208).write_binary(&mut expected).unwrap();
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("BINARY".parse(), Ok(TraceFormat::Binary));
+        assert!("csv"
+            .parse::<TraceFormat>()
+            .unwrap_err()
+            .contains("text, binary"));
+    }
+}
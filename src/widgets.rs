@@ -0,0 +1,55 @@
+use egui_sdl2_gl::egui::{Color32, TextEdit, TextStyle, Ui};
+
+/// Width of one monospace digit, for sizing hex fields.
+const DIGIT_WIDTH: f32 = 8.0;
+
+/// A text field editing `value` as `digits` hex digits.
+///
+/// The field always shows exactly `digits` digits; typing more shifts the
+/// oldest ones out on the left, like a hardware hex keypad. Returns whether
+/// the value changed.
+pub fn hex_edit(ui: &mut Ui, value: &mut usize, digits: usize, color: Option<Color32>) -> bool {
+    let mut text = format!("{:0width$X}", value, width = digits);
+    let response = ui.add(
+        TextEdit::singleline(&mut text)
+            .text_style(TextStyle::Monospace)
+            .text_color_opt(color)
+            .desired_width(digits as f32 * DIGIT_WIDTH),
+    );
+    if !response.changed() {
+        return false;
+    }
+    let hex: String = text.chars().filter(char::is_ascii_hexdigit).collect();
+    let start = hex.len().saturating_sub(digits);
+    let new = usize::from_str_radix(&hex[start..], 16).unwrap_or(0);
+    let changed = new != *value;
+    *value = new;
+    changed
+}
+
+/// [`hex_edit`] for a byte.
+pub fn hex_edit_u8(ui: &mut Ui, value: &mut u8, color: Option<Color32>) -> bool {
+    let mut wide = *value as usize;
+    let changed = hex_edit(ui, &mut wide, 2, color);
+    *value = wide as u8;
+    changed
+}
+
+/// Shows `value` as hex, editable only when `editable` is set. Returns
+/// whether the value changed.
+pub fn hex_value(ui: &mut Ui, value: &mut usize, digits: usize, editable: bool) -> bool {
+    if editable {
+        hex_edit(ui, value, digits, None)
+    } else {
+        ui.code(format!("{:0width$X}", value, width = digits));
+        false
+    }
+}
+
+/// [`hex_value`] for a byte.
+pub fn hex_value_u8(ui: &mut Ui, value: &mut u8, editable: bool) -> bool {
+    let mut wide = *value as usize;
+    let changed = hex_value(ui, &mut wide, 2, editable);
+    *value = wide as u8;
+    changed
+}
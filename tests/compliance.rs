@@ -0,0 +1,114 @@
+//! Runs the test ROMs in `tests/roms` headlessly under every quirk profile
+//! and compares the final screen against the golden images in
+//! `tests/golden`.
+//!
+//! The ROMs are Octo sources compiled at test time. To regenerate the golden
+//! images after an intended change, run the tests with `UPDATE_GOLDEN=1` and
+//! check the new screens by eye.
+
+use std::{env, fs, path::PathBuf};
+
+use reimu::{
+    cpu::QuirkProfile,
+    headless::{Limit, Runner, StopReason},
+    octo, Machine,
+};
+
+/// Every test ROM exits well before this; hitting it means a ROM hung.
+const FRAME_LIMIT: u64 = 600;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(dir)
+        .join(file)
+}
+
+/// Runs `rom` until it exits, pressing `keys`, and returns its screen.
+fn run(rom: &str, profile: QuirkProfile, keys: &str) -> String {
+    let program = octo::compile_file(&path("roms", &format!("{}.8o", rom))).unwrap();
+    let mut machine = Machine::new(profile.quirks());
+    machine.load_rom(&program.bytes).unwrap();
+    machine.cpu_mut().seed_rng(0);
+    let mut runner = Runner::new(keys.parse().unwrap(), Some(Limit::Frames(FRAME_LIMIT)));
+    let reason = runner.run(&mut machine);
+    assert_eq!(
+        reason,
+        StopReason::Exited(0),
+        "{} did not exit under the {} profile",
+        rom,
+        profile
+    );
+    machine.cpu().gpu().render_text()
+}
+
+/// Compares `screen` against the golden image `name`, or replaces the image
+/// when `UPDATE_GOLDEN` is set.
+fn check(name: &str, screen: &str) {
+    let golden = path("golden", &format!("{}.txt", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, screen).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", golden.display(), err));
+    assert!(
+        screen == expected,
+        "Screen differs from {}\n--- expected\n{}--- found\n{}",
+        golden.display(),
+        expected,
+        screen
+    );
+}
+
+macro_rules! compliance_tests {
+    ($($test:ident: $rom:literal, $profile:ident, $golden:literal, $keys:literal;)*) => {
+        $(
+            #[test]
+            fn $test() {
+                check($golden, &run($rom, QuirkProfile::$profile, $keys));
+            }
+        )*
+    };
+}
+
+compliance_tests! {
+    opcodes_vip: "opcodes", CosmacVip, "opcodes", "";
+    opcodes_chip48: "opcodes", Chip48, "opcodes", "";
+    opcodes_schip: "opcodes", SuperChip, "opcodes", "";
+    opcodes_xochip: "opcodes", XoChip, "opcodes", "";
+
+    flags_vip: "flags", CosmacVip, "flags", "";
+    flags_chip48: "flags", Chip48, "flags", "";
+    flags_schip: "flags", SuperChip, "flags", "";
+    flags_xochip: "flags", XoChip, "flags", "";
+
+    quirks_vip: "quirks", CosmacVip, "quirks-vip", "";
+    quirks_chip48: "quirks", Chip48, "quirks-chip48", "";
+    quirks_schip: "quirks", SuperChip, "quirks-schip", "";
+    quirks_xochip: "quirks", XoChip, "quirks-xochip", "";
+
+    keypad_vip: "keypad", CosmacVip, "keypad", "10:7 30-40:5";
+    keypad_chip48: "keypad", Chip48, "keypad", "10:7 30-40:5";
+    keypad_schip: "keypad", SuperChip, "keypad", "10:7 30-40:5";
+    keypad_xochip: "keypad", XoChip, "keypad", "10:7 30-40:5";
+}
+
+#[test]
+fn quirk_profiles_are_told_apart() {
+    let screens: Vec<String> = QuirkProfile::ALL
+        .iter()
+        .map(|profile| run("quirks", *profile, ""))
+        .collect();
+    for (idx, screen) in screens.iter().enumerate() {
+        for (other, other_screen) in screens.iter().enumerate().skip(idx + 1) {
+            assert_ne!(
+                screen,
+                other_screen,
+                "{} and {} show the same quirks",
+                QuirkProfile::ALL[idx],
+                QuirkProfile::ALL[other]
+            );
+        }
+    }
+}